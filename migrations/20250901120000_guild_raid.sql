@@ -0,0 +1,3 @@
+-- The time at which the next raid of the guild will take place. 0 if the
+-- guild has not declared a raid
+ALTER TABLE guild ADD COLUMN raid_time INT NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- The hydra a guild can fight with its guild pet
+ALTER TABLE guild ADD COLUMN hydra_last_battle INT NOT NULL DEFAULT 0;
+ALTER TABLE guild ADD COLUMN hydra_last_full INT NOT NULL DEFAULT 0;
+
+-- When the member is allowed to fight the hydra again. Resets hydra_fought
+ALTER TABLE guild_member ADD COLUMN hydra_next_battle INT NOT NULL DEFAULT 0;
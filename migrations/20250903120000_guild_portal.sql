@@ -0,0 +1,6 @@
+-- demon_portal_health is the remaining life of the current portal enemy. 0
+-- means, that it has not been attacked yet and has full life
+UPDATE guild SET demon_portal_health = 0;
+
+-- The last time the member fought the portal. Resets portal_fought
+ALTER TABLE guild_member ADD COLUMN portal_last_fought INT NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+-- Until when the character is not allowed to chat
+ALTER TABLE character ADD COLUMN muted_until INT NOT NULL DEFAULT 0;
+
+-- The characters, whose messages a character does not want to see
+CREATE TABLE ignored_player (
+  pid INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  ignored INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  PRIMARY KEY (pid, ignored)
+);
+
+CREATE INDEX chat_message_guild ON chat_message (guild, id);
+CREATE INDEX chat_message_whisper ON chat_message (whisper, id);
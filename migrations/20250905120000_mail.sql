@@ -0,0 +1,31 @@
+CREATE TABLE mail (
+  id INTEGER PRIMARY KEY autoincrement NOT NULL,
+  receiver INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  -- NULL for mails sent by the game itself
+  sender INT REFERENCES character (pid) ON DELETE SET NULL,
+  sender_name TEXT NOT NULL,
+  -- 0 => Normal
+  -- 3 => Guild kicked
+  -- 5 => Guild invite
+  typ INT NOT NULL DEFAULT 0,
+  title TEXT NOT NULL,
+  body TEXT NOT NULL,
+  sent INT NOT NULL,
+  is_read BOOL NOT NULL DEFAULT FALSE,
+  -- Mails with attachments are shown as claimable rewards until this time
+  claimable_until INT,
+  claimed BOOL NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX mail_receiver ON mail (receiver, id DESC);
+
+-- Either a resource, or an item attached to a mail
+CREATE TABLE mail_attachment (
+  id INTEGER PRIMARY KEY autoincrement NOT NULL,
+  mail_id INT NOT NULL REFERENCES mail (id) ON DELETE CASCADE,
+  resource_typ INT NOT NULL DEFAULT 0,
+  amount INT NOT NULL DEFAULT 0,
+  item_id INT REFERENCES item (id) ON DELETE CASCADE
+);
+
+CREATE INDEX mail_attachment_mail ON mail_attachment (mail_id);
@@ -0,0 +1,11 @@
+-- A friend request from pid to friend. Once both sides have requested each
+-- other, the friendship is accepted
+CREATE TABLE friend (
+  pid INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  friend INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  accepted BOOL NOT NULL DEFAULT FALSE,
+  PRIMARY KEY (pid, friend)
+);
+
+-- The last time a request has been made with this session
+ALTER TABLE session ADD COLUMN last_active INT NOT NULL DEFAULT 0;
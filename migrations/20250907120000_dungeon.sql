@@ -0,0 +1,14 @@
+-- The progress of a character in a dungeon. Dungeons without a row are
+-- still locked
+CREATE TABLE dungeon (
+  pid INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  is_shadow BOOL NOT NULL,
+  -- 0 based id of the dungeon as the client knows it
+  dungeon INT NOT NULL,
+  -- The amount of enemies, that have been defeated
+  progress INT NOT NULL DEFAULT 0,
+  PRIMARY KEY (pid, is_shadow, dungeon)
+);
+
+-- The time at which the next dungeon fight is free
+ALTER TABLE character ADD COLUMN dungeon_timer INT NOT NULL DEFAULT 0;
@@ -0,0 +1,18 @@
+-- The companions, that fight together with the character in the tower.
+-- The tower progress itself is stored as a light dungeon
+CREATE TABLE companion (
+  pid INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  -- 1 => Warrior, 2 => Mage, 3 => Scout
+  class INT NOT NULL,
+  hat INT REFERENCES item (id) ON DELETE SET NULL,
+  breastplate INT REFERENCES item (id) ON DELETE SET NULL,
+  gloves INT REFERENCES item (id) ON DELETE SET NULL,
+  footwear INT REFERENCES item (id) ON DELETE SET NULL,
+  amulet INT REFERENCES item (id) ON DELETE SET NULL,
+  belt INT REFERENCES item (id) ON DELETE SET NULL,
+  ring INT REFERENCES item (id) ON DELETE SET NULL,
+  talisman INT REFERENCES item (id) ON DELETE SET NULL,
+  weapon INT REFERENCES item (id) ON DELETE SET NULL,
+  shield INT REFERENCES item (id) ON DELETE SET NULL,
+  PRIMARY KEY (pid, class)
+);
@@ -0,0 +1,32 @@
+-- The fortress of a character. Characters without a row have not unlocked
+-- their fortress yet
+CREATE TABLE fortress (
+  pid INT PRIMARY KEY NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  -- The levels of the buildings. 0 => not built yet
+  fortress INT NOT NULL DEFAULT 0,
+  laborers_quarters INT NOT NULL DEFAULT 0,
+  woodcutters_hut INT NOT NULL DEFAULT 0,
+  quarry INT NOT NULL DEFAULT 0,
+  gem_mine INT NOT NULL DEFAULT 0,
+  academy INT NOT NULL DEFAULT 0,
+  archery_guild INT NOT NULL DEFAULT 0,
+  barracks INT NOT NULL DEFAULT 0,
+  mages_tower INT NOT NULL DEFAULT 0,
+  treasury INT NOT NULL DEFAULT 0,
+  smithy INT NOT NULL DEFAULT 0,
+  wall INT NOT NULL DEFAULT 0,
+  -- The resources, that can be spent
+  wood INT NOT NULL DEFAULT 0,
+  stone INT NOT NULL DEFAULT 0,
+  -- The resources, that have been produced, but not collected yet
+  stored_wood INT NOT NULL DEFAULT 0,
+  stored_stone INT NOT NULL DEFAULT 0,
+  stored_experience INT NOT NULL DEFAULT 0,
+  -- The time up to which the production has been added to the stored
+  -- resources
+  produced_until INT NOT NULL,
+  -- 0 based id of the building, that is being upgraded
+  upgrade_building INT,
+  upgrade_began INT NOT NULL DEFAULT 0,
+  upgrade_finish INT NOT NULL DEFAULT 0
+);
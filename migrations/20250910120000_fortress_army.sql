@@ -0,0 +1,19 @@
+-- The units of a fortress. Units in training are finished one after the
+-- other, so only the start of the current one needs to be known
+CREATE TABLE fortress_unit (
+  pid INT NOT NULL REFERENCES fortress (pid) ON DELETE CASCADE,
+  -- 0 => Soldier, 1 => Magician, 2 => Archer
+  unit INT NOT NULL,
+  count INT NOT NULL DEFAULT 0,
+  in_training INT NOT NULL DEFAULT 0,
+  training_began INT NOT NULL DEFAULT 0,
+  training_finish INT NOT NULL DEFAULT 0,
+  PRIMARY KEY (pid, unit)
+);
+
+ALTER TABLE fortress ADD COLUMN honor INT NOT NULL DEFAULT 100;
+-- The character, whose fortress can be attacked next
+ALTER TABLE fortress ADD COLUMN attack_target INT REFERENCES character (pid)
+  ON DELETE SET NULL;
+-- The time at which a new attack target can be chosen for free
+ALTER TABLE fortress ADD COLUMN attack_reroll INT NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- The gem, that the gem mine is currently searching for. 0 => no search
+ALTER TABLE fortress ADD COLUMN gem_target INT NOT NULL DEFAULT 0;
+ALTER TABLE fortress ADD COLUMN gem_search_began INT NOT NULL DEFAULT 0;
+ALTER TABLE fortress ADD COLUMN gem_search_finish INT NOT NULL DEFAULT 0;
@@ -0,0 +1,36 @@
+-- The underworld of a character. It is unlocked by using the heart of
+-- darkness, so characters without a row have not unlocked it yet
+CREATE TABLE underworld (
+  pid INT PRIMARY KEY NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  -- The levels of the buildings. 0 => not built yet
+  heart_of_darkness INT NOT NULL DEFAULT 1,
+  gate INT NOT NULL DEFAULT 0,
+  gold_pit INT NOT NULL DEFAULT 0,
+  soul_extractor INT NOT NULL DEFAULT 0,
+  goblin_pit INT NOT NULL DEFAULT 0,
+  torture_chamber INT NOT NULL DEFAULT 0,
+  gladiator_trainer INT NOT NULL DEFAULT 0,
+  troll_block INT NOT NULL DEFAULT 0,
+  adventuromatic INT NOT NULL DEFAULT 0,
+  keeper INT NOT NULL DEFAULT 0,
+  souls INT NOT NULL DEFAULT 0,
+  -- The resources, that have been produced, but not collected yet
+  stored_silver INT NOT NULL DEFAULT 0,
+  stored_souls INT NOT NULL DEFAULT 0,
+  stored_thirst INT NOT NULL DEFAULT 0,
+  -- The time up to which the production has been added to the stored
+  -- resources
+  produced_until INT NOT NULL,
+  -- 0 based id of the building, that is being upgraded
+  upgrade_building INT,
+  upgrade_began INT NOT NULL DEFAULT 0,
+  upgrade_finish INT NOT NULL DEFAULT 0,
+  -- The amount of times each unit has been upgraded
+  goblin_upgrades INT NOT NULL DEFAULT 0,
+  troll_upgrades INT NOT NULL DEFAULT 0,
+  keeper_upgrades INT NOT NULL DEFAULT 0,
+  honor INT NOT NULL DEFAULT 100,
+  lured_today INT NOT NULL DEFAULT 0,
+  -- The time at which `lured_today` resets
+  lures_reset INT NOT NULL DEFAULT 0
+);
@@ -0,0 +1,34 @@
+-- The pet collection of a character. It is created with the first pet, that
+-- the character finds
+CREATE TABLE pets (
+  pid INT PRIMARY KEY NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  honor INT NOT NULL DEFAULT 100,
+  -- The character, whose pets can be fought next
+  opponent INT REFERENCES character (pid) ON DELETE SET NULL,
+  opponent_chosen INT NOT NULL DEFAULT 0,
+  -- The time at which the next habitat exploration is free
+  next_exploration INT NOT NULL DEFAULT 0,
+  -- The time at which fed fruits and fought habitats reset
+  daily_reset INT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE pet (
+  pid INT NOT NULL REFERENCES pets (pid) ON DELETE CASCADE,
+  -- 1..=100. Every habitat has 20 pets
+  pet_id INT NOT NULL,
+  level INT NOT NULL DEFAULT 1,
+  fruits_today INT NOT NULL DEFAULT 0,
+  PRIMARY KEY (pid, pet_id)
+);
+
+CREATE TABLE pet_habitat (
+  pid INT NOT NULL REFERENCES pets (pid) ON DELETE CASCADE,
+  -- 0 => Shadow, 1 => Light, 2 => Earth, 3 => Fire, 4 => Water
+  habitat INT NOT NULL,
+  -- The amount of exploration fights, that have been won
+  explored INT NOT NULL DEFAULT 0,
+  fruits INT NOT NULL DEFAULT 0,
+  -- Whether the habitat has already fought the opponent today
+  battled BOOL NOT NULL DEFAULT FALSE,
+  PRIMARY KEY (pid, habitat)
+);
@@ -0,0 +1,10 @@
+-- The album of a character. It is created with the first entry, that the
+-- character collects
+CREATE TABLE scrapbook (
+  pid INT PRIMARY KEY NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  -- One bit per album position in the order the client expects them.
+  -- Monsters come first, then items
+  album BLOB NOT NULL,
+  -- The amount of bits set in `album`
+  entries INT NOT NULL DEFAULT 0
+);
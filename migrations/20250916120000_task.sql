@@ -0,0 +1,32 @@
+-- The event, whose tasks can be done on the world. The theme is the
+-- `EventTaskTheme` the client knows
+ALTER TABLE world ADD COLUMN event_task_theme INT NOT NULL DEFAULT 2;
+ALTER TABLE world ADD COLUMN event_task_start INT NOT NULL DEFAULT 1708300800;
+ALTER TABLE world ADD COLUMN event_task_end INT NOT NULL DEFAULT 1798646399;
+
+-- The progress of a character towards a daily or event task
+CREATE TABLE task (
+  pid INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  is_event BOOL NOT NULL,
+  -- The `TaskType` the client knows
+  typ INT NOT NULL,
+  current INT NOT NULL DEFAULT 0,
+  -- The time at which the progress no longer counts. This is the next day
+  -- for daily tasks and the end of the event for event tasks
+  reset INT NOT NULL,
+  PRIMARY KEY (pid, is_event, typ)
+);
+
+-- The reward chests of the daily and event tasks, that have been opened
+CREATE TABLE task_chest (
+  pid INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  is_event BOOL NOT NULL,
+  -- 0 based position of the chest
+  chest INT NOT NULL,
+  -- The time at which the chest can be opened again
+  reset INT NOT NULL,
+  PRIMARY KEY (pid, is_event, chest)
+);
+
+-- Lucky coins are won from reward chests and the wheel of fortune
+ALTER TABLE tavern ADD COLUMN lucky_coins INT NOT NULL DEFAULT 0;
@@ -0,0 +1,10 @@
+-- The progress of a character in the login calendar
+CREATE TABLE calendar (
+  pid INT PRIMARY KEY NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  -- The amount of days, that have been claimed in the month
+  collected INT NOT NULL DEFAULT 0,
+  -- The month the claimed days belong to, counted in months since year 0
+  month INT NOT NULL DEFAULT 0,
+  -- The time at which the next day can be claimed
+  next_possible INT NOT NULL DEFAULT 0
+);
@@ -0,0 +1,4 @@
+-- The paid spins of the wheel of fortune today. They reset at wheel_reset
+ALTER TABLE tavern ADD COLUMN wheel_spins_today INT NOT NULL DEFAULT 0;
+ALTER TABLE tavern ADD COLUMN wheel_reset INT NOT NULL DEFAULT 0;
+ALTER TABLE tavern ADD COLUMN wheel_next_free_spin INT NOT NULL DEFAULT 0;
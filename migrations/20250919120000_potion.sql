@@ -0,0 +1,11 @@
+-- The potions a character has drunk. Expired potions are removed, when they
+-- are loaded
+CREATE TABLE potion (
+  pid INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  -- 0..3
+  slot INT NOT NULL,
+  -- The item ident of the potion. It encodes the type and size
+  potion INT NOT NULL,
+  expires INT NOT NULL,
+  PRIMARY KEY (pid, slot)
+);
@@ -0,0 +1,12 @@
+-- The blacksmith resources of a character. It is created with the first item,
+-- that the character dismantles or upgrades
+CREATE TABLE blacksmith (
+  pid INT PRIMARY KEY NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  metal INT NOT NULL DEFAULT 0,
+  arcane INT NOT NULL DEFAULT 0,
+  -- The dismantles left on the day of last_dismantled
+  dismantles_left INT NOT NULL DEFAULT 0,
+  last_dismantled INT NOT NULL DEFAULT 0
+);
+
+ALTER TABLE item ADD COLUMN upgrades INT NOT NULL DEFAULT 0;
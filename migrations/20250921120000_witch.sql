@@ -0,0 +1,5 @@
+-- The cauldron of the witch is filled by every character of the world. The
+-- enchantments are unlocked in a fixed order and the item type the witch
+-- wants is the one of the next enchantment
+ALTER TABLE world ADD COLUMN witch_items INT NOT NULL DEFAULT 0;
+ALTER TABLE world ADD COLUMN witch_unlocked INT NOT NULL DEFAULT 0;
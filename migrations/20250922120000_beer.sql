@@ -0,0 +1,2 @@
+-- The time at which the beers drunk in the tavern are reset
+ALTER TABLE tavern ADD COLUMN beer_reset INT NOT NULL DEFAULT 0;
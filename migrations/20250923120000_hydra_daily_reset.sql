@@ -0,0 +1,5 @@
+-- The hydra resets at the start of the day like the portal, so the member
+-- stores when they fought it last instead of when they can fight it again
+ALTER TABLE guild_member RENAME COLUMN hydra_next_battle TO hydra_last_fought;
+UPDATE guild_member
+SET hydra_last_fought = max(hydra_last_fought - 60 * 60 * 24, 0);
@@ -0,0 +1,2 @@
+-- The time at which the fortress can attack again
+ALTER TABLE fortress ADD COLUMN next_attack INT NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+-- The dungeon keys, that have dropped for a character. A key only drops once,
+-- even while it still waits in the bag to be used
+CREATE TABLE dungeon_key (
+  pid INT NOT NULL REFERENCES character (pid) ON DELETE CASCADE,
+  is_shadow BOOL NOT NULL,
+  dungeon INT NOT NULL,
+  PRIMARY KEY (pid, is_shadow, dungeon)
+);
@@ -0,0 +1,211 @@
+use sqlx::SqliteConnection;
+
+use super::{
+    ResponseBuilder, ServerError,
+    item::{equipment_slot, load_item},
+    next_day, now,
+    task::{TaskType, progress_tasks},
+};
+
+/// The amount of items, that can be dismantled each day
+const DAILY_DISMANTLES: i64 = 5;
+/// The amount of times an item can be upgraded
+const MAX_UPGRADES: i64 = 20;
+/// The increase of every attribute of an item in percent per upgrade
+const UPGRADE_PERCENT: i64 = 3;
+/// The arcane crystals every upgrade after the first one costs more
+const UPGRADE_ARCANE: i64 = 2;
+
+/// The actions of the blacksmith. The values are the targets the client moves
+/// the item to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BlacksmithAction {
+    Dismantle = 201,
+    Upgrade = 204,
+}
+
+impl BlacksmithAction {
+    pub(crate) fn from_client(val: i64) -> Option<BlacksmithAction> {
+        Some(match val {
+            201 => BlacksmithAction::Dismantle,
+            204 => BlacksmithAction::Upgrade,
+            _ => return None,
+        })
+    }
+}
+
+/// The blacksmith resources of a character
+#[derive(Debug, Default)]
+pub(crate) struct Blacksmith {
+    pub metal: i64,
+    pub arcane: i64,
+    /// The dismantles left today
+    pub dismantles_left: i64,
+    pub last_dismantled: i64,
+}
+
+/// Loads the blacksmith resources of the character. The dismantles are
+/// refilled every day
+pub(crate) async fn load_blacksmith(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Blacksmith, ServerError> {
+    let row = sqlx::query!(
+        "SELECT metal, arcane, dismantles_left, last_dismantled
+        FROM blacksmith WHERE pid = $1",
+        pid
+    )
+    .fetch_optional(&mut *con)
+    .await?;
+    let mut smith = match row {
+        Some(row) => Blacksmith {
+            metal: row.metal,
+            arcane: row.arcane,
+            dismantles_left: row.dismantles_left,
+            last_dismantled: row.last_dismantled,
+        },
+        None => Blacksmith::default(),
+    };
+    let today = next_day() - 60 * 60 * 24;
+    if smith.last_dismantled < today {
+        smith.dismantles_left = DAILY_DISMANTLES;
+    }
+    Ok(smith)
+}
+
+async fn store_blacksmith(
+    con: &mut SqliteConnection,
+    pid: i64,
+    smith: &Blacksmith,
+) -> Result<(), ServerError> {
+    sqlx::query!(
+        "INSERT INTO blacksmith
+            (pid, metal, arcane, dismantles_left, last_dismantled)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT DO UPDATE SET metal = excluded.metal,
+            arcane = excluded.arcane,
+            dismantles_left = excluded.dismantles_left,
+            last_dismantled = excluded.last_dismantled",
+        pid,
+        smith.metal,
+        smith.arcane,
+        smith.dismantles_left,
+        smith.last_dismantled
+    )
+    .execute(&mut *con)
+    .await?;
+    Ok(())
+}
+
+/// Gives the character arcane crystals
+pub(crate) async fn add_arcane(
+    con: &mut SqliteConnection,
+    pid: i64,
+    arcane: i64,
+) -> Result<(), ServerError> {
+    let mut smith = load_blacksmith(con, pid).await?;
+    smith.arcane += arcane;
+    store_blacksmith(con, pid, &smith).await
+}
+
+/// The sum of the attribute values of an item. Prices and the resources of
+/// dismantling scale with it
+fn item_value(atr_vals: [i64; 3]) -> i64 {
+    atr_vals.iter().sum()
+}
+
+/// Loads the attribute values and upgrades of the equipment item. Other
+/// items can not be used at the blacksmith
+async fn load_smith_item(
+    con: &mut SqliteConnection,
+    item_id: i64,
+) -> Result<([i64; 3], i64), ServerError> {
+    let item = load_item(con, item_id)
+        .await?
+        .ok_or(ServerError::BadRequest)?;
+    if equipment_slot(item.item_type).is_none() {
+        return Err(ServerError::BadRequest);
+    }
+    let upgrades =
+        sqlx::query_scalar!("SELECT upgrades FROM item WHERE id = $1", item_id)
+            .fetch_one(&mut *con)
+            .await?;
+    Ok(([item.atr_val1, item.atr_val2, item.atr_val3], upgrades))
+}
+
+/// Upgrades every attribute of the equipment item with metal and arcane
+/// crystals. Metal scales with the value of the item and arcane crystals with
+/// its upgrades. The official formulas are not known, so these costs and the
+/// yield of dismantling are this server's own
+pub(crate) async fn upgrade_item(
+    con: &mut SqliteConnection,
+    pid: i64,
+    item_id: i64,
+) -> Result<(), ServerError> {
+    let (atr_vals, upgrades) = load_smith_item(con, item_id).await?;
+    if upgrades >= MAX_UPGRADES {
+        return Err(ServerError::BadRequest);
+    }
+    let metal = (item_value(atr_vals) / 4 + 5) * (upgrades + 1);
+    let arcane = upgrades * UPGRADE_ARCANE;
+    let mut smith = load_blacksmith(con, pid).await?;
+    if smith.metal < metal || smith.arcane < arcane {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    smith.metal -= metal;
+    smith.arcane -= arcane;
+    store_blacksmith(con, pid, &smith).await?;
+
+    let [val1, val2, val3] = atr_vals.map(|val| match val {
+        0 => 0,
+        val => val + (val * UPGRADE_PERCENT / 100).max(1),
+    });
+    sqlx::query!(
+        "UPDATE item SET atr_val1 = $2, atr_val2 = $3, atr_val3 = $4,
+            upgrades = upgrades + 1
+        WHERE id = $1",
+        item_id,
+        val1,
+        val2,
+        val3
+    )
+    .execute(&mut *con)
+    .await?;
+    progress_tasks(con, pid, TaskType::UpgradeItemAttributes, 1).await
+}
+
+/// Dismantles the equipment item into metal and arcane crystals. The caller
+/// has to remove the item from its place
+pub(crate) async fn dismantle_item(
+    con: &mut SqliteConnection,
+    pid: i64,
+    item_id: i64,
+) -> Result<(), ServerError> {
+    let (atr_vals, upgrades) = load_smith_item(con, item_id).await?;
+    let mut smith = load_blacksmith(con, pid).await?;
+    if smith.dismantles_left <= 0 {
+        return Err(ServerError::BadRequest);
+    }
+    let value = item_value(atr_vals);
+    let metal = value / 2 + 1;
+    let arcane = value / 10 + upgrades * UPGRADE_ARCANE / 2;
+    smith.metal += metal;
+    smith.arcane += arcane;
+    smith.dismantles_left -= 1;
+    smith.last_dismantled = now();
+    store_blacksmith(con, pid, &smith).await?;
+
+    sqlx::query!("DELETE FROM item WHERE id = $1", item_id)
+        .execute(&mut *con)
+        .await?;
+    progress_tasks(con, pid, TaskType::BlacksmithDismantle, 1).await?;
+    progress_tasks(con, pid, TaskType::GainMetalFromDismantle, metal).await?;
+    let arcane_task = TaskType::GainArcaneFromDismantle;
+    progress_tasks(con, pid, arcane_task, arcane).await
+}
+
+pub(crate) fn add_smith(resp: &mut ResponseBuilder, smith: &Blacksmith) {
+    resp.add_key("smith");
+    resp.add_val(smith.dismantles_left);
+    resp.add_val(smith.last_dismantled);
+}
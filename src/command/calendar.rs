@@ -0,0 +1,256 @@
+use fastrand::Rng;
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    ResponseBuilder, ServerError, ServerResponse,
+    item::{DbItem, add_to_bag, insert_item},
+    mail::{RewardTyp, give_reward, send_reward_mail},
+    next_day, now,
+    pets::FRUIT_IDENT,
+    player::guard_wage,
+    poll, xp_for_next_level,
+};
+use crate::request::Session;
+
+/// The rewards of the calendar. The values are the reward types the client
+/// knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CalendarReward {
+    Silver = 1,
+    Mushrooms = 2,
+    Experience = 3,
+    /// A random equipment item of the level and class of the character
+    Item = 10,
+    Strength = 11,
+    Dexterity = 12,
+    Intelligence = 13,
+    Constitution = 14,
+    Luck = 15,
+    /// The fruits of the habitats follow this one in habitat order
+    ShadowFruit = 16,
+    LightFruit = 17,
+    EarthFruit = 18,
+    FireFruit = 19,
+    WaterFruit = 20,
+    QuicksandGlasses = 23,
+}
+
+/// The days of the calendar as (reward, amount). The amount of scaling
+/// rewards is multiplied with the value of the reward at the level of the
+/// character
+const CALENDAR: [(CalendarReward, i64); 22] = {
+    use CalendarReward::*;
+    [
+        (Silver, 1),
+        (Experience, 1),
+        (Strength, 1),
+        (ShadowFruit, 1),
+        (Mushrooms, 1),
+        (Dexterity, 1),
+        (Silver, 2),
+        (Item, 1),
+        (LightFruit, 2),
+        (Intelligence, 1),
+        (QuicksandGlasses, 1),
+        (Experience, 2),
+        (EarthFruit, 2),
+        (Constitution, 1),
+        (Mushrooms, 2),
+        (FireFruit, 3),
+        (Silver, 3),
+        (Item, 1),
+        (Luck, 1),
+        (WaterFruit, 3),
+        (Experience, 3),
+        (Mushrooms, 5),
+    ]
+};
+
+/// The hours of guard wage a scaling silver reward is worth
+const SILVER_HOURS: i64 = 2;
+/// The share of the experience to the next level in percent, that a scaling
+/// experience reward is worth
+const XP_PERCENT: i64 = 10;
+/// The attribute points a scaling attribute reward grants per level
+const ATTRIBUTE_PER_LEVEL: i64 = 1;
+/// The amount of quicksand glasses the client shows for this reward
+const QUICKSAND_GLASSES: i64 = 10;
+
+/// The progress of a character in the calendar of the current month
+#[derive(Debug, Default)]
+pub(crate) struct Calendar {
+    /// The amount of days, that have been claimed this month
+    pub collected: i64,
+    pub next_possible: i64,
+}
+
+/// The month the time is in, counted in months since year 0
+fn month_index(time: i64) -> i64 {
+    // Converts the days since the epoch into a civil date, with years
+    // starting in march
+    let days = time.div_euclid(60 * 60 * 24) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    // March is month 0, so january and february belong to the next year
+    era * 400 * 12 + year_of_era * 12 + month + 2
+}
+
+/// Loads the calendar progress of the character. Progress from earlier months
+/// does not count
+pub(crate) async fn load_calendar(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Calendar, ServerError> {
+    let row = sqlx::query!(
+        "SELECT collected, month, next_possible FROM calendar WHERE pid = $1",
+        pid
+    )
+    .fetch_optional(&mut *con)
+    .await?;
+    let Some(row) = row else {
+        return Ok(Calendar::default());
+    };
+    Ok(Calendar {
+        collected: match row.month == month_index(now()) {
+            true => row.collected,
+            false => 0,
+        },
+        next_possible: row.next_possible,
+    })
+}
+
+/// Gives the character the reward of a calendar day
+async fn give_calendar_reward(
+    con: &mut SqliteConnection,
+    pid: i64,
+    reward: CalendarReward,
+    amount: i64,
+) -> Result<(), ServerError> {
+    use CalendarReward::*;
+    let character =
+        sqlx::query!("SELECT level, class FROM character WHERE pid = $1", pid)
+            .fetch_one(&mut *con)
+            .await?;
+    let level = character.level;
+    match reward {
+        Silver => {
+            let silver = guard_wage(level, 0) * SILVER_HOURS * amount;
+            give_reward(con, pid, RewardTyp::Silver, silver).await?;
+        }
+        Mushrooms => {
+            give_reward(con, pid, RewardTyp::Mushrooms, amount).await?;
+        }
+        Experience => {
+            let xp = xp_for_next_level(level) * XP_PERCENT / 100 * amount;
+            give_reward(con, pid, RewardTyp::XP, xp).await?;
+        }
+        Strength | Dexterity | Intelligence | Constitution | Luck => {
+            let attribute = reward as i64 - Strength as i64;
+            let points = level * ATTRIBUTE_PER_LEVEL * amount;
+            sqlx::query!(
+                "UPDATE attributes SET
+                    strength = strength + ($2 = 0) * $3,
+                    dexterity = dexterity + ($2 = 1) * $3,
+                    intelligence = intelligence + ($2 = 2) * $3,
+                    stamina = stamina + ($2 = 3) * $3,
+                    luck = luck + ($2 = 4) * $3
+                WHERE id = (SELECT attributes FROM character WHERE pid = $1)",
+                pid,
+                attribute,
+                points
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+        Item => {
+            let mut rng = Rng::new();
+            let mut overflow = Vec::new();
+            for _ in 0..amount {
+                let item =
+                    DbItem::random_equipment(&mut rng, level, character.class);
+                let item = insert_item(con, &item).await?;
+                if !add_to_bag(con, pid, item).await? {
+                    overflow.push(item);
+                }
+            }
+            if !overflow.is_empty() {
+                send_reward_mail(con, pid, "Calendar", &[], &overflow).await?;
+            }
+        }
+        ShadowFruit | LightFruit | EarthFruit | FireFruit | WaterFruit => {
+            let habitat = reward as i64 - ShadowFruit as i64;
+            let mut overflow = Vec::new();
+            for _ in 0..amount {
+                let fruit =
+                    insert_item(con, &DbItem::fruit(FRUIT_IDENT + habitat))
+                        .await?;
+                if !add_to_bag(con, pid, fruit).await? {
+                    overflow.push(fruit);
+                }
+            }
+            if !overflow.is_empty() {
+                send_reward_mail(con, pid, "Calendar", &[], &overflow).await?;
+            }
+        }
+        QuicksandGlasses => {
+            let glasses = QUICKSAND_GLASSES * amount;
+            give_reward(con, pid, RewardTyp::QuicksandGlass, glasses).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Claims the next day of the calendar. Only one day can be claimed per day
+pub(crate) async fn player_open_calender(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+) -> Result<ServerResponse, ServerError> {
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+
+    let calendar = load_calendar(&mut tx, pid).await?;
+    if calendar.next_possible > now() {
+        return Err(ServerError::BadRequest);
+    }
+    let Some(&(reward, amount)) = usize::try_from(calendar.collected)
+        .ok()
+        .and_then(|idx| CALENDAR.get(idx))
+    else {
+        return Err(ServerError::BadRequest);
+    };
+    give_calendar_reward(&mut tx, pid, reward, amount).await?;
+
+    let collected = calendar.collected + 1;
+    let month = month_index(now());
+    let next_possible = next_day();
+    sqlx::query!(
+        "INSERT INTO calendar (pid, collected, month, next_possible)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO UPDATE SET collected = excluded.collected,
+            month = excluded.month, next_possible = excluded.next_possible",
+        pid,
+        collected,
+        month,
+        next_possible
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Writes the rewards of the calendar days
+pub(crate) fn add_calendar_info(resp: &mut ResponseBuilder) {
+    resp.add_key("calenderinfo");
+    for (reward, amount) in CALENDAR {
+        resp.add_val(reward as i64);
+        resp.add_val(amount);
+    }
+}
@@ -0,0 +1,224 @@
+use sf_api::misc::{from_sf_string, to_sf_string};
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse, now, poll,
+};
+use crate::request::Session;
+
+/// The maximum amount of messages of one kind, that are sent to the client
+const CHAT_HISTORY_LEN: i64 = 50;
+/// The maximum length of a single chat message
+const MAX_MESSAGE_LEN: usize = 500;
+
+/// Formats a message the way the client expects them in the chat history
+fn format_message(time: i64, name: &str, message: &str) -> String {
+    let time = time.rem_euclid(86400);
+    format!(
+        "{:02}:{:02} {name}:§{}",
+        time / 3600,
+        time % 3600 / 60,
+        to_sf_string(message)
+    )
+}
+
+/// Checks, that the character is allowed to chat and returns the cleaned up
+/// message
+async fn prepare_message(
+    con: &mut SqliteConnection,
+    pid: i64,
+    message: &str,
+) -> Result<String, ServerError> {
+    let message = from_sf_string(message).trim().to_string();
+    if message.is_empty() || message.chars().count() > MAX_MESSAGE_LEN {
+        return Err(ServerError::BadRequest);
+    }
+
+    let muted_until = sqlx::query_scalar!(
+        "SELECT muted_until FROM character WHERE pid = $1", pid
+    )
+    .fetch_one(&mut *con)
+    .await?;
+    if muted_until > now() {
+        return Err(ServerError::Muted);
+    }
+    Ok(message)
+}
+
+pub(crate) async fn group_chat(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let mut tx = db.begin().await?;
+    let message =
+        prepare_message(&mut tx, session.player_id, args.get_str(0, "msg")?)
+            .await?;
+
+    let guild_id = sqlx::query_scalar!(
+        "SELECT guild_id FROM guild_member WHERE pid = $1", session.player_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    let now = now();
+    sqlx::query!(
+        "INSERT INTO chat_message (sender, time, guild, message, is_global)
+        VALUES ($1, $2, $3, $4, FALSE)",
+        session.player_id,
+        now,
+        guild_id,
+        message
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let mut resp = ResponseBuilder::default();
+    add_chat(&mut resp, &mut tx, session.player_id, 0).await?;
+    tx.commit().await?;
+    poll(session, "", db, resp).await
+}
+
+/// Sends a whisper from the character to the character with the given name
+pub(crate) async fn send_whisper(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    name: &str,
+    message: &str,
+) -> Result<ServerResponse, ServerError> {
+    let mut tx = db.begin().await?;
+    let message = prepare_message(&mut tx, session.player_id, message).await?;
+
+    let target = sqlx::query_scalar!(
+        "SELECT pid FROM character
+        WHERE lower(name) = lower($1) AND world_id = (
+            SELECT world_id FROM character WHERE pid = $2
+        )",
+        name,
+        session.player_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+    if target == session.player_id {
+        return Err(ServerError::BadRequest);
+    }
+
+    let now = now();
+    sqlx::query!(
+        "INSERT INTO chat_message (sender, time, whisper, message, is_global)
+        VALUES ($1, $2, $3, $4, FALSE)",
+        session.player_id,
+        now,
+        target,
+        message
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let mut resp = ResponseBuilder::default();
+    add_chat(&mut resp, &mut tx, session.player_id, 0).await?;
+    tx.commit().await?;
+    poll(session, "", db, resp).await
+}
+
+/// Polls the state of the character together with all chat messages, that
+/// are newer than the message id the client provides. Clients, that do not
+/// send a cursor get the recent history
+pub(crate) async fn chat_poll(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let since = args.get_int(0, "chat time").unwrap_or_default();
+    let mut resp = ResponseBuilder::default();
+    add_chat(
+        &mut resp,
+        &mut *db.acquire().await?,
+        session.player_id,
+        since,
+    )
+    .await?;
+    poll(session, "poll", db, resp).await
+}
+
+/// Adds the guild chat and whispers of the character, that are newer than
+/// the provided message id. Messages of ignored characters are left out
+pub(crate) async fn add_chat(
+    resp: &mut ResponseBuilder,
+    con: &mut SqliteConnection,
+    pid: i64,
+    since: i64,
+) -> Result<(), ServerError> {
+    let mut guild_chat = sqlx::query!(
+        "SELECT m.id, m.time, m.message,
+            coalesce(c.name, '') as `name!: String`
+        FROM chat_message m
+        JOIN guild_member gm on gm.guild_id = m.guild
+        LEFT JOIN character c on c.pid = m.sender
+        WHERE gm.pid = $1 AND m.id > $2
+          AND coalesce(m.sender, 0) NOT IN (
+            SELECT ignored FROM ignored_player WHERE pid = $1
+          )
+        ORDER BY m.id DESC
+        LIMIT $3",
+        pid,
+        since,
+        CHAT_HISTORY_LEN
+    )
+    .fetch_all(&mut *con)
+    .await?;
+    guild_chat.reverse();
+
+    // The copy of a sent whisper shows who it was sent to
+    let mut whispers = sqlx::query!(
+        "SELECT m.id, m.time, m.message,
+            CASE WHEN m.sender = $1 THEN 'to ' || coalesce(t.name, '')
+                ELSE coalesce(c.name, '')
+            END as `name!: String`
+        FROM chat_message m
+        LEFT JOIN character c on c.pid = m.sender
+        LEFT JOIN character t on t.pid = m.whisper
+        WHERE (m.whisper = $1 OR (m.sender = $1 AND m.whisper IS NOT NULL))
+          AND m.id > $2
+          AND coalesce(m.sender, 0) NOT IN (
+            SELECT ignored FROM ignored_player WHERE pid = $1
+          )
+        ORDER BY m.id DESC
+        LIMIT $3",
+        pid,
+        since,
+        CHAT_HISTORY_LEN
+    )
+    .fetch_all(&mut *con)
+    .await?;
+    whispers.reverse();
+
+    let latest = guild_chat
+        .iter()
+        .map(|a| a.id)
+        .chain(whispers.iter().map(|a| a.id))
+        .max()
+        .unwrap_or(since);
+
+    if !guild_chat.is_empty() {
+        let messages: Vec<_> = guild_chat
+            .iter()
+            .map(|a| format_message(a.time, &a.name, &a.message))
+            .collect();
+        resp.add_key("chathistory.s(5)");
+        resp.add_str(&messages.join("/"));
+    }
+    if !whispers.is_empty() {
+        let messages: Vec<_> = whispers
+            .iter()
+            .map(|a| format_message(a.time, &a.name, &a.message))
+            .collect();
+        resp.add_key("chatwhisper.s(5)");
+        resp.add_str(&messages.join("/"));
+    }
+    resp.add_key("chattime");
+    resp.add_val(latest);
+    Ok(())
+}
@@ -31,6 +31,11 @@ enum Command {
     AddWorld {
         world_name: String,
     },
+    /// Prevents a character from chatting for the given amount of minutes
+    Mute {
+        name: String,
+        minutes: i64,
+    },
 }
 
 pub(crate) async fn handle_cheat_command(
@@ -70,6 +75,20 @@ pub(crate) async fn handle_cheat_command(
             .execute(db)
             .await?;
         }
+        Command::Mute { name, minutes } => {
+            let muted_until = super::in_seconds(minutes * 60);
+            sqlx::query!(
+                "UPDATE character SET muted_until = $1
+                WHERE lower(name) = lower($2) AND world_id = (
+                    SELECT world_id FROM character WHERE pid = $3
+                )",
+                muted_until,
+                name,
+                session.player_id
+            )
+            .execute(db)
+            .await?;
+        }
         Command::SetPassword { new } => {
             let hashed_password = sha1_hash(&format!("{new}{HASH_CONST}"));
             let mut tx = db.begin().await?;
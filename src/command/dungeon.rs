@@ -0,0 +1,487 @@
+use fastrand::Rng;
+use num_traits::FromPrimitive;
+use sf_api::{
+    gamestate::{
+        dungeons::{LightDungeon, ShadowDungeon},
+        unlockables::HabitatType,
+    },
+    simulate::{
+        Monster,
+        constants::{LIGHT_ENEMIES, SHADOW_ENEMIES},
+    },
+};
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse, add_xp,
+    fight::{Fighter, add_fight, load_player_fighter, simulate_team_fight},
+    in_seconds,
+    item::{
+        DbItem, LIGHT_KEY_IDENT, SHADOW_KEY_IDENT, add_to_bag, insert_item,
+    },
+    mail::send_reward_mail,
+    now,
+    pets::find_pet,
+    poll,
+    task::{TaskType, progress_tasks},
+    tower::{load_companion_fighters, unlock_companions},
+};
+use crate::request::Session;
+
+/// The amount of dungeons the client expects progress for
+const DUNGEON_COUNT: usize = 37;
+/// The time between two free dungeon fights
+const DUNGEON_COOLDOWN: i64 = 60 * 60;
+/// The mushrooms it costs to fight before the cooldown has passed
+const DUNGEON_SKIP_PRICE: i64 = 1;
+/// The chance in percent to find the key of the next dungeon on a quest
+const KEY_DROP_CHANCE: u32 = 10;
+
+/// The dungeons, that are unlocked by keys found on quests, in the order the
+/// keys are found
+const KEY_DUNGEONS: std::ops::RangeInclusive<i64> = 0..=10;
+/// Dungeons, that are unlocked by finishing the dungeon before them
+const CHAINED_DUNGEONS: [(i64, i64); 2] = [(10, 11), (11, 12)];
+/// The tower is not a normal dungeon and has its own command. Its id is used
+/// for the twister in the shadow world instead
+pub(crate) const TOWER: i64 = 14;
+/// The light dungeon, that has to be finished to find the key of the tower
+const TOWER_REQUIREMENT: i64 = 9;
+/// The light dungeon, whose last enemy drops the heart of darkness
+const HEART_OF_DARKNESS_DUNGEON: i64 = 11;
+
+/// The enemies of a dungeon in the order they have to be defeated
+fn dungeon_enemies(dungeon: i64, is_shadow: bool) -> &'static [Monster] {
+    if is_shadow {
+        ShadowDungeon::from_i64(dungeon).map_or(&[], |d| SHADOW_ENEMIES[d])
+    } else {
+        LightDungeon::from_i64(dungeon).map_or(&[], |d| LIGHT_ENEMIES[d])
+    }
+}
+
+/// The 1 based position of the enemy in the enemy tables of the dungeons,
+/// that identifies it as a monster. The shadow dungeons come after the light
+/// ones
+fn dungeon_monster_id(dungeon: i64, stage: usize, is_shadow: bool) -> i64 {
+    let light = LIGHT_ENEMIES
+        .iter()
+        .filter(|(d, _)| is_shadow || (*d as i64) < dungeon)
+        .map(|(_, enemies)| enemies.len());
+    let shadow = SHADOW_ENEMIES
+        .iter()
+        .filter(|(d, _)| is_shadow && (*d as i64) < dungeon)
+        .map(|(_, enemies)| enemies.len());
+    (light.chain(shadow).sum::<usize>() + stage + 1) as i64
+}
+
+/// The level a character needs to have to enter the dungeon
+fn level_requirement(dungeon: i64, is_shadow: bool) -> i64 {
+    dungeon_enemies(dungeon, is_shadow)
+        .first()
+        .map_or(i64::MAX, |a| i64::from(a.level))
+}
+
+/// Loads the progress of the character in every dungeon of one kind. Locked
+/// dungeons have a progress of -1
+pub(crate) async fn load_dungeon_progress(
+    con: &mut SqliteConnection,
+    pid: i64,
+    is_shadow: bool,
+) -> Result<Vec<i64>, ServerError> {
+    let rows = sqlx::query!(
+        "SELECT dungeon, progress FROM dungeon
+        WHERE pid = $1 AND is_shadow = $2",
+        pid,
+        is_shadow
+    )
+    .fetch_all(&mut *con)
+    .await?;
+
+    let mut progress = vec![-1; DUNGEON_COUNT];
+    for row in rows {
+        if let Some(val) = progress.get_mut(row.dungeon as usize) {
+            *val = row.progress;
+        }
+    }
+    Ok(progress)
+}
+
+/// Writes the progress of one kind of dungeons together with the enemies
+/// around the current progress
+pub(crate) fn add_dungeon_progress(
+    resp: &mut ResponseBuilder,
+    progress: &[i64],
+    is_shadow: bool,
+) {
+    let kind = if is_shadow { "shadow" } else { "light" };
+
+    resp.add_key(&format!("dungeonprogress{kind}({})", progress.len()));
+    for val in progress {
+        resp.add_val(val);
+    }
+
+    let mut enemies = Vec::new();
+    for (idx, &progress) in progress.iter().enumerate() {
+        let dungeon = idx as i64;
+        let monsters = dungeon_enemies(dungeon, is_shadow);
+        if progress < 0 || progress >= monsters.len() as i64 {
+            continue;
+        }
+        for offset in -2..=2 {
+            let stage = progress + offset;
+            if stage < 0 {
+                continue;
+            }
+            let stage = stage as usize;
+            if let Some(monster) = monsters.get(stage) {
+                enemies.push((dungeon, stage, monster, offset == 0));
+            }
+        }
+    }
+
+    resp.add_key(&format!("dungeonenemies{kind}({})", enemies.len()));
+    for (dungeon, stage, _, _) in &enemies {
+        resp.add_val(dungeon_monster_id(*dungeon, *stage, is_shadow));
+        resp.add_val(dungeon + 1);
+        resp.add_val(0); // loot
+    }
+
+    let current: Vec<_> = enemies.iter().filter(|a| a.3).collect();
+    resp.add_key(&format!("currentdungeonenemies{kind}({})", current.len()));
+    for (dungeon, stage, monster, _) in current {
+        resp.add_val(dungeon_monster_id(*dungeon, *stage, is_shadow));
+        resp.add_val(dungeon + 1);
+        resp.add_val(monster.level);
+        resp.add_val(monster.class as i64 + 1);
+        resp.add_val(0); // element
+    }
+}
+
+pub(crate) async fn player_dungeon_battle(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let dungeon = args.get_int(0, "dungeon")? - 1;
+    let use_mushroom = args.get_int(1, "use mushroom").unwrap_or_default();
+    // The tower itself is fought with `PlayerTowerBattle`
+    let is_twister = dungeon == TOWER;
+    dungeon_battle(session, db, dungeon, is_twister, use_mushroom == 1).await
+}
+
+pub(crate) async fn player_shadow_battle(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let dungeon = args.get_int(0, "dungeon")? - 1;
+    let use_mushroom = args.get_int(1, "use mushroom").unwrap_or_default();
+    dungeon_battle(session, db, dungeon, true, use_mushroom == 1).await
+}
+
+/// Fights the next enemy in the dungeon. Every fight, won or lost, starts the
+/// cooldown, which can be skipped with mushrooms. In the tower the companions
+/// fight together with the character
+pub(crate) async fn dungeon_battle(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    dungeon: i64,
+    is_shadow: bool,
+    use_mushroom: bool,
+) -> Result<ServerResponse, ServerError> {
+    let mut tx = db.begin().await?;
+
+    let character = sqlx::query!(
+        "SELECT level, class, mushrooms, dungeon_timer FROM character
+        WHERE pid = $1",
+        session.player_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let progress = sqlx::query_scalar!(
+        "SELECT progress FROM dungeon
+        WHERE pid = $1 AND is_shadow = $2 AND dungeon = $3",
+        session.player_id,
+        is_shadow,
+        dungeon
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    let monsters = dungeon_enemies(dungeon, is_shadow);
+    let monster = monsters
+        .get(progress as usize)
+        .ok_or(ServerError::BadRequest)?;
+    if character.level < level_requirement(dungeon, is_shadow) {
+        return Err(ServerError::BadRequest);
+    }
+
+    let mushrooms = match character.dungeon_timer > now() {
+        false => 0,
+        true if use_mushroom => DUNGEON_SKIP_PRICE,
+        true => return Err(ServerError::StillBusy),
+    };
+    if character.mushrooms < mushrooms {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    let timer = in_seconds(DUNGEON_COOLDOWN);
+    sqlx::query!(
+        "UPDATE character
+        SET mushrooms = mushrooms - $2, dungeon_timer = $3
+        WHERE pid = $1",
+        session.player_id,
+        mushrooms,
+        timer
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let mut team = vec![load_player_fighter(&mut tx, session.player_id).await?];
+    if dungeon == TOWER && !is_shadow {
+        team.extend(load_companion_fighters(&mut tx, session.player_id).await?);
+    }
+    let monster_id = dungeon_monster_id(dungeon, progress as usize, is_shadow);
+    let enemy = [Fighter::from_monster(monster_id, monster)];
+    let (fights, won) = simulate_team_fight(&team, &enemy);
+
+    let mut resp = ResponseBuilder::default();
+    match fights.as_slice() {
+        [(a, b, log)] => add_fight(&mut resp, None, 0, a, b, log),
+        fights => {
+            for (idx, (a, b, log)) in fights.iter().enumerate() {
+                add_fight(&mut resp, Some(idx + 1), 0, a, b, log);
+            }
+        }
+    }
+    resp.add_key("fightversion");
+    resp.add_val(1);
+    let fight = TaskType::FightInDungeons;
+    progress_tasks(&mut tx, session.player_id, fight, 1).await?;
+
+    if won {
+        sqlx::query!(
+            "UPDATE dungeon SET progress = progress + 1
+            WHERE pid = $1 AND is_shadow = $2 AND dungeon = $3",
+            session.player_id,
+            is_shadow,
+            dungeon
+        )
+        .execute(&mut *tx)
+        .await?;
+        add_xp(&mut tx, session.player_id, i64::from(monster.xp)).await?;
+        find_pet(
+            &mut tx,
+            session.player_id,
+            HabitatType::Earth,
+            &mut Rng::new(),
+        )
+        .await?;
+
+        let finished = progress + 1 >= monsters.len() as i64;
+        if finished {
+            // The last enemy of a dungeon drops an item of its level
+            let item = DbItem::random_equipment(
+                &mut Rng::new(),
+                i64::from(monster.level),
+                character.class,
+            );
+            let item = insert_item(&mut tx, &item).await?;
+            if !add_to_bag(&mut tx, session.player_id, item).await? {
+                send_reward_mail(
+                    &mut tx,
+                    session.player_id,
+                    "Dungeon",
+                    &[],
+                    &[item],
+                )
+                .await?;
+            }
+            for (_, next) in
+                CHAINED_DUNGEONS.iter().filter(|(prev, _)| *prev == dungeon)
+            {
+                unlock_dungeon(&mut tx, session.player_id, *next, is_shadow)
+                    .await?;
+            }
+            // The twister opens up once the tower has been climbed
+            if dungeon == TOWER && !is_shadow {
+                unlock_dungeon(&mut tx, session.player_id, TOWER, true).await?;
+            }
+            if dungeon == HEART_OF_DARKNESS_DUNGEON && !is_shadow {
+                drop_heart_of_darkness(&mut tx, session.player_id).await?;
+            }
+        }
+    }
+
+    tx.commit().await?;
+    poll(session, "", db, resp).await
+}
+
+async fn unlock_dungeon(
+    con: &mut SqliteConnection,
+    pid: i64,
+    dungeon: i64,
+    is_shadow: bool,
+) -> Result<(), ServerError> {
+    sqlx::query!(
+        "INSERT INTO dungeon (pid, is_shadow, dungeon) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING",
+        pid,
+        is_shadow,
+        dungeon
+    )
+    .execute(&mut *con)
+    .await?;
+    if dungeon == TOWER && !is_shadow {
+        unlock_companions(con, pid).await?;
+    }
+    Ok(())
+}
+
+/// Gives the character the heart of darkness. It is sent by mail, if the bag
+/// is full
+async fn drop_heart_of_darkness(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<(), ServerError> {
+    let heart = insert_item(con, &DbItem::heart_of_darkness()).await?;
+    if !add_to_bag(con, pid, heart).await? {
+        send_reward_mail(con, pid, "Heart of Darkness", &[], &[heart]).await?;
+    }
+    Ok(())
+}
+
+/// Whether every enemy of the dungeon has been defeated
+fn is_finished(progress: &[i64], dungeon: i64, is_shadow: bool) -> bool {
+    progress[dungeon as usize]
+        >= dungeon_enemies(dungeon, is_shadow).len() as i64
+}
+
+/// Rolls, whether the character finds the key to the next locked dungeon on
+/// a quest. Using the key unlocks the dungeon and each key only drops once.
+/// Keys are only found once the character is strong enough to enter the
+/// dungeon. The key of a shadow dungeon can only be found after the light
+/// version has been finished. The tower has no key and opens up directly
+pub(crate) async fn find_dungeon_key(
+    con: &mut SqliteConnection,
+    pid: i64,
+    level: i64,
+    rng: &mut Rng,
+) -> Result<(), ServerError> {
+    let light = load_dungeon_progress(con, pid, false).await?;
+    let shadow = load_dungeon_progress(con, pid, true).await?;
+    let dropped = sqlx::query!(
+        "SELECT is_shadow, dungeon FROM dungeon_key WHERE pid = $1", pid
+    )
+    .fetch_all(&mut *con)
+    .await?;
+    let has_key = |dungeon: i64, is_shadow: bool| {
+        dropped
+            .iter()
+            .any(|a| a.dungeon == dungeon && a.is_shadow == is_shadow)
+    };
+
+    let next_light = KEY_DUNGEONS
+        .clone()
+        .find(|a| light[*a as usize] < 0 && !has_key(*a, false))
+        .map(|a| (a, false));
+    let next_shadow = KEY_DUNGEONS
+        .clone()
+        .find(|a| {
+            shadow[*a as usize] < 0
+                && !has_key(*a, true)
+                && is_finished(&light, *a, false)
+        })
+        .map(|a| (a, true));
+
+    let next_tower = (light[TOWER as usize] < 0
+        && is_finished(&light, TOWER_REQUIREMENT, false))
+    .then_some((TOWER, false));
+
+    let Some((next, is_shadow)) = [next_light, next_tower, next_shadow]
+        .into_iter()
+        .flatten()
+        .find(|(dungeon, is_shadow)| {
+            level >= level_requirement(*dungeon, *is_shadow)
+        })
+    else {
+        return Ok(());
+    };
+    if rng.u32(0..100) >= KEY_DROP_CHANCE {
+        return Ok(());
+    }
+    if next == TOWER {
+        return unlock_dungeon(con, pid, next, is_shadow).await;
+    }
+    sqlx::query!(
+        "INSERT INTO dungeon_key (pid, is_shadow, dungeon) VALUES ($1, $2, $3)",
+        pid, is_shadow, next
+    )
+    .execute(&mut *con)
+    .await?;
+    let key = insert_item(con, &DbItem::dungeon_key(next, is_shadow)).await?;
+    if !add_to_bag(con, pid, key).await? {
+        send_reward_mail(con, pid, "Dungeon key", &[], &[key]).await?;
+    }
+    Ok(())
+}
+
+/// Unlocks the dungeon of the key with the given ident. Returns false, if the
+/// key is not a dungeon key, or its dungeon is already unlocked
+pub(crate) async fn use_dungeon_key(
+    con: &mut SqliteConnection,
+    pid: i64,
+    ident: i64,
+) -> Result<bool, ServerError> {
+    let (dungeon, is_shadow) = match ident {
+        SHADOW_KEY_IDENT.. => (ident - SHADOW_KEY_IDENT, true),
+        _ => (ident - LIGHT_KEY_IDENT, false),
+    };
+    if !KEY_DUNGEONS.contains(&dungeon) {
+        return Ok(false);
+    }
+    let progress = load_dungeon_progress(con, pid, is_shadow).await?;
+    if progress[dungeon as usize] >= 0 {
+        return Ok(false);
+    }
+    unlock_dungeon(con, pid, dungeon, is_shadow).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monster_ids() {
+        assert_eq!(dungeon_monster_id(0, 0, false), 1);
+        assert_eq!(dungeon_monster_id(0, 3, false), 4);
+        let light: usize =
+            LIGHT_ENEMIES.iter().map(|(_, enemies)| enemies.len()).sum();
+        assert_eq!(dungeon_monster_id(0, 0, true), light as i64 + 1);
+    }
+
+    #[test]
+    fn progress_of_the_first_dungeon() {
+        let mut progress = vec![-1; DUNGEON_COUNT];
+        progress[0] = 0;
+        let mut resp = ResponseBuilder::default();
+        add_dungeon_progress(&mut resp, &progress, false);
+
+        let vals = resp.values("dungeonprogresslight(37)");
+        assert_eq!(vals.len(), DUNGEON_COUNT);
+        assert_eq!(vals[0], "0");
+        assert_eq!(vals[1], "-1");
+
+        // The current enemy and the two after it
+        let enemies = resp.values("dungeonenemieslight(3)");
+        assert_eq!(enemies, ["1", "1", "0", "2", "1", "0", "3", "1", "0"]);
+
+        let monster = &dungeon_enemies(0, false)[0];
+        let current = resp.values("currentdungeonenemieslight(1)");
+        assert_eq!(
+            current[..3],
+            ["1".to_string(), "1".to_string(), monster.level.to_string()]
+        );
+    }
+}
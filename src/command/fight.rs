@@ -0,0 +1,431 @@
+use fastrand::Rng;
+use sf_api::{command::AttributeType, simulate::Monster};
+use sqlx::SqliteConnection;
+
+use super::{
+    ResponseBuilder, ServerError,
+    item::load_equipment,
+    pets::pet_attribute_bonus,
+    potion::{load_potions, potion_bonus},
+    witch::EnchantmentEffects,
+};
+
+/// Everything the fight engine needs to know about one side of a 1on1 fight.
+/// Players have their pid as the id, monsters the negative monster id
+#[derive(Debug, Clone)]
+pub(crate) struct Fighter {
+    pub id: i64,
+    pub level: i64,
+    pub class: i64,
+    /// Strength, Dexterity, Intelligence, Constitution, Luck
+    pub attributes: [i64; 5],
+    pub max_hp: i64,
+    /// The additional damage of critical hits in percent
+    pub crit_bonus: i64,
+    /// Strikes first regardless of the luck of the enemy
+    pub first_strike: bool,
+    pub look: FighterLook,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum FighterLook {
+    Player {
+        name: String,
+        /// mouth, hair, brows, eyes, beards, nose, ears, extra, horns,
+        /// influencer
+        portrait: [i64; 10],
+        race: i64,
+        gender: i64,
+    },
+    Monster,
+}
+
+impl Fighter {
+    /// Builds a monster with attributes roughly in line with a player of the
+    /// same level
+    pub(crate) fn monster(monster_id: i64, level: i64, class: i64) -> Fighter {
+        let main = 10 + level * 8;
+        let mut attributes = [main / 2, main / 2, main / 2, main, main / 3];
+        attributes[main_attribute_idx(class)] = main;
+        let mut fighter = Fighter {
+            id: -monster_id,
+            level,
+            class,
+            attributes,
+            max_hp: 0,
+            crit_bonus: 0,
+            first_strike: false,
+            look: FighterLook::Monster,
+        };
+        fighter.max_hp = fighter.calc_hp();
+        fighter
+    }
+
+    /// Builds a fighter from the known stats of a dungeon monster
+    pub(crate) fn from_monster(monster_id: i64, monster: &Monster) -> Fighter {
+        let atr = |typ| i64::from(monster.attributes[typ]);
+        Fighter {
+            id: -monster_id,
+            level: i64::from(monster.level),
+            class: monster.class as i64 + 1,
+            attributes: [
+                atr(AttributeType::Strength),
+                atr(AttributeType::Dexterity),
+                atr(AttributeType::Intelligence),
+                atr(AttributeType::Constitution),
+                atr(AttributeType::Luck),
+            ],
+            max_hp: monster.hp as i64,
+            crit_bonus: 0,
+            first_strike: false,
+            look: FighterLook::Monster,
+        }
+    }
+
+    pub(crate) fn calc_hp(&self) -> i64 {
+        let factor = match self.class {
+            1 | 6 | 11 => 5, // Warrior, Berserker, Paladin
+            2 | 9 | 10 => 2, // Mage, Bard, Necromancer
+            _ => 4,
+        };
+        (self.attributes[3] * factor * (self.level + 1)).max(1)
+    }
+
+    /// Increases the damage of the fighter by the given percentage
+    pub(crate) fn add_damage_bonus(&mut self, percent: i64) {
+        let idx = main_attribute_idx(self.class);
+        self.attributes[idx] = self.attributes[idx] * (100 + percent) / 100;
+    }
+
+    fn main_attribute(&self) -> i64 {
+        self.attributes[main_attribute_idx(self.class)]
+    }
+}
+
+pub(crate) fn main_attribute_idx(class: i64) -> usize {
+    match class {
+        1 | 5 | 6 | 11 => 0, // Warrior, BattleMage, Berserker, Paladin
+        3 | 4 | 7 => 1,      // Scout, Assassin, DemonHunter
+        _ => 2,
+    }
+}
+
+/// The attributes of a character in the order strength, dexterity,
+/// intelligence, constitution, luck
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CharacterAttributes {
+    /// The attributes the character has bought
+    pub base: [i64; 5],
+    /// The attributes including the bonuses of the equipment, gems, pets and
+    /// potions
+    pub total: [i64; 5],
+    /// The bonus in percent the potions grant to the life
+    pub life_bonus: i64,
+}
+
+impl CharacterAttributes {
+    /// The attributes, that the bonuses add to the base attributes
+    pub(crate) fn additions(&self) -> [i64; 5] {
+        std::array::from_fn(|idx| self.total[idx] - self.base[idx])
+    }
+}
+
+/// Loads the attributes of a character together with all their bonuses
+pub(crate) async fn load_attributes(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<CharacterAttributes, ServerError> {
+    let row = sqlx::query!(
+        "SELECT a.strength, a.dexterity, a.intelligence, a.stamina, a.luck
+        FROM character c
+        JOIN attributes a on a.id = c.attributes
+        WHERE pid = $1",
+        pid
+    )
+    .fetch_one(&mut *con)
+    .await?;
+    let base = [
+        row.strength, row.dexterity, row.intelligence, row.stamina, row.luck,
+    ];
+
+    let mut total = base;
+    for item in load_equipment(con, pid).await?.into_iter().flatten() {
+        for (total, bonus) in total.iter_mut().zip(item.attributes()) {
+            *total += bonus;
+        }
+    }
+    let pet_bonus = pet_attribute_bonus(con, pid).await?;
+    for (total, bonus) in total.iter_mut().zip(pet_bonus) {
+        *total = *total * (100 + bonus) / 100;
+    }
+    let potions = load_potions(con, pid).await?;
+    let (potion_attributes, life_bonus) = potion_bonus(&potions);
+    for (total, bonus) in total.iter_mut().zip(potion_attributes) {
+        *total = *total * (100 + bonus) / 100;
+    }
+    Ok(CharacterAttributes {
+        base,
+        total,
+        life_bonus,
+    })
+}
+
+/// Loads the fighter stats of a character including the bonus of the
+/// equipped items
+pub(crate) async fn load_player_fighter(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Fighter, ServerError> {
+    let row = sqlx::query!(
+        "SELECT name, level, class, race, gender, portrait.*
+        FROM character c
+        NATURAL JOIN portrait
+        WHERE pid = $1",
+        pid
+    )
+    .fetch_one(&mut *con)
+    .await?;
+
+    let equipment = load_equipment(con, pid).await?;
+    let enchantments = EnchantmentEffects::of_items(&equipment);
+    let attributes = load_attributes(con, pid).await?;
+    let mut fighter = Fighter {
+        id: pid,
+        level: row.level,
+        class: row.class,
+        attributes: attributes.total,
+        max_hp: 0,
+        crit_bonus: enchantments.crit_damage,
+        first_strike: enchantments.first_strike,
+        look: FighterLook::Player {
+            name: row.name,
+            portrait: [
+                row.mouth, row.hair, row.brows, row.eyes, row.beards, row.nose,
+                row.ears, row.extra, row.horns, row.influencer,
+            ],
+            race: row.race,
+            gender: row.gender,
+        },
+    };
+    fighter.max_hp = fighter.calc_hp() * (100 + attributes.life_bonus) / 100;
+    Ok(fighter)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FightRound {
+    pub actor: i64,
+    /// 0 => hit, 1 => crit, 3 => blocked, 4 => evaded
+    pub action: i64,
+    pub target_life: i64,
+}
+
+#[derive(Debug)]
+pub(crate) struct FightLog {
+    pub rounds: Vec<FightRound>,
+    pub winner: i64,
+    /// The life both sides have left after the fight
+    pub life_left: [i64; 2],
+}
+
+/// Simulates a 1on1 fight until one side is defeated. The fighters start with
+/// the provided life, which allows continuing a fight across multiple
+/// battles (guild raids, hydra, portal)
+pub(crate) fn simulate_fight(
+    a: &Fighter,
+    a_life: i64,
+    b: &Fighter,
+    b_life: i64,
+) -> FightLog {
+    let mut rng = Rng::new();
+    let fighters = [a, b];
+    let mut life = [a_life.max(1), b_life.max(1)];
+    let mut rounds = Vec::new();
+
+    // The luckier fighter gets the first strike, unless only one of them
+    // always strikes first
+    let mut attacker = match (a.first_strike, b.first_strike) {
+        (true, false) => 0,
+        (false, true) => 1,
+        _ if a.attributes[4] >= b.attributes[4] => 0,
+        _ => 1,
+    };
+
+    // Prevent endless fights between two blocking walls
+    for _ in 0..200 {
+        let defender = 1 - attacker;
+        let (att, def) = (fighters[attacker], fighters[defender]);
+
+        let action = match def.class {
+            1 if rng.u8(0..100) < 25 => 3,
+            3 | 4 if rng.u8(0..100) < 50 => 4,
+            _ => {
+                let crit_chance =
+                    (att.attributes[4] * 5 / (def.level * 2).max(1)).min(50);
+                match rng.i64(0..100) < crit_chance {
+                    true => 1,
+                    false => 0,
+                }
+            }
+        };
+
+        if action <= 1 {
+            let base = (att.level * 2 + 2) * (1 + att.main_attribute() / 10);
+            let resistance = 1.0
+                - (def.attributes[main_attribute_idx(att.class)] as f64
+                    / (att.level as f64 * 20.0))
+                    .clamp(0.0, 0.5);
+            let mut damage =
+                (rng.i64(base..=base * 2) as f64 * resistance) as i64;
+            if action == 1 {
+                damage = damage * (200 + att.crit_bonus) / 100;
+            }
+            life[defender] -= damage.max(1);
+        }
+
+        rounds.push(FightRound {
+            actor: att.id,
+            action,
+            target_life: life[defender].max(0),
+        });
+
+        if life[defender] <= 0 {
+            break;
+        }
+        attacker = defender;
+    }
+
+    // If nobody fell, the side with more relative life left wins
+    let winner = match (life[0] > 0, life[1] > 0) {
+        (true, false) => 0,
+        (false, true) => 1,
+        _ if life[0] * b.max_hp >= life[1] * a.max_hp => 0,
+        _ => 1,
+    };
+
+    FightLog {
+        rounds,
+        winner: fighters[winner].id,
+        life_left: [life[0].max(0), life[1].max(0)],
+    }
+}
+
+/// Writes a fight between two fighters into the response. `fight_no` is used
+/// to differentiate multiple fights in one response (fightheader1, fight1, ..)
+/// and should be `None` for single fights
+pub(crate) fn add_fight(
+    resp: &mut ResponseBuilder,
+    fight_no: Option<usize>,
+    location: i64,
+    a: &Fighter,
+    b: &Fighter,
+    log: &FightLog,
+) {
+    let suffix = fight_no.map(|a| a.to_string()).unwrap_or_default();
+
+    resp.add_key(&format!("fightheader{suffix}"));
+    resp.add_val(1);
+    resp.add_val(0);
+    resp.add_val(0);
+    resp.add_val(location);
+    resp.add_val(1);
+    add_fighter(resp, a);
+    add_fighter(resp, b);
+
+    resp.add_key(&format!("fight{suffix}"));
+    let mut rounds = String::new();
+    for (idx, round) in log.rounds.iter().enumerate() {
+        if idx > 0 {
+            rounds.push(',');
+        }
+        rounds.push_str(&format!(
+            "{},{},{}",
+            round.actor, round.action, round.target_life
+        ));
+    }
+    resp.add_str(&rounds);
+
+    resp.add_key(&format!("winnerid{suffix}"));
+    resp.add_val(log.winner);
+}
+
+fn add_fighter(resp: &mut ResponseBuilder, fighter: &Fighter) {
+    match &fighter.look {
+        FighterLook::Player {
+            name,
+            portrait,
+            race,
+            gender,
+        } => {
+            resp.add_val(fighter.id);
+            resp.add_str(name);
+            resp.add_val(fighter.level);
+            resp.add_val(fighter.max_hp);
+            resp.add_val(fighter.max_hp);
+            for attr in fighter.attributes {
+                resp.add_val(attr);
+            }
+            for val in portrait {
+                resp.add_val(val);
+            }
+            resp.add_val(race);
+            resp.add_val(gender);
+            resp.add_val(fighter.class);
+            // Main & sub weapon
+            for _ in 0..24 {
+                resp.add_val(0);
+            }
+        }
+        FighterLook::Monster => {
+            resp.add_val(fighter.id);
+            resp.add_val(fighter.id);
+            resp.add_val(fighter.level);
+            resp.add_val(fighter.max_hp);
+            resp.add_val(fighter.max_hp);
+            for attr in fighter.attributes {
+                resp.add_val(attr);
+            }
+            resp.add_val(fighter.id);
+            for _ in 0..11 {
+                resp.add_val(0);
+            }
+            resp.add_val(fighter.class);
+            resp.add_val(-1);
+            for _ in 0..23 {
+                resp.add_val(0);
+            }
+        }
+    }
+}
+
+/// Simulates a battle between two teams. The first fighter of each team
+/// fights until defeated, after which the next one of that team continues
+/// against the damaged winner. Returns all single fights and whether team
+/// `a` has defeated all of team `b`
+pub(crate) fn simulate_team_fight<'a>(
+    a: &'a [Fighter],
+    b: &'a [Fighter],
+) -> (Vec<(&'a Fighter, &'a Fighter, FightLog)>, bool) {
+    let mut fights = Vec::new();
+    let (mut a_idx, mut b_idx) = (0, 0);
+    let (mut a_life, mut b_life) = (None, None);
+
+    while let (Some(fa), Some(fb)) = (a.get(a_idx), b.get(b_idx)) {
+        let log = simulate_fight(
+            fa,
+            a_life.unwrap_or(fa.max_hp),
+            fb,
+            b_life.unwrap_or(fb.max_hp),
+        );
+        if log.winner == fa.id {
+            a_life = Some(log.life_left[0]);
+            b_life = None;
+            b_idx += 1;
+        } else {
+            b_life = Some(log.life_left[1]);
+            a_life = None;
+            a_idx += 1;
+        }
+        fights.push((fa, fb, log));
+    }
+    (fights, b_idx >= b.len())
+}
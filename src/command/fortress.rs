@@ -0,0 +1,1387 @@
+use std::fmt::Write;
+
+use fastrand::Rng;
+use log::error;
+use num_traits::FromPrimitive;
+use sf_api::gamestate::{
+    fortress::{FortressBuildingType, FortressResourceType, FortressUnitType},
+    unlockables::HabitatType,
+};
+use sqlx::{Sqlite, SqliteConnection};
+use strum::IntoEnumIterator;
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse, add_xp,
+    character_level,
+    fight::{Fighter, add_fight, simulate_team_fight},
+    in_seconds,
+    item::{DbItem, add_to_bag, insert_item},
+    mail::{MAIL_NORMAL, send_system_mail},
+    now,
+    pets::find_pet,
+    poll,
+    task::{TaskType, progress_tasks},
+};
+use crate::request::Session;
+
+/// The character level at which the fortress is unlocked
+const FORTRESS_LEVEL_REQUIREMENT: i64 = 25;
+/// The highest level any building can be upgraded to
+pub(crate) const MAX_BUILDING_LEVEL: i64 = 20;
+/// The amount of buildings a fortress has
+const BUILDING_COUNT: usize = 12;
+/// The amount of hours a production building can produce, before it is full
+const PRODUCTION_HOURS: i64 = 12;
+/// The time an upgrade can be shortened by with one mushroom
+const SKIP_SECONDS_PER_MUSHROOM: i64 = 10 * 60;
+/// The percentage the laborers' quarters shorten upgrades by per level
+const LABORERS_BONUS: i64 = 3;
+/// The amount of wood and stone that can be saved without a treasury
+const BASE_STORAGE: i64 = 2000;
+/// The combat level the wall gains per building level
+const WALL_LEVEL_FACTOR: i64 = 5;
+/// The monster id of the first unit type. The wall comes after the units
+const UNIT_MONSTER_ID: i64 = 700;
+/// The percentage of wood and stone, that is stolen in a won attack
+const STEAL_PERCENT: i64 = 10;
+/// The percentage of the honor of the loser, that the winner of an attack
+/// gains
+const HONOR_PERCENT: i64 = 10;
+/// The time after an attack, before the fortress can attack again
+const ATTACK_COOLDOWN: i64 = 60 * 60;
+/// The time after which a new attack target can be chosen for free
+const REROLL_COOLDOWN: i64 = 60 * 60;
+/// The mushrooms it costs to choose a new attack target before the cooldown
+/// has passed
+pub(crate) const REROLL_PRICE: i64 = 1;
+
+/// The time, silver, wood and stone it costs to upgrade a building to level 1.
+/// The client only shows the prices the server sends and the official tables
+/// are not known, so these are this server's own values
+const BASE_PRICES: [[i64; 4]; BUILDING_COUNT] = [
+    [900, 1000, 0, 0],
+    [900, 500, 35, 12],
+    [900, 200, 0, 0],
+    [900, 300, 22, 0],
+    [900, 1500, 50, 17],
+    [900, 700, 7, 9],
+    [900, 500, 41, 7],
+    [900, 400, 20, 14],
+    [900, 600, 61, 20],
+    [900, 2500, 40, 13],
+    [900, 400, 25, 8],
+    [900, 15000, 30, 13],
+];
+
+/// The time a gem search takes with a level 1 gem mine
+const GEM_SEARCH_TIME: i64 = 8 * 60 * 60;
+/// The percentage each gem mine level shortens the gem search by
+const GEM_MINE_BONUS: i64 = 3;
+/// The percentage each gem mine level increases the power of gems by
+const GEM_POWER_BONUS: i64 = 5;
+
+/// The time, silver, wood and stone it costs to train one unit
+const UNIT_PRICES: [[i64; 4]; 3] =
+    [[600, 0, 15, 5], [600, 0, 11, 6], [300, 0, 19, 3]];
+
+/// The time, silver, wood and stone an upgrade, or unit costs
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct FortressPrice {
+    pub time: i64,
+    pub silver: i64,
+    pub wood: i64,
+    pub stone: i64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Fortress {
+    /// The level of every building, indexed by `FortressBuildingType`
+    pub levels: [i64; BUILDING_COUNT],
+    pub wood: i64,
+    pub stone: i64,
+    /// Produced, but not yet collected wood, stone and experience
+    pub stored: [i64; 3],
+    pub produced_until: i64,
+    pub upgrade: Option<FortressBuildingType>,
+    pub upgrade_began: i64,
+    pub upgrade_finish: i64,
+    /// The units indexed by `FortressUnitType`
+    pub units: [Units; 3],
+    pub honor: i64,
+    pub attack_target: Option<i64>,
+    pub attack_reroll: i64,
+    pub next_attack: i64,
+    /// The `GemValue` of the gem being searched for. 0 => no search
+    pub gem_target: i64,
+    pub gem_search_began: i64,
+    pub gem_search_finish: i64,
+}
+
+/// The units of one type. Units in training are finished one after the other
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Units {
+    pub count: i64,
+    pub in_training: i64,
+    pub training_began: i64,
+    pub training_finish: i64,
+}
+
+impl Fortress {
+    pub(crate) fn level(&self, building: FortressBuildingType) -> i64 {
+        self.levels[building as usize]
+    }
+
+    /// The sum of all building levels
+    pub(crate) fn upgrades(&self) -> i64 {
+        self.levels.iter().sum()
+    }
+
+    /// The price to upgrade the building to its next level
+    pub(crate) fn upgrade_price(
+        &self,
+        building: FortressBuildingType,
+    ) -> FortressPrice {
+        let next = self.level(building) + 1;
+        let [time, silver, wood, stone] = BASE_PRICES[building as usize];
+        let laborers = self.level(FortressBuildingType::LaborersQuarters);
+        let time = time * next * (100 - laborers * LABORERS_BONUS) / 100;
+        FortressPrice {
+            time,
+            silver: silver * next * next,
+            wood: wood * next * next,
+            stone: stone * next * next,
+        }
+    }
+
+    /// The price to search for a gem in the gem mine
+    pub(crate) fn gem_search_price(&self) -> FortressPrice {
+        let level = self.level(FortressBuildingType::GemMine);
+        if level == 0 {
+            return FortressPrice::default();
+        }
+        FortressPrice {
+            time: GEM_SEARCH_TIME * (100 - level * GEM_MINE_BONUS) / 100,
+            silver: 0,
+            wood: 100 * level,
+            stone: 50 * level,
+        }
+    }
+
+    /// The power of a gem found by a character of the given level. Gems of
+    /// all attributes are half as strong
+    fn gem_power(&self, gem_value: i64, level: i64) -> i64 {
+        let mine = self.level(FortressBuildingType::GemMine);
+        let tier = gem_value / 10;
+        let power = level * tier * (100 + mine * GEM_POWER_BONUS) / 100;
+        match gem_value % 10 {
+            5 => power / 2,
+            _ => power,
+        }
+    }
+
+    /// The combat level of the wall, when the fortress is attacked
+    pub(crate) fn wall_level(&self) -> i64 {
+        self.level(FortressBuildingType::Wall) * WALL_LEVEL_FACTOR
+    }
+
+    /// The amount of soldiers recommended to attack this fortress. Roughly
+    /// one per defending unit and wall level
+    pub(crate) fn soldier_advice(&self) -> i64 {
+        self.units[FortressUnitType::Archer as usize].count
+            + self.units[FortressUnitType::Magician as usize].count
+            + self.level(FortressBuildingType::Wall)
+    }
+
+    /// The maximum amount of units of the type, that can be trained
+    pub(crate) fn unit_limit(&self, unit: FortressUnitType) -> i64 {
+        let level = self.level(unit_building(unit));
+        match unit {
+            FortressUnitType::Soldier => level * 3,
+            FortressUnitType::Magician => level,
+            FortressUnitType::Archer => level * 2,
+        }
+    }
+
+    pub(crate) fn unit_price(&self, unit: FortressUnitType) -> FortressPrice {
+        let [time, silver, wood, stone] = UNIT_PRICES[unit as usize];
+        FortressPrice {
+            time,
+            silver,
+            wood,
+            stone,
+        }
+    }
+
+    /// Adds all units, that have finished their training by the given time
+    fn train(&mut self, time: i64) {
+        for unit in FortressUnitType::iter() {
+            let price = self.unit_price(unit);
+            let units = &mut self.units[unit as usize];
+            if units.in_training <= 0 {
+                continue;
+            }
+            let done = ((time - units.training_began) / price.time)
+                .clamp(0, units.in_training);
+            units.count += done;
+            units.in_training -= done;
+            units.training_began += done * price.time;
+            if units.in_training == 0 {
+                units.training_began = 0;
+                units.training_finish = 0;
+            }
+        }
+    }
+
+    /// The maximum amount of wood and stone, that can be saved
+    pub(crate) fn storage_limit(&self) -> i64 {
+        BASE_STORAGE * (self.level(FortressBuildingType::Treasury) + 1)
+    }
+
+    /// The maximum of a resource, that can be saved. Experience is added to
+    /// the character directly, so the stored amount is the only limit there
+    pub(crate) fn resource_limit(&self, resource: FortressResourceType) -> i64 {
+        match resource {
+            FortressResourceType::Experience => self.production_limit(resource),
+            _ => self.storage_limit(),
+        }
+    }
+
+    pub(crate) fn per_hour(&self, resource: FortressResourceType) -> i64 {
+        let level = self.level(production_building(resource));
+        production_per_hour(resource, level)
+    }
+
+    /// The production per hour after the next upgrade of the building
+    pub(crate) fn per_hour_next_level(
+        &self,
+        resource: FortressResourceType,
+    ) -> i64 {
+        let level = self.level(production_building(resource));
+        production_per_hour(resource, level + 1)
+    }
+
+    /// The amount of a resource the production building can hold
+    pub(crate) fn production_limit(
+        &self,
+        resource: FortressResourceType,
+    ) -> i64 {
+        self.per_hour(resource) * PRODUCTION_HOURS
+    }
+
+    /// The amount of a resource, that can be collected at the given time
+    pub(crate) fn collectable(
+        &self,
+        resource: FortressResourceType,
+        time: i64,
+    ) -> i64 {
+        let elapsed = (time - self.produced_until).max(0);
+        let produced = self.per_hour(resource) * elapsed / 3600;
+        (self.stored[resource as usize] + produced)
+            .min(self.production_limit(resource))
+    }
+
+    /// Adds everything produced up to the given time to the stored resources
+    fn produce(&mut self, time: i64) {
+        for resource in FortressResourceType::iter() {
+            self.stored[resource as usize] = self.collectable(resource, time);
+        }
+        self.produced_until = self.produced_until.max(time);
+    }
+
+    /// Produces resources up to now and finishes the upgrade, if it is done.
+    /// Production before the upgrade finished happens at the old level
+    pub(crate) fn update(&mut self) {
+        if let Some(building) = self.upgrade
+            && self.upgrade_finish <= now()
+        {
+            self.produce(self.upgrade_finish);
+            self.finish_upgrade(building);
+        }
+        self.produce(now());
+        self.train(now());
+    }
+
+    fn finish_upgrade(&mut self, building: FortressBuildingType) {
+        self.levels[building as usize] += 1;
+        self.upgrade = None;
+        self.upgrade_began = 0;
+        self.upgrade_finish = 0;
+    }
+
+    /// Checks if the building can be upgraded right now, ignoring its price
+    fn can_upgrade(&self, building: FortressBuildingType) -> bool {
+        let fortress_level = self.level(FortressBuildingType::Fortress);
+        let level = self.level(building);
+        let smithy_requirements = [
+            FortressBuildingType::ArcheryGuild,
+            FortressBuildingType::Barracks,
+            FortressBuildingType::MagesTower,
+            FortressBuildingType::Wall,
+        ];
+
+        if self.upgrade.is_some() || level >= MAX_BUILDING_LEVEL {
+            return false;
+        }
+        // Buildings can not be upgraded while they are in use
+        if building == FortressBuildingType::GemMine && self.gem_target != 0 {
+            return false;
+        }
+        if FortressUnitType::iter().any(|unit| {
+            unit_building(unit) == building
+                && self.units[unit as usize].in_training > 0
+        }) {
+            return false;
+        }
+        if building != FortressBuildingType::Fortress && level >= fortress_level
+        {
+            return false;
+        }
+        if building == FortressBuildingType::Smithy
+            && smithy_requirements.iter().any(|a| self.level(*a) == 0)
+        {
+            return false;
+        }
+        fortress_level >= i64::from(building.required_min_fortress_level())
+    }
+}
+
+/// The building, that trains the unit
+fn unit_building(unit: FortressUnitType) -> FortressBuildingType {
+    match unit {
+        FortressUnitType::Soldier => FortressBuildingType::Barracks,
+        FortressUnitType::Magician => FortressBuildingType::MagesTower,
+        FortressUnitType::Archer => FortressBuildingType::ArcheryGuild,
+    }
+}
+
+/// The building, that produces the resource
+fn production_building(resource: FortressResourceType) -> FortressBuildingType {
+    match resource {
+        FortressResourceType::Wood => FortressBuildingType::WoodcuttersHut,
+        FortressResourceType::Stone => FortressBuildingType::Quarry,
+        FortressResourceType::Experience => FortressBuildingType::Academy,
+    }
+}
+
+/// The production of a building of the level per hour. Like the prices, the
+/// client shows whatever the server sends, so these rates are this server's
+/// own and grow linearly with the level
+fn production_per_hour(resource: FortressResourceType, level: i64) -> i64 {
+    let per_level = match resource {
+        FortressResourceType::Wood => 150,
+        FortressResourceType::Stone => 50,
+        FortressResourceType::Experience => 500,
+    };
+    per_level * level.clamp(0, MAX_BUILDING_LEVEL)
+}
+
+/// Loads the fortress of the character, if it has been unlocked
+pub(crate) async fn load_fortress(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Option<Fortress>, ServerError> {
+    let Some(row) = sqlx::query!("SELECT * FROM fortress WHERE pid = $1", pid)
+        .fetch_optional(&mut *con)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let unit_rows =
+        sqlx::query!("SELECT * FROM fortress_unit WHERE pid = $1", pid)
+            .fetch_all(&mut *con)
+            .await?;
+    let mut units = [Units::default(); 3];
+    for row in unit_rows {
+        if let Some(units) = units.get_mut(row.unit as usize) {
+            *units = Units {
+                count: row.count,
+                in_training: row.in_training,
+                training_began: row.training_began,
+                training_finish: row.training_finish,
+            };
+        }
+    }
+
+    Ok(Some(Fortress {
+        levels: [
+            row.fortress, row.laborers_quarters, row.woodcutters_hut,
+            row.quarry, row.gem_mine, row.academy, row.archery_guild,
+            row.barracks, row.mages_tower, row.treasury, row.smithy, row.wall,
+        ],
+        wood: row.wood,
+        stone: row.stone,
+        stored: [row.stored_wood, row.stored_stone, row.stored_experience],
+        produced_until: row.produced_until,
+        upgrade: row
+            .upgrade_building
+            .and_then(FortressBuildingType::from_i64),
+        upgrade_began: row.upgrade_began,
+        upgrade_finish: row.upgrade_finish,
+        units,
+        honor: row.honor,
+        attack_target: row.attack_target,
+        attack_reroll: row.attack_reroll,
+        next_attack: row.next_attack,
+        gem_target: row.gem_target,
+        gem_search_began: row.gem_search_began,
+        gem_search_finish: row.gem_search_finish,
+    }))
+}
+
+async fn store_fortress(
+    con: &mut SqliteConnection,
+    pid: i64,
+    fortress: &Fortress,
+) -> Result<(), ServerError> {
+    let [
+        main,
+        laborers,
+        woodcutter,
+        quarry,
+        gem_mine,
+        academy,
+        archery,
+        barracks,
+        mages,
+        treasury,
+        smithy,
+        wall,
+    ] = fortress.levels;
+    let [stored_wood, stored_stone, stored_experience] = fortress.stored;
+    let upgrade = fortress.upgrade.map(|a| a as i64);
+
+    sqlx::query!(
+        "UPDATE fortress
+        SET fortress = $2, laborers_quarters = $3, woodcutters_hut = $4,
+            quarry = $5, gem_mine = $6, academy = $7, archery_guild = $8,
+            barracks = $9, mages_tower = $10, treasury = $11, smithy = $12,
+            wall = $13, wood = $14, stone = $15, stored_wood = $16,
+            stored_stone = $17, stored_experience = $18,
+            produced_until = $19, upgrade_building = $20,
+            upgrade_began = $21, upgrade_finish = $22, honor = $23,
+            attack_target = $24, attack_reroll = $25, gem_target = $26,
+            gem_search_began = $27, gem_search_finish = $28,
+            next_attack = $29
+        WHERE pid = $1",
+        pid,
+        main,
+        laborers,
+        woodcutter,
+        quarry,
+        gem_mine,
+        academy,
+        archery,
+        barracks,
+        mages,
+        treasury,
+        smithy,
+        wall,
+        fortress.wood,
+        fortress.stone,
+        stored_wood,
+        stored_stone,
+        stored_experience,
+        fortress.produced_until,
+        upgrade,
+        fortress.upgrade_began,
+        fortress.upgrade_finish,
+        fortress.honor,
+        fortress.attack_target,
+        fortress.attack_reroll,
+        fortress.gem_target,
+        fortress.gem_search_began,
+        fortress.gem_search_finish,
+        fortress.next_attack,
+    )
+    .execute(&mut *con)
+    .await?;
+
+    for (unit, units) in fortress.units.iter().enumerate() {
+        let unit = unit as i64;
+        sqlx::query!(
+            "INSERT INTO fortress_unit
+                (pid, unit, count, in_training, training_began,
+                training_finish)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT DO UPDATE SET count = excluded.count,
+                in_training = excluded.in_training,
+                training_began = excluded.training_began,
+                training_finish = excluded.training_finish",
+            pid,
+            unit,
+            units.count,
+            units.in_training,
+            units.training_began,
+            units.training_finish
+        )
+        .execute(&mut *con)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Loads the fortress with everything produced up to now. Characters, that
+/// are high enough level, get their fortress the first time they use it
+async fn update_fortress(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Fortress, ServerError> {
+    if character_level(con, pid).await? < FORTRESS_LEVEL_REQUIREMENT {
+        return Err(ServerError::BadRequest);
+    }
+    let time = now();
+    sqlx::query!(
+        "INSERT INTO fortress (pid, produced_until) VALUES ($1, $2)
+        ON CONFLICT DO NOTHING",
+        pid,
+        time
+    )
+    .execute(&mut *con)
+    .await?;
+
+    let mut fortress = load_fortress(con, pid)
+        .await?
+        .ok_or(ServerError::Internal)?;
+    fortress.update();
+    if fortress.attack_target.is_none() {
+        fortress.attack_target = find_attack_target(con, pid).await?;
+    }
+    Ok(fortress)
+}
+
+/// Adds wood and stone to the fortress of the character, as far as the
+/// storage allows. Characters without a fortress get nothing
+pub(crate) async fn add_fortress_resources(
+    con: &mut SqliteConnection,
+    pid: i64,
+    wood: i64,
+    stone: i64,
+) -> Result<(), ServerError> {
+    let Some(mut fortress) = load_fortress(con, pid).await? else {
+        return Ok(());
+    };
+    let limit = fortress.storage_limit();
+    fortress.wood = (fortress.wood + wood).min(limit.max(fortress.wood));
+    fortress.stone = (fortress.stone + stone).min(limit.max(fortress.stone));
+    store_fortress(con, pid, &fortress).await
+}
+
+/// Chooses a random other fortress of the same world to attack
+async fn find_attack_target(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Option<i64>, ServerError> {
+    Ok(sqlx::query_scalar!(
+        "SELECT f.pid FROM fortress f
+        JOIN character c ON c.pid = f.pid
+        WHERE f.pid != $1
+            AND c.world_id = (SELECT world_id FROM character WHERE pid = $1)
+        ORDER BY random()
+        LIMIT 1",
+        pid
+    )
+    .fetch_optional(&mut *con)
+    .await?)
+}
+
+fn building_arg(
+    args: &CommandArguments<'_>,
+) -> Result<FortressBuildingType, ServerError> {
+    let building = args.get_int(0, "building")?;
+    FortressBuildingType::from_i64(building - 1).ok_or(ServerError::BadRequest)
+}
+
+pub(crate) async fn fortress_build_start(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let building = building_arg(&args)?;
+    let mut tx = db.begin().await?;
+    let mut fortress = update_fortress(&mut tx, session.player_id).await?;
+
+    if !fortress.can_upgrade(building) {
+        return Err(ServerError::BadRequest);
+    }
+    let price = fortress.upgrade_price(building);
+    if fortress.wood < price.wood || fortress.stone < price.stone {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    let silver = sqlx::query_scalar!(
+        "UPDATE character SET silver = silver - $2
+        WHERE pid = $1 RETURNING silver",
+        session.player_id,
+        price.silver
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if silver < 0 {
+        return Err(ServerError::NotEnoughMoney);
+    }
+
+    fortress.wood -= price.wood;
+    fortress.stone -= price.stone;
+    fortress.upgrade = Some(building);
+    fortress.upgrade_began = now();
+    fortress.upgrade_finish = now() + price.time;
+    store_fortress(&mut tx, session.player_id, &fortress).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Cancels the current upgrade and refunds its price
+pub(crate) async fn fortress_build_stop(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let building = building_arg(&args)?;
+    let mut tx = db.begin().await?;
+    let mut fortress = update_fortress(&mut tx, session.player_id).await?;
+
+    if fortress.upgrade != Some(building) {
+        return Err(ServerError::BadRequest);
+    }
+    let price = fortress.upgrade_price(building);
+    sqlx::query!(
+        "UPDATE character SET silver = silver + $2 WHERE pid = $1",
+        session.player_id, price.silver
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    fortress.wood += price.wood;
+    fortress.stone += price.stone;
+    fortress.upgrade = None;
+    fortress.upgrade_began = 0;
+    fortress.upgrade_finish = 0;
+    store_fortress(&mut tx, session.player_id, &fortress).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Finishes the current upgrade. Upgrades, that are not done yet, can be
+/// finished with mushrooms
+pub(crate) async fn fortress_build_finished(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let building = building_arg(&args)?;
+    let use_mushrooms = args.get_int(1, "mushrooms").unwrap_or_default();
+    let mut tx = db.begin().await?;
+    let upgrade = load_fortress(&mut tx, session.player_id)
+        .await?
+        .and_then(|a| a.upgrade);
+    if upgrade != Some(building) {
+        return Err(ServerError::BadRequest);
+    }
+    let mut fortress = update_fortress(&mut tx, session.player_id).await?;
+
+    // Updating the fortress already finished the upgrade, if it is done
+    if fortress.upgrade == Some(building) {
+        if use_mushrooms <= 0 {
+            return Err(ServerError::StillBusy);
+        }
+        skip_with_mushrooms(
+            &mut tx, session.player_id, fortress.upgrade_finish,
+        )
+        .await?;
+        fortress.finish_upgrade(building);
+    }
+    store_fortress(&mut tx, session.player_id, &fortress).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Pays the mushrooms it costs to finish something early, that would
+/// otherwise be finished at the given time
+pub(crate) async fn skip_with_mushrooms(
+    con: &mut SqliteConnection,
+    pid: i64,
+    finish: i64,
+) -> Result<(), ServerError> {
+    let remaining = (finish - now()).max(0);
+    let price =
+        (remaining + SKIP_SECONDS_PER_MUSHROOM - 1) / SKIP_SECONDS_PER_MUSHROOM;
+    let mushrooms = sqlx::query_scalar!(
+        "UPDATE character SET mushrooms = mushrooms - $2
+        WHERE pid = $1 RETURNING mushrooms",
+        pid,
+        price
+    )
+    .fetch_one(&mut *con)
+    .await?;
+    if mushrooms < 0 {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    Ok(())
+}
+
+/// Collects the resource from its production building. Wood and stone can
+/// only be collected until the storage is full
+pub(crate) async fn fortress_gather(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let resource = match args.get_int(0, "resource")? {
+        1 => FortressResourceType::Wood,
+        2 => FortressResourceType::Stone,
+        3 => FortressResourceType::Experience,
+        _ => return Err(ServerError::BadRequest),
+    };
+    let mut tx = db.begin().await?;
+    let mut fortress = update_fortress(&mut tx, session.player_id).await?;
+
+    let limit = fortress.storage_limit();
+    let stored = &mut fortress.stored[resource as usize];
+    match resource {
+        FortressResourceType::Wood => {
+            let amount = (*stored).min(limit - fortress.wood).max(0);
+            fortress.wood += amount;
+            *stored -= amount;
+            if amount > 0 {
+                let wood = TaskType::CollectWood;
+                progress_tasks(&mut tx, session.player_id, wood, 1).await?;
+            }
+        }
+        FortressResourceType::Stone => {
+            let amount = (*stored).min(limit - fortress.stone).max(0);
+            fortress.stone += amount;
+            *stored -= amount;
+            if amount > 0 {
+                let stone = TaskType::CollectStone;
+                progress_tasks(&mut tx, session.player_id, stone, 1).await?;
+            }
+        }
+        FortressResourceType::Experience => {
+            add_xp(&mut tx, session.player_id, *stored).await?;
+            *stored = 0;
+        }
+    }
+    store_fortress(&mut tx, session.player_id, &fortress).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Starts training units. Units are trained one after the other and are
+/// added to the queue of units already in training
+pub(crate) async fn fortress_build_unit_start(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let unit = match args.get_int(0, "unit")? {
+        1 => FortressUnitType::Soldier,
+        2 => FortressUnitType::Magician,
+        3 => FortressUnitType::Archer,
+        _ => return Err(ServerError::BadRequest),
+    };
+    let count = args.get_int(1, "count")?;
+    let mut tx = db.begin().await?;
+    let mut fortress = update_fortress(&mut tx, session.player_id).await?;
+
+    let limit = fortress.unit_limit(unit);
+    let price = fortress.unit_price(unit);
+    let units = &mut fortress.units[unit as usize];
+    if count < 1 || units.count + units.in_training + count > limit {
+        return Err(ServerError::BadRequest);
+    }
+    // The building can not train units while it is being upgraded
+    if fortress.upgrade == Some(unit_building(unit)) {
+        return Err(ServerError::StillBusy);
+    }
+    let (wood, stone) = (price.wood * count, price.stone * count);
+    if fortress.wood < wood || fortress.stone < stone {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    fortress.wood -= wood;
+    fortress.stone -= stone;
+
+    if units.in_training == 0 {
+        units.training_began = now();
+        units.training_finish = now();
+    }
+    units.in_training += count;
+    units.training_finish += price.time * count;
+    store_fortress(&mut tx, session.player_id, &fortress).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Starts searching for a random gem in the gem mine
+pub(crate) async fn fortress_gem_stone_start(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+) -> Result<ServerResponse, ServerError> {
+    let mut tx = db.begin().await?;
+    let mut fortress = update_fortress(&mut tx, session.player_id).await?;
+
+    let in_upgrade = fortress.upgrade == Some(FortressBuildingType::GemMine);
+    if fortress.level(FortressBuildingType::GemMine) == 0 || in_upgrade {
+        return Err(ServerError::BadRequest);
+    }
+    if fortress.gem_target != 0 {
+        return Err(ServerError::StillBusy);
+    }
+    let price = fortress.gem_search_price();
+    if fortress.wood < price.wood || fortress.stone < price.stone {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    fortress.wood -= price.wood;
+    fortress.stone -= price.stone;
+
+    // The tens are the size of the gem, the last digit its attribute
+    let mut rng = Rng::new();
+    fortress.gem_target = rng.i64(1..=3) * 10 + rng.i64(0..=5);
+    fortress.gem_search_began = now();
+    fortress.gem_search_finish = now() + price.time;
+    store_fortress(&mut tx, session.player_id, &fortress).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Cancels the gem search. The price of the search is not refunded
+pub(crate) async fn fortress_gem_stone_stop(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+) -> Result<ServerResponse, ServerError> {
+    let mut tx = db.begin().await?;
+    let mut fortress = update_fortress(&mut tx, session.player_id).await?;
+    if fortress.gem_target == 0 {
+        return Err(ServerError::BadRequest);
+    }
+    fortress.gem_target = 0;
+    fortress.gem_search_began = 0;
+    fortress.gem_search_finish = 0;
+    store_fortress(&mut tx, session.player_id, &fortress).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Puts the found gem into the bag. Searches, that are not done yet, can be
+/// finished with mushrooms
+pub(crate) async fn fortress_gem_stone_finished(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let use_mushrooms = args.get_int(0, "mushrooms").unwrap_or_default();
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+    let mut fortress = update_fortress(&mut tx, pid).await?;
+
+    if fortress.gem_target == 0 {
+        return Err(ServerError::BadRequest);
+    }
+    if fortress.gem_search_finish > now() {
+        if use_mushrooms <= 0 {
+            return Err(ServerError::StillBusy);
+        }
+        skip_with_mushrooms(&mut tx, pid, fortress.gem_search_finish).await?;
+    }
+
+    let level = character_level(&mut tx, pid).await?;
+    let power = fortress.gem_power(fortress.gem_target, level);
+    let gem =
+        insert_item(&mut tx, &DbItem::gem(fortress.gem_target, power)).await?;
+    if !add_to_bag(&mut tx, pid, gem).await? {
+        return Err(ServerError::InventoryFull);
+    }
+    progress_tasks(&mut tx, pid, TaskType::FindGemInFortress, 1).await?;
+    fortress.gem_target = 0;
+    fortress.gem_search_began = 0;
+    fortress.gem_search_finish = 0;
+    store_fortress(&mut tx, pid, &fortress).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// All units of one type fight together as one fighter, that hits as hard
+/// as a single unit, but has the life of all of them
+fn squad_fighter(unit: FortressUnitType, level: i64, count: i64) -> Fighter {
+    let class = match unit {
+        FortressUnitType::Soldier => 1,
+        FortressUnitType::Magician => 2,
+        FortressUnitType::Archer => 3,
+    };
+    let mut fighter =
+        Fighter::monster(UNIT_MONSTER_ID + unit as i64, level, class);
+    fighter.max_hp *= count;
+    fighter
+}
+
+/// The units of a squad, that died from the damage it has taken
+fn squad_losses(count: i64, max_hp: i64, life_left: i64) -> i64 {
+    (count * (max_hp - life_left) + max_hp - 1) / max_hp
+}
+
+/// The wall, archers and magicians, that defend the fortress, together with
+/// the unit type of each squad
+fn fortress_defense(
+    fortress: &Fortress,
+    level: i64,
+) -> Vec<(Option<FortressUnitType>, Fighter)> {
+    let mut defense = Vec::new();
+    if fortress.wall_level() > 0 {
+        let wall_id = UNIT_MONSTER_ID + FortressUnitType::iter().len() as i64;
+        let wall = Fighter::monster(wall_id, fortress.wall_level(), 1);
+        defense.push((None, wall));
+    }
+    for unit in [FortressUnitType::Archer, FortressUnitType::Magician] {
+        let count = fortress.units[unit as usize].count;
+        if count > 0 {
+            defense.push((Some(unit), squad_fighter(unit, level, count)));
+        }
+    }
+    defense
+}
+
+/// Attacks the current target with the given amount of soldiers. Soldiers,
+/// archers and magicians die depending on the damage they have taken.
+/// Winning steals some of the resources of the target and the winner gains
+/// some of the honor of the loser
+pub(crate) async fn fortress_attack(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let soldiers = args.get_int(0, "soldiers")?;
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+    let mut fortress = update_fortress(&mut tx, pid).await?;
+    if fortress.next_attack > now() {
+        return Err(ServerError::StillBusy);
+    }
+
+    let available = fortress.units[FortressUnitType::Soldier as usize].count;
+    if soldiers < 1 || soldiers > available {
+        return Err(ServerError::BadRequest);
+    }
+    let target = fortress.attack_target.ok_or(ServerError::BadRequest)?;
+    let mut enemy = load_fortress(&mut tx, target)
+        .await?
+        .ok_or(ServerError::BadRequest)?;
+    enemy.update();
+
+    let level = character_level(&mut tx, pid).await?;
+    let enemy_level = character_level(&mut tx, target).await?;
+    let attackers = [squad_fighter(FortressUnitType::Soldier, level, soldiers)];
+    let (defense_units, defense): (Vec<_>, Vec<_>) =
+        fortress_defense(&enemy, enemy_level).into_iter().unzip();
+    let (fights, won) = simulate_team_fight(&attackers, &defense);
+
+    let mut resp = ResponseBuilder::default();
+    match fights.as_slice() {
+        [(a, b, log)] => add_fight(&mut resp, None, 0, a, b, log),
+        fights => {
+            for (idx, (a, b, log)) in fights.iter().enumerate() {
+                add_fight(&mut resp, Some(idx + 1), 0, a, b, log);
+            }
+        }
+    }
+    resp.add_key("fightversion");
+    resp.add_val(1);
+
+    let life_left = match (won, fights.last()) {
+        (true, Some((_, _, log))) => log.life_left[0],
+        (true, None) => attackers[0].max_hp,
+        (false, _) => 0,
+    };
+    let lost = squad_losses(soldiers, attackers[0].max_hp, life_left);
+    fortress.units[FortressUnitType::Soldier as usize].count -= lost;
+
+    // Every defending squad keeps the life it had after its last fight
+    let mut defense_life: Vec<_> = defense.iter().map(|a| a.max_hp).collect();
+    for (_, defender, log) in &fights {
+        if let Some(idx) =
+            defense.iter().position(|a| std::ptr::eq(a, *defender))
+        {
+            defense_life[idx] = log.life_left[1];
+        }
+    }
+    for ((unit, squad), life_left) in
+        defense_units.iter().zip(&defense).zip(defense_life)
+    {
+        let Some(unit) = unit else {
+            continue;
+        };
+        let units = &mut enemy.units[*unit as usize];
+        units.count -= squad_losses(units.count, squad.max_hp, life_left);
+    }
+
+    let (wood, stone, honor) = if won {
+        let wood = (enemy.wood * STEAL_PERCENT / 100)
+            .min(fortress.storage_limit() - fortress.wood)
+            .max(0);
+        let stone = (enemy.stone * STEAL_PERCENT / 100)
+            .min(fortress.storage_limit() - fortress.stone)
+            .max(0);
+        (wood, stone, enemy.honor * HONOR_PERCENT / 100)
+    } else {
+        (0, 0, -(fortress.honor * HONOR_PERCENT / 100))
+    };
+    fortress.wood += wood;
+    fortress.stone += stone;
+    fortress.honor += honor;
+    enemy.wood -= wood;
+    enemy.stone -= stone;
+    enemy.honor -= honor;
+    progress_tasks(&mut tx, pid, TaskType::CommandFortressBattle, 1).await?;
+    if won {
+        find_pet(&mut tx, pid, HabitatType::Fire, &mut Rng::new()).await?;
+    }
+
+    fortress.attack_target = find_attack_target(&mut tx, pid).await?;
+    fortress.next_attack = in_seconds(ATTACK_COOLDOWN);
+    store_fortress(&mut tx, pid, &fortress).await?;
+    store_fortress(&mut tx, target, &enemy).await?;
+
+    let name =
+        sqlx::query_scalar!("SELECT name FROM character WHERE pid = $1", pid)
+            .fetch_one(&mut *tx)
+            .await?;
+    let title = match won {
+        true => format!("{name} has raided your fortress"),
+        false => format!("Your fortress has repelled {name}"),
+    };
+    let body =
+        format!("Wood lost: {wood}\nStone lost: {stone}\nHonor: {}", -honor);
+    send_system_mail(&mut tx, target, MAIL_NORMAL, &title, &body).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, resp).await
+}
+
+/// Chooses a new attack target. This is free once the cooldown has passed
+/// and costs a mushroom before that
+pub(crate) async fn fortress_enemy(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let pay = args.get_int(0, "pay").unwrap_or_default();
+    // Counterattacks on the sender of a combat message are not supported
+    if args.get_int(1, "message id").unwrap_or_default() != 0 {
+        return Err(ServerError::BadRequest);
+    }
+    let mut tx = db.begin().await?;
+    let mut fortress = update_fortress(&mut tx, session.player_id).await?;
+
+    if fortress.attack_reroll <= now() {
+        fortress.attack_reroll = in_seconds(REROLL_COOLDOWN);
+    } else if pay == 1 {
+        let mushrooms = sqlx::query_scalar!(
+            "UPDATE character SET mushrooms = mushrooms - $2
+            WHERE pid = $1 RETURNING mushrooms",
+            session.player_id,
+            REROLL_PRICE
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if mushrooms < 0 {
+            return Err(ServerError::NotEnoughMoney);
+        }
+    } else {
+        return Err(ServerError::StillBusy);
+    }
+    fortress.attack_target =
+        find_attack_target(&mut tx, session.player_id).await?;
+    store_fortress(&mut tx, session.player_id, &fortress).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// The rank of the fortress in the fortress hall of fame of its world
+pub(crate) async fn fortress_rank(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<i64, ServerError> {
+    Ok(sqlx::query_scalar!(
+        "WITH own AS (
+            SELECT f.honor, c.world_id FROM fortress f
+            JOIN character c ON c.pid = f.pid
+            WHERE f.pid = $1
+        )
+        SELECT count(*) FROM fortress f
+        JOIN character c ON c.pid = f.pid
+        WHERE c.world_id = (SELECT world_id FROM own)
+            AND (f.honor > (SELECT honor FROM own)
+                OR (f.honor = (SELECT honor FROM own) AND f.pid <= $1))",
+        pid
+    )
+    .fetch_one(&mut *con)
+    .await?)
+}
+
+pub(crate) async fn fortress_get_hof(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let rank = args.get_int(0, "rank").unwrap_or_default();
+    let pre = args.get_int(2, "pre").unwrap_or_default();
+    let post = args.get_int(3, "post").unwrap_or_default();
+
+    let rank = match rank {
+        1.. => rank,
+        _ => {
+            let name = args.get_str(1, "name")?;
+            let pid = sqlx::query_scalar!(
+                "SELECT pid FROM character WHERE name = $1 AND world_id = $2",
+                name, session.world_id
+            )
+            .fetch_optional(db)
+            .await?
+            .ok_or(ServerError::BadRequest)?;
+            fortress_rank(&mut *db.acquire().await?, pid).await?
+        }
+    };
+
+    let offset = (rank - pre).max(1) - 1;
+    let limit = (pre + post).min(30);
+
+    let res = sqlx::query!(
+        "SELECT c.name, coalesce(g.name, '') as `guild!: String`, f.honor,
+            f.fortress + f.laborers_quarters + f.woodcutters_hut + f.quarry
+            + f.gem_mine + f.academy + f.archery_guild + f.barracks
+            + f.mages_tower + f.treasury + f.smithy + f.wall
+            as `upgrades!: i64`
+        FROM fortress f
+        JOIN character c ON c.pid = f.pid
+        LEFT JOIN guild_member gm ON gm.pid = c.pid
+        LEFT JOIN guild g ON g.id = gm.guild_id
+        WHERE c.world_id = $3
+        ORDER BY f.honor DESC, f.pid ASC
+        LIMIT $2 OFFSET $1",
+        offset,
+        limit,
+        session.world_id,
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut fortresses = String::new();
+    for (entry_idx, fortress) in res.into_iter().enumerate() {
+        fortresses
+            .write_fmt(format_args!(
+                "{},{},{},{},{};",
+                offset + entry_idx as i64 + 1,
+                fortress.name,
+                fortress.guild,
+                fortress.upgrades,
+                fortress.honor,
+            ))
+            .map_err(|e| {
+                error!("Error while writing format: {e:?}");
+                ServerError::Internal
+            })?;
+    }
+
+    ResponseBuilder::default()
+        .add_key("Ranklistfortress.r")
+        .add_str(&fortresses)
+        .build()
+}
+
+/// Writes the part of the player save, that starts with the building levels
+/// and ends with the per hour production
+pub(crate) fn add_fortress_save(
+    resp: &mut ResponseBuilder,
+    fortress: Option<&Fortress>,
+) {
+    let time = now();
+    let levels = fortress.map(|a| a.levels).unwrap_or_default();
+    for level in levels {
+        resp.add_val(level); // 524..=535
+    }
+    for _ in 536..547 {
+        resp.add_val(0);
+    }
+
+    let [soldiers, magicians, archers] =
+        fortress.map(|a| a.units).unwrap_or_default();
+    resp.add_val(soldiers.count | magicians.count << 16); // 547
+    resp.add_val(archers.count | soldiers.in_training << 16); // 548
+    resp.add_val(magicians.in_training | archers.in_training << 16); // 549
+    for units in [soldiers, magicians, archers] {
+        resp.add_val(units.training_began); // 550..=552
+    }
+    for units in [soldiers, magicians, archers] {
+        resp.add_val(units.training_finish); // 553..=555
+    }
+    for _ in 556..562 {
+        resp.add_val(0);
+    }
+
+    let Some(fortress) = fortress else {
+        for _ in 562..578 {
+            resp.add_val(0);
+        }
+        return;
+    };
+    for resource in FortressResourceType::iter() {
+        resp.add_val(fortress.collectable(resource, time)); // 562..=564
+    }
+    for resource in FortressResourceType::iter() {
+        resp.add_val(fortress.production_limit(resource)); // 565..=567
+    }
+    for resource in FortressResourceType::iter() {
+        resp.add_val(fortress.resource_limit(resource)); // 568..=570
+    }
+    resp.add_val(fortress.upgrade.map_or(0, |a| a as i64 + 1)); // 571
+    resp.add_val(fortress.upgrade_finish); // 572
+    resp.add_val(fortress.upgrade_began); // 573
+    for resource in FortressResourceType::iter() {
+        resp.add_val(fortress.per_hour(resource)); // 574..=576
+    }
+    resp.add_val(time); // 577 collectable updated
+}
+
+/// Writes the fortress part of the look at save of another player, starting
+/// at index 208
+pub(crate) fn add_other_fortress(
+    resp: &mut ResponseBuilder,
+    fortress: Option<&Fortress>,
+    level: i64,
+) {
+    let Some(fortress) = fortress else {
+        for _ in 208..252 {
+            resp.add_val(0);
+        }
+        return;
+    };
+    let time = now();
+    for level in fortress.levels {
+        resp.add_val(level); // 208..=219
+    }
+    for _ in 220..228 {
+        resp.add_val(0);
+    }
+    let [soldiers, magicians, archers] = fortress.units;
+    resp.add_val(fortress.wood); // 228
+    resp.add_val(fortress.stone); // 229
+    resp.add_val(soldiers.count | magicians.count << 16); // 230
+    resp.add_val(archers.count); // 231
+    for _ in 232..239 {
+        resp.add_val(0);
+    }
+    for resource in [FortressResourceType::Wood, FortressResourceType::Stone] {
+        resp.add_val(fortress.collectable(resource, time)); // 239..=240
+    }
+    for resource in [FortressResourceType::Wood, FortressResourceType::Stone] {
+        resp.add_val(fortress.production_limit(resource)); // 241..=242
+    }
+    for _ in 243..249 {
+        resp.add_val(0);
+    }
+    for _ in FortressUnitType::iter() {
+        resp.add_val(level); // 249..=251
+    }
+}
+
+/// Writes the price of the next upgrade of every building
+pub(crate) fn add_fortress_prices(
+    resp: &mut ResponseBuilder,
+    fortress: Option<&Fortress>,
+) {
+    resp.add_key("fortressprice.fortressPrice(13)");
+    for building in FortressBuildingType::iter() {
+        let price = fortress
+            .map(|a| a.upgrade_price(building))
+            .unwrap_or_default();
+        resp.add_val(price.time);
+        resp.add_val(price.silver);
+        resp.add_val(price.wood);
+        resp.add_val(price.stone);
+    }
+    let price = fortress.map(|a| a.gem_search_price()).unwrap_or_default();
+    resp.add_val(price.time);
+    resp.add_val(price.silver);
+    resp.add_val(price.wood);
+    resp.add_val(price.stone);
+}
+
+/// Writes the price to train one unit of every type
+pub(crate) fn add_unit_prices(
+    resp: &mut ResponseBuilder,
+    fortress: Option<&Fortress>,
+) {
+    resp.add_key("unitprice.fortressPrice(3)");
+    for unit in FortressUnitType::iter() {
+        let price = fortress.map(|a| a.unit_price(unit)).unwrap_or_default();
+        resp.add_val(price.time);
+        resp.add_val(price.silver);
+        resp.add_val(price.wood);
+        resp.add_val(price.stone);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fortress() -> Fortress {
+        Fortress {
+            levels: [1; BUILDING_COUNT],
+            wood: 10,
+            stone: 20,
+            stored: [0; 3],
+            produced_until: now(),
+            upgrade: None,
+            upgrade_began: 0,
+            upgrade_finish: 0,
+            units: Default::default(),
+            honor: 0,
+            attack_target: None,
+            attack_reroll: 0,
+            next_attack: 0,
+            gem_target: 0,
+            gem_search_began: 0,
+            gem_search_finish: 0,
+        }
+    }
+
+    #[test]
+    fn save_has_every_index() {
+        for fortress in [None, Some(&fortress())] {
+            let mut resp = ResponseBuilder::default();
+            resp.add_key("save");
+            add_fortress_save(&mut resp, fortress);
+            assert_eq!(resp.values("save").len(), 578 - 524);
+        }
+    }
+
+    #[test]
+    fn other_fortress_has_every_index() {
+        for fortress in [None, Some(&fortress())] {
+            let mut resp = ResponseBuilder::default();
+            resp.add_key("lookat");
+            add_other_fortress(&mut resp, fortress, 10);
+            let vals = resp.values("lookat");
+            assert_eq!(vals.len(), 252 - 208);
+            if fortress.is_some() {
+                assert_eq!(vals[228 - 208], "10");
+                assert_eq!(vals[229 - 208], "20");
+            }
+        }
+    }
+
+    #[test]
+    fn losses_by_damage() {
+        assert_eq!(squad_losses(10, 100, 100), 0);
+        assert_eq!(squad_losses(10, 100, 0), 10);
+        assert_eq!(squad_losses(10, 100, 95), 1);
+        assert_eq!(squad_losses(10, 100, 50), 5);
+    }
+}
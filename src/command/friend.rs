@@ -0,0 +1,202 @@
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse,
+    mail::{MAIL_NORMAL, send_system_mail},
+    now, poll,
+};
+use crate::request::Session;
+
+/// How long after the last request a character is still shown as online
+const ONLINE_DURATION: i64 = 10 * 60;
+
+const RELATION_IGNORED: i64 = -1;
+const RELATION_NORMAL: i64 = 0;
+const RELATION_FRIEND: i64 = 1;
+
+pub(crate) async fn player_friend_set(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let other = args.get_int(0, "player id")?;
+    let relation = args.get_int(1, "relation")?;
+    if other == session.player_id {
+        return Err(ServerError::BadRequest);
+    }
+
+    let mut tx = db.begin().await?;
+    let other_exists = sqlx::query_scalar!(
+        "SELECT count(*) FROM character WHERE pid = $1 AND world_id = (
+            SELECT world_id FROM character WHERE pid = $2
+        )",
+        other,
+        session.player_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if other_exists == 0 {
+        return Err(ServerError::BadRequest);
+    }
+
+    // Any change of the relation ends a friendship. Their request to us
+    // stays around, so that we can accept it again later
+    sqlx::query!(
+        "DELETE FROM friend WHERE pid = $1 AND friend = $2", session.player_id,
+        other
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE friend SET accepted = FALSE WHERE pid = $1 AND friend = $2",
+        other, session.player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM ignored_player WHERE pid = $1 AND ignored = $2",
+        session.player_id, other
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    match relation {
+        RELATION_IGNORED => {
+            sqlx::query!(
+                "INSERT INTO ignored_player (pid, ignored) VALUES ($1, $2)",
+                session.player_id, other
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        RELATION_NORMAL => {}
+        RELATION_FRIEND => {
+            let requested = sqlx::query_scalar!(
+                "SELECT count(*) FROM friend WHERE pid = $1 AND friend = $2",
+                other, session.player_id
+            )
+            .fetch_one(&mut *tx)
+            .await?
+                > 0;
+
+            sqlx::query!(
+                "INSERT INTO friend (pid, friend, accepted)
+                VALUES ($1, $2, $3)",
+                session.player_id, other, requested
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            if requested {
+                sqlx::query!(
+                    "UPDATE friend SET accepted = TRUE
+                    WHERE pid = $1 AND friend = $2",
+                    other,
+                    session.player_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            } else {
+                let name = sqlx::query_scalar!(
+                    "SELECT name FROM character WHERE pid = $1",
+                    session.player_id
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                send_system_mail(
+                    &mut tx,
+                    other,
+                    MAIL_NORMAL,
+                    &format!("Friend request from {name}"),
+                    &format!("Add {name} as a friend to accept the request"),
+                )
+                .await?;
+            }
+        }
+        _ => return Err(ServerError::BadRequest),
+    }
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// The relation the character has to the other character, as the client
+/// expects it
+pub(crate) async fn relation_to(
+    con: &mut SqliteConnection,
+    pid: i64,
+    other: i64,
+) -> Result<i64, ServerError> {
+    let row = sqlx::query!(
+        "SELECT
+            EXISTS (
+                SELECT * FROM friend
+                WHERE pid = $1 AND friend = $2 AND accepted
+            ) as `is_friend!: bool`,
+            EXISTS (
+                SELECT * FROM ignored_player WHERE pid = $1 AND ignored = $2
+            ) as `is_ignored!: bool`",
+        pid,
+        other
+    )
+    .fetch_one(&mut *con)
+    .await?;
+
+    Ok(match (row.is_friend, row.is_ignored) {
+        (true, _) => RELATION_FRIEND,
+        (_, true) => RELATION_IGNORED,
+        _ => RELATION_NORMAL,
+    })
+}
+
+/// Adds all friends and ignored characters of the character
+pub(crate) async fn add_friend_list(
+    resp: &mut ResponseBuilder,
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<(), ServerError> {
+    let online_since = now() - ONLINE_DURATION;
+    let relations = sqlx::query!(
+        "SELECT c.pid, c.name, c.level,
+            coalesce(g.name, '') as `guild!: String`,
+            r.relation as `relation!: i64`,
+            EXISTS (
+                SELECT * FROM session s
+                WHERE s.pid = c.pid AND s.last_active > $2
+            ) as `online!: bool`
+        FROM (
+            SELECT friend as other, 1 as relation
+            FROM friend WHERE pid = $1 AND accepted
+            UNION ALL
+            SELECT ignored as other, -1 as relation
+            FROM ignored_player WHERE pid = $1
+        ) r
+        JOIN character c on c.pid = r.other
+        LEFT JOIN guild_member gm on gm.pid = c.pid
+        LEFT JOIN guild g on g.id = gm.guild_id
+        ORDER BY r.relation DESC, c.name ASC",
+        pid,
+        online_since
+    )
+    .fetch_all(&mut *con)
+    .await?;
+
+    let mut list = String::new();
+    for entry in relations {
+        list.push_str(&format!(
+            "{},{},{},{},{},{};",
+            entry.pid,
+            entry.name,
+            entry.guild,
+            entry.level,
+            entry.relation,
+            entry.online as i64
+        ));
+    }
+    if list.is_empty() {
+        list.push(';');
+    }
+    resp.add_key("friendlist.r");
+    resp.add_str(&list);
+    Ok(())
+}
@@ -1,10 +1,46 @@
 use std::fmt::Write;
 
-use sqlx::Sqlite;
+use sf_api::misc::to_sf_string;
+use sqlx::{Sqlite, SqliteConnection};
 
-use super::CommandArguments;
+use super::{
+    CommandArguments,
+    fight::{
+        Fighter, add_fight, load_player_fighter, simulate_fight,
+        simulate_team_fight,
+    },
+    in_seconds,
+    mail::{
+        MAIL_GUILD_INVITE, MAIL_NORMAL, RewardTyp, send_reward_mail,
+        send_system_mail,
+    },
+    next_day, now, poll,
+};
 use crate::{ResponseBuilder, ServerError, ServerResponse, request::Session};
 
+/// The guild ranks as the client knows them
+pub(crate) const RANK_LEADER: i64 = 1;
+pub(crate) const RANK_OFFICER: i64 = 2;
+
+/// The amount of raids a guild can complete
+const RAID_COUNT: i64 = 50;
+/// The time between declaring a raid and it taking place
+const RAID_DELAY: i64 = 60 * 60;
+/// The id the client uses as the opponent of a raid
+const RAID_OPPONENT_ID: i64 = 1_000_000;
+
+/// The amount of heads the hydra has, that can be defeated
+const HYDRA_HEADS: i64 = 20;
+/// The monster id of the first hydra head
+const HYDRA_MONSTER_ID: i64 = 800;
+/// The amount of pets a guild can choose from
+const PET_COUNT: i64 = 100;
+
+/// The amount of enemies in the demon portal
+const PORTAL_ENEMIES: i64 = 50;
+/// The monster id of the first demon portal enemy
+const PORTAL_MONSTER_ID: i64 = 850;
+
 pub(crate) async fn group_get_hof(
     session: Session,
     db: &sqlx::Pool<Sqlite>,
@@ -56,12 +92,13 @@ pub(crate) async fn group_get_hof(
             FROM guild as g
             JOIN guild_member as gm on gm.guild_id = g.id
             NATURAL JOIN character as c
-            WHERE g.world_id = $3 AND RANK = 3
+            WHERE g.world_id = $3 AND gm.rank = $4
             ORDER BY g.honor desc, g.id asc
             LIMIT $2 OFFSET $1",
         offset,
         limit,
-        session.world_id
+        session.world_id,
+        RANK_LEADER
     )
     .fetch_all(db)
     .await?;
@@ -86,3 +123,613 @@ pub(crate) async fn group_get_hof(
         .add_str(&guilds)
         .build()
 }
+
+pub(crate) async fn group_raid_declare(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+) -> Result<ServerResponse, ServerError> {
+    let mut tx = db.begin().await?;
+
+    let member = sqlx::query!(
+        "SELECT guild_id, rank, raid, raid_time
+        FROM guild_member
+        JOIN guild on guild.id = guild_member.guild_id
+        WHERE pid = $1",
+        session.player_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    if member.rank != RANK_LEADER && member.rank != RANK_OFFICER {
+        return Err(ServerError::BadRequest);
+    }
+    if member.raid >= RAID_COUNT {
+        return Err(ServerError::BadRequest);
+    }
+    if member.raid_time != 0 {
+        return Err(ServerError::StillBusy);
+    }
+
+    let raid_time = in_seconds(RAID_DELAY);
+    sqlx::query!(
+        "UPDATE guild SET raid_time = $1 WHERE id = $2", raid_time,
+        member.guild_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Whoever declares the raid is obviously going to join it
+    sqlx::query!(
+        "UPDATE guild_member SET is_attacking = TRUE WHERE pid = $1",
+        session.player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+pub(crate) async fn group_ready_attack(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+) -> Result<ServerResponse, ServerError> {
+    let res = sqlx::query!(
+        "UPDATE guild_member SET is_attacking = TRUE WHERE pid = $1",
+        session.player_id
+    )
+    .execute(db)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ServerError::BadRequest);
+    }
+    poll(session, "", db, Default::default()).await
+}
+
+pub(crate) async fn group_invite_member(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let name = args.get_str(0, "name")?;
+    let mut tx = db.begin().await?;
+
+    let member = sqlx::query!(
+        "SELECT guild.name, guild.world_id, rank
+        FROM guild_member
+        JOIN guild on guild.id = guild_member.guild_id
+        WHERE pid = $1",
+        session.player_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    if member.rank != RANK_LEADER && member.rank != RANK_OFFICER {
+        return Err(ServerError::BadRequest);
+    }
+
+    let target = sqlx::query_scalar!(
+        "SELECT pid FROM character
+        WHERE lower(name) = lower($1) AND world_id = $2
+          AND pid NOT IN (SELECT pid FROM guild_member)",
+        name,
+        member.world_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    send_system_mail(&mut tx, target, MAIL_GUILD_INVITE, &member.name, "")
+        .await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// The monsters, that defend the given raid. Each raid is a little harder
+/// and has a few more monsters than the one before
+fn raid_monsters(raid: i64) -> Vec<Fighter> {
+    let level = 50 + raid * 10;
+    let count = 3 + raid / 10;
+    (0..count)
+        .map(|pos| {
+            let class = pos % 3 + 1;
+            Fighter::monster(400 + raid * 5 + pos, level + pos, class)
+        })
+        .collect()
+}
+
+/// Runs the raid of the guild of the character, if it is due. Raids are not
+/// run by a scheduler, but whenever someone of the guild looks at the state
+/// after the raid time has passed. That member gets to watch the raid
+pub(crate) async fn update_guild(
+    resp: &mut ResponseBuilder,
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<(), ServerError> {
+    let Some(guild) = sqlx::query!(
+        "SELECT guild.id, guild.name, raid, raid_time, demon_portal_act
+        FROM guild_member
+        JOIN guild on guild.id = guild_member.guild_id
+        WHERE pid = $1",
+        pid
+    )
+    .fetch_optional(&mut *con)
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let now = now();
+    if guild.raid_time == 0 || guild.raid_time > now {
+        return Ok(());
+    }
+
+    let participants = sqlx::query_scalar!(
+        "SELECT pid FROM guild_member
+        WHERE guild_id = $1 AND is_attacking
+        ORDER BY joined ASC",
+        guild.id
+    )
+    .fetch_all(&mut *con)
+    .await?;
+
+    let mut attackers = Vec::new();
+    for pid in &participants {
+        let mut fighter = load_player_fighter(con, *pid).await?;
+        fighter.add_damage_bonus(portal_damage_bonus(guild.demon_portal_act));
+        attackers.push(fighter);
+    }
+
+    let monsters = raid_monsters(guild.raid);
+    let (fights, won) = simulate_team_fight(&attackers, &monsters);
+
+    if !fights.is_empty() {
+        resp.add_key("fightgroups");
+        resp.add_str(&format!(
+            "{},{RAID_OPPONENT_ID},{},",
+            guild.id, guild.name
+        ));
+        for (idx, (a, b, log)) in fights.iter().enumerate() {
+            add_fight(resp, Some(idx + 1), 0, a, b, log);
+        }
+        resp.add_key("fightversion");
+        resp.add_val(1);
+    }
+
+    if won {
+        let raid = guild.raid + 1;
+        let silver = raid * 500;
+        let honor = raid * 10;
+
+        sqlx::query!(
+            "UPDATE guild SET raid = $1, honor = honor + $2 WHERE id = $3",
+            raid, honor, guild.id
+        )
+        .execute(&mut *con)
+        .await?;
+
+        for pid in &participants {
+            send_reward_mail(
+                con,
+                *pid,
+                &format!("Raid {raid} won"),
+                &[(RewardTyp::Silver, silver), (RewardTyp::Honor, honor)],
+                &[],
+            )
+            .await?;
+        }
+    } else {
+        let title = format!("Raid {} lost", guild.raid + 1);
+        for pid in &participants {
+            send_system_mail(con, *pid, MAIL_NORMAL, &title, "").await?;
+        }
+    }
+
+    sqlx::query!("UPDATE guild SET raid_time = 0 WHERE id = $1", guild.id)
+        .execute(&mut *con)
+        .await?;
+    sqlx::query!(
+        "UPDATE guild_member SET is_attacking = FALSE WHERE guild_id = $1",
+        guild.id
+    )
+    .execute(&mut *con)
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn group_set_pet(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let pet_id = args.get_int(0, "pet id")?;
+    if !(1..=PET_COUNT).contains(&pet_id) {
+        return Err(ServerError::BadRequest);
+    }
+
+    let rank = sqlx::query_scalar!(
+        "SELECT rank FROM guild_member WHERE pid = $1", session.player_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    if rank != RANK_LEADER && rank != RANK_OFFICER {
+        return Err(ServerError::BadRequest);
+    }
+
+    sqlx::query!(
+        "UPDATE guild SET pet_id = $1
+        WHERE id = (SELECT guild_id FROM guild_member WHERE pid = $2)",
+        pet_id,
+        session.player_id
+    )
+    .execute(db)
+    .await?;
+
+    poll(session, "", db, Default::default()).await
+}
+
+/// The hydra after the given amount of heads have been defeated. Its life is
+/// meant to be whittled down by the whole guild over multiple days
+fn hydra(heads: i64) -> Fighter {
+    let mut hydra =
+        Fighter::monster(HYDRA_MONSTER_ID + heads, 100 + heads * 20, 2);
+    hydra.max_hp *= 25;
+    hydra
+}
+
+/// Whether a daily guild fight, that was last fought at the time, has already
+/// been fought today. The hydra and the portal reset at the start of the day
+pub(crate) fn fought_today(fought: bool, last_fought: i64) -> bool {
+    fought && last_fought >= next_day() - 60 * 60 * 24
+}
+
+/// The maximum level of the guild pet. Every defeated hydra head raises it
+fn guild_pet_max_lvl(heads: i64) -> i64 {
+    20 + heads * 10
+}
+
+pub(crate) async fn group_pet_battle(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    // The hydra can only be fought once per day, so there is no paid fight
+    if args.get_int(0, "use mushroom")? != 0 {
+        return Err(ServerError::BadRequest);
+    }
+    let mut tx = db.begin().await?;
+
+    let member = sqlx::query!(
+        "SELECT guild_id, hydra_fought, hydra_last_fought, pet_id,
+            hydra_heads, hydra_current_life, hydra_last_full,
+            c.level, gu.petlvl
+        FROM guild_member gm
+        JOIN guild on guild.id = gm.guild_id
+        JOIN character c on c.pid = gm.pid
+        JOIN guild_upgrade gu on gu.pid = gm.pid
+        WHERE gm.pid = $1",
+        session.player_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    let Some(pet_id) = member.pet_id else {
+        return Err(ServerError::BadRequest);
+    };
+    let heads = member.hydra_heads.unwrap_or_default();
+    if heads >= HYDRA_HEADS {
+        return Err(ServerError::BadRequest);
+    }
+
+    if fought_today(member.hydra_fought, member.hydra_last_fought) {
+        return Err(ServerError::StillBusy);
+    }
+    let now = now();
+
+    // Every member fights with the guild pet at their own pet skill. The
+    // defeated heads make the pet stronger for everyone
+    let pet_lvl = member.petlvl.min(guild_pet_max_lvl(heads));
+    let mut pet = Fighter::monster(pet_id, member.level, 1);
+    for attr in &mut pet.attributes {
+        *attr = *attr * (100 + pet_lvl * 2 + heads * 5) / 100;
+    }
+    pet.max_hp = pet.calc_hp();
+
+    let mut hydra = hydra(heads);
+    let life = match member.hydra_current_life {
+        ..=0 => hydra.max_hp,
+        life => life,
+    };
+    let log = simulate_fight(&pet, pet.max_hp, &hydra, life);
+
+    let mut resp = ResponseBuilder::default();
+    add_fight(&mut resp, None, 0, &pet, &hydra, &log);
+    resp.add_key("fightversion");
+    resp.add_val(1);
+
+    let (mut heads, mut life, mut last_full) =
+        (heads, log.life_left[1], member.hydra_last_full);
+    if life == 0 {
+        heads += 1;
+        hydra = self::hydra(heads);
+        life = hydra.max_hp;
+        last_full = now;
+    }
+
+    sqlx::query!(
+        "UPDATE guild
+        SET hydra_heads = $1, hydra_current_life = $2, hydra_last_full = $3,
+            hydra_last_battle = $4
+        WHERE id = $5",
+        heads,
+        life,
+        last_full,
+        now,
+        member.guild_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE guild_member
+        SET hydra_fought = TRUE, hydra_last_fought = $1
+        WHERE pid = $2",
+        now,
+        session.player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    poll(session, "", db, resp).await
+}
+
+/// The demon portal enemy of the given act
+fn portal_enemy(act: i64) -> Fighter {
+    let mut enemy = Fighter::monster(
+        PORTAL_MONSTER_ID + act,
+        150 + act * 10,
+        (act - 1) % 3 + 1,
+    );
+    enemy.max_hp *= 20;
+    enemy
+}
+
+/// The damage bonus in percent, that all members of a guild get for having
+/// defeated the enemies before the current act of the demon portal
+pub(crate) fn portal_damage_bonus(act: i64) -> i64 {
+    act - 1
+}
+
+pub(crate) async fn group_portal_battle(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+) -> Result<ServerResponse, ServerError> {
+    let mut tx = db.begin().await?;
+
+    let member = sqlx::query!(
+        "SELECT guild_id, portal_fought, portal_last_fought, demon_portal_act,
+            demon_portal_health
+        FROM guild_member
+        JOIN guild on guild.id = guild_member.guild_id
+        WHERE pid = $1",
+        session.player_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    if fought_today(member.portal_fought, member.portal_last_fought) {
+        return Err(ServerError::StillBusy);
+    }
+    let act = member.demon_portal_act;
+    if act > PORTAL_ENEMIES {
+        return Err(ServerError::BadRequest);
+    }
+
+    let mut player = load_player_fighter(&mut tx, session.player_id).await?;
+    player.add_damage_bonus(portal_damage_bonus(act));
+    let enemy = portal_enemy(act);
+    let life = match member.demon_portal_health {
+        ..=0 => enemy.max_hp,
+        life => life,
+    };
+    let log = simulate_fight(&player, player.max_hp, &enemy, life);
+
+    let mut resp = ResponseBuilder::default();
+    add_fight(&mut resp, None, 0, &player, &enemy, &log);
+    resp.add_key("fightversion");
+    resp.add_val(1);
+
+    // A defeated enemy makes room for the next act, which starts with full
+    // life again
+    let (act, life) = match log.life_left[1] {
+        0 => (act + 1, 0),
+        life => (act, life),
+    };
+    sqlx::query!(
+        "UPDATE guild SET demon_portal_act = $1, demon_portal_health = $2
+        WHERE id = $3",
+        act,
+        life,
+        member.guild_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let now = now();
+    sqlx::query!(
+        "UPDATE guild_member
+        SET portal_fought = TRUE, portal_last_fought = $1
+        WHERE pid = $2",
+        now,
+        session.player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    poll(session, "", db, resp).await
+}
+
+/// The quest experience bonus in percent, that the completed raids of the
+/// characters guild grant
+pub(crate) async fn raid_xp_bonus(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<i64, ServerError> {
+    let raid = sqlx::query_scalar!(
+        "SELECT raid FROM guild_member
+        JOIN guild on guild.id = guild_member.guild_id
+        WHERE pid = $1",
+        pid
+    )
+    .fetch_optional(&mut *con)
+    .await?;
+    Ok(raid.unwrap_or_default())
+}
+
+/// The silver bonus in percent, that the treasure of the characters guild
+/// grants. Every level the members invested into the treasure adds one
+/// percent
+pub(crate) async fn treasure_silver_bonus(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<i64, ServerError> {
+    let treasure = sqlx::query_scalar!(
+        "SELECT coalesce(sum(gu.treasure), 0) as `treasure!: i64`
+        FROM guild_member own
+        JOIN guild_member gm on gm.guild_id = own.guild_id
+        JOIN guild_upgrade gu on gu.pid = gm.pid
+        WHERE own.pid = $1",
+        pid
+    )
+    .fetch_one(&mut *con)
+    .await?;
+    Ok(treasure)
+}
+
+/// Adds everything the client needs to know about the own guild to the
+/// response. Does nothing, if the character is not in a guild
+pub(crate) async fn add_guild_save(
+    resp: &mut ResponseBuilder,
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<(), ServerError> {
+    let Some(guild) = sqlx::query!(
+        "SELECT guild.id, guild.name, description, emblem, raid, raid_time,
+            guild.honor, pet_id, hydra_heads, hydra_current_life,
+            hydra_last_battle, hydra_last_full, demon_portal_act,
+            demon_portal_health,
+            (
+            SELECT count(*)
+            FROM guild AS x
+            WHERE x.world_id = guild.world_id
+              AND (x.honor > guild.honor
+                   OR (x.honor = guild.honor AND x.id <= guild.id))
+            ) as `rank!: i64`
+        FROM guild_member
+        JOIN guild on guild.id = guild_member.guild_id
+        WHERE pid = $1",
+        pid
+    )
+    .fetch_optional(&mut *con)
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let members = sqlx::query!(
+        "SELECT c.name, c.level, gm.rank, gm.last_active, gm.is_attacking,
+            gm.is_defending, gm.portal_fought, gm.portal_last_fought,
+            gu.treasure, gu.instructor, gu.petlvl
+        FROM guild_member gm
+        JOIN character c on c.pid = gm.pid
+        JOIN guild_upgrade gu on gu.pid = gm.pid
+        WHERE gm.guild_id = $1
+        ORDER BY gm.rank ASC, gm.joined ASC
+        LIMIT 50",
+        guild.id
+    )
+    .fetch_all(&mut *con)
+    .await?;
+
+    let mut save = vec![0; 495];
+    save[0] = guild.id;
+    save[3] = members.len() as i64;
+    let portal_enemy = portal_enemy(guild.demon_portal_act);
+    let portal_life = match guild.demon_portal_health {
+        ..=0 => 100,
+        life => life * 100 / portal_enemy.max_hp,
+    };
+    let portal_progress = guild.demon_portal_act - 1;
+    save[6] =
+        members.iter().map(|a| a.treasure).sum::<i64>() | portal_life << 16;
+    save[7] = members.iter().map(|a| a.instructor).sum::<i64>()
+        | portal_progress << 16;
+    save[8] = guild.raid;
+    save[13] = guild.honor;
+    for (offset, member) in members.iter().enumerate() {
+        save[64 + offset] = member.level;
+        save[114 + offset] = member.last_active;
+        if fought_today(member.portal_fought, member.portal_last_fought) {
+            save[164 + offset] = member.portal_last_fought;
+        }
+        save[214 + offset] = member.treasure;
+        save[264 + offset] = member.instructor;
+        save[314 + offset] = member.rank;
+        save[390 + offset] = member.petlvl;
+        save[445 + offset] =
+            member.is_defending as i64 + member.is_attacking as i64 * 10;
+    }
+    if let Some(pet_id) = guild.pet_id {
+        let heads = guild.hydra_heads.unwrap_or_default();
+        let hydra = hydra(heads);
+        save[377] = pet_id;
+        save[378] = guild_pet_max_lvl(heads);
+        save[381] = guild.hydra_last_full;
+        save[382] = guild.hydra_last_battle;
+        save[383] = match guild.hydra_current_life {
+            ..=0 => hydra.max_hp,
+            life => life,
+        };
+        save[384] = hydra.max_hp;
+        save[385..390].copy_from_slice(&hydra.attributes);
+    }
+    if guild.raid_time != 0 {
+        save[364] = RAID_OPPONENT_ID;
+        save[365] = guild.raid_time;
+    }
+
+    resp.add_key("owngroupname.r");
+    resp.add_str(&guild.name);
+
+    resp.add_key("owngrouprank");
+    resp.add_val(guild.rank);
+
+    resp.add_key("owngroupdescription.s");
+    resp.add_str(&format!(
+        "{}§{}",
+        guild.emblem,
+        to_sf_string(&guild.description)
+    ));
+
+    resp.add_key("owngroupmember.r");
+    let names: Vec<_> = members.iter().map(|a| a.name.as_str()).collect();
+    resp.add_str(&names.join(","));
+
+    resp.add_key("owngroupsave.groupSave");
+    for val in save {
+        resp.add_val(val);
+    }
+
+    Ok(())
+}
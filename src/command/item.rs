@@ -1,8 +1,21 @@
+use fastrand::Rng;
 use num_derive::FromPrimitive;
 use serde::{Deserialize, Serialize};
 use sf_api::gamestate::items::Enchantment;
+use sqlx::{Sqlite, SqliteConnection};
 
-use super::ResponseBuilder;
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse,
+    blacksmith::{BlacksmithAction, dismantle_item, upgrade_item},
+    dungeon::use_dungeon_key,
+    fight::main_attribute_idx,
+    pets::store_fruit,
+    poll,
+    potion::drink_potion,
+    scrapbook::collect_item,
+    underworld::unlock_underworld,
+};
+use crate::request::Session;
 
 #[derive(Debug, FromPrimitive, Clone, Copy, Serialize, Deserialize)]
 pub enum RawItemTyp {
@@ -214,3 +227,808 @@ pub fn add_debug_item(resp: &mut ResponseBuilder, name: impl AsRef<str>) {
     resp.add_val(item.silver as i64);
     resp.add_val(item.mushrooms as i64 | (item.gem_pwr as i64) << 16);
 }
+
+/// The item type of dungeon keys and other miscellaneous items
+const MISC_ITEM_TYPE: i64 = 11;
+/// The ident of the key of the first light dungeon
+pub(crate) const LIGHT_KEY_IDENT: i64 = 1;
+/// The ident of the key of the first shadow dungeon
+pub(crate) const SHADOW_KEY_IDENT: i64 = 51;
+/// The item type of potions
+const POTION_ITEM_TYPE: i64 = 12;
+/// The item type of gems
+pub(crate) const GEM_ITEM_TYPE: i64 = 15;
+/// The item type of pet items. Fruits are the only ones
+const PET_ITEM_TYPE: i64 = 16;
+/// The item type of the heart of darkness, that unlocks the underworld
+const HEART_OF_DARKNESS_ITEM_TYPE: i64 = 18;
+/// The `gem_type` of an item, that has an empty gem slot. Items without a
+/// gem slot have 0 and items with a socketed gem have the `GemValue` of it
+const EMPTY_GEM_SLOT: i64 = 1;
+/// The chance in percent, that a generated item has an empty gem slot
+const GEM_SLOT_CHANCE: i64 = 10;
+/// The block chance in percent of generated shields
+const SHIELD_BLOCK_CHANCE: i64 = 25;
+/// The amount of models generated items are chosen from. Every type has at
+/// least this many
+const RANDOM_ITEM_MODELS: i64 = 10;
+/// The silver a generated item sells for per level
+const RANDOM_ITEM_SILVER: i64 = 25;
+/// The attribute type of constitution on items
+const CONSTITUTION_ATTRIBUTE: i64 = 4;
+
+/// An item as it is stored in the item table
+#[derive(Debug, Default)]
+pub(crate) struct DbItem {
+    pub enchantment: i64,
+    pub item_type: i64,
+    pub effect1: i64,
+    pub effect2: i64,
+    pub ident: i64,
+    pub count: i64,
+    pub expires: Option<i64>,
+    pub gem_type: i64,
+    pub gem_power: i64,
+    pub class: i64,
+    pub atr_typ1: i64,
+    pub atr_val1: i64,
+    pub atr_typ2: i64,
+    pub atr_val2: i64,
+    pub atr_typ3: i64,
+    pub atr_val3: i64,
+    pub model_id: i64,
+    pub silver: i64,
+    pub mushrooms: i64,
+}
+
+impl DbItem {
+    /// A gem, that can be socketed into an item. `value` is the `GemValue`
+    pub(crate) fn gem(value: i64, power: i64) -> DbItem {
+        DbItem {
+            item_type: GEM_ITEM_TYPE,
+            ident: value,
+            gem_power: power,
+            silver: power,
+            ..Default::default()
+        }
+    }
+
+    /// A fruit for the pets of a habitat
+    pub(crate) fn fruit(ident: i64) -> DbItem {
+        DbItem {
+            item_type: PET_ITEM_TYPE,
+            ident,
+            ..Default::default()
+        }
+    }
+
+    /// A random equipment item of the level, that a character of the class
+    /// can wear
+    pub(crate) fn random_equipment(
+        rng: &mut Rng,
+        level: i64,
+        class: i64,
+    ) -> DbItem {
+        let (weapon_class, armor_class) = item_classes(class).unwrap_or((1, 1));
+        let mut types = vec![1, 3, 4, 5, 6, 7, 8, 9, 10];
+        if matches!(class, 1 | 11) {
+            types.push(2);
+        }
+        let item_type = types[rng.usize(..types.len())];
+        let (item_class, effect1, effect2) = match item_type {
+            // Min and max damage
+            1 => (weapon_class, level * 2, level * 4),
+            // Block chance
+            2 => (armor_class, SHIELD_BLOCK_CHANCE, 0),
+            3..=7 => (armor_class, level * 3, 0),
+            _ => (0, 0, 0),
+        };
+        let main = main_attribute_idx(class) as i64 + 1;
+        DbItem {
+            item_type,
+            class: item_class,
+            model_id: rng.i64(1..=RANDOM_ITEM_MODELS),
+            effect1,
+            effect2,
+            atr_typ1: main,
+            atr_val1: level / 2 + 1 + rng.i64(0..=level / 4),
+            atr_typ2: CONSTITUTION_ATTRIBUTE,
+            atr_val2: level / 2 + 1 + rng.i64(0..=level / 4),
+            silver: level * RANDOM_ITEM_SILVER,
+            gem_type: match rng.i64(0..100) < GEM_SLOT_CHANCE {
+                true => EMPTY_GEM_SLOT,
+                false => 0,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// A potion with the ident, that encodes its type and size
+    pub(crate) fn potion(ident: i64) -> DbItem {
+        DbItem {
+            item_type: POTION_ITEM_TYPE,
+            ident,
+            ..Default::default()
+        }
+    }
+
+    /// The key, that unlocks the 0 based dungeon
+    pub(crate) fn dungeon_key(dungeon: i64, is_shadow: bool) -> DbItem {
+        let first = if is_shadow {
+            SHADOW_KEY_IDENT
+        } else {
+            LIGHT_KEY_IDENT
+        };
+        DbItem {
+            item_type: MISC_ITEM_TYPE,
+            ident: first + dungeon,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn heart_of_darkness() -> DbItem {
+        DbItem {
+            item_type: HEART_OF_DARKNESS_ITEM_TYPE,
+            ..Default::default()
+        }
+    }
+
+    /// Writes the 12 values the client uses to describe an item
+    pub(crate) fn write(&self, resp: &mut ResponseBuilder) {
+        resp.add_val(
+            self.item_type | self.gem_type << 16 | self.enchantment << 24,
+        );
+        resp.add_val(self.ident + self.class * 1000 + self.model_id);
+        resp.add_val(self.effect1);
+        resp.add_val(self.effect2);
+
+        let mut atrs = [
+            self.atr_typ1, self.atr_typ2, self.atr_typ3, self.atr_val1,
+            self.atr_val2, self.atr_val3,
+        ];
+        if let Some(expires) = self.expires {
+            atrs = [expires, 0, 0, 0, 0, 0];
+        } else if self.count > 0 {
+            atrs = [0, 0, 0, self.count, 0, 0];
+        }
+        for atr in atrs {
+            resp.add_val(atr);
+        }
+
+        resp.add_val(self.silver);
+        resp.add_val(self.mushrooms | self.gem_power << 16);
+    }
+
+    /// The attributes this item adds to the character, in the order
+    /// strength, dexterity, intelligence, constitution, luck
+    pub(crate) fn attributes(&self) -> [i64; 5] {
+        let mut res = [0; 5];
+        if self.expires.is_some() || self.count > 0 {
+            return res;
+        }
+        for (typ, val) in [
+            (self.atr_typ1, self.atr_val1),
+            (self.atr_typ2, self.atr_val2),
+            (self.atr_typ3, self.atr_val3),
+        ] {
+            let affected: &[usize] = match typ {
+                1..=5 => &[0, 1, 2, 3, 4][typ as usize - 1..typ as usize],
+                6 => &[0, 1, 2, 3, 4],
+                21 => &[0, 3, 4],
+                22 => &[1, 3, 4],
+                23 => &[2, 3, 4],
+                _ => &[],
+            };
+            for idx in affected {
+                res[*idx] += val;
+            }
+        }
+        // The last digit of the gem value is the attribute, 5 means all
+        if self.gem_type >= 10 {
+            let affected: &[usize] = match self.gem_type % 10 {
+                idx @ 0..=4 => &[0, 1, 2, 3, 4][idx as usize..=idx as usize],
+                5 => &[0, 1, 2, 3, 4],
+                _ => &[],
+            };
+            for idx in affected {
+                res[*idx] += self.gem_power;
+            }
+        }
+        res
+    }
+}
+
+/// Writes an empty item slot
+pub(crate) fn add_empty_item(resp: &mut ResponseBuilder) {
+    for _ in 0..12 {
+        resp.add_val(0);
+    }
+}
+
+pub(crate) async fn load_item(
+    con: &mut SqliteConnection,
+    id: i64,
+) -> Result<Option<DbItem>, ServerError> {
+    Ok(sqlx::query_as!(
+        DbItem,
+        "SELECT enchantment, item_type, effect1, effect2, ident, count,
+                expires, gem_type, gem_power, class, atr_typ1, atr_val1,
+                atr_typ2, atr_val2, atr_typ3, atr_val3, model_id, silver,
+                mushrooms
+            FROM item WHERE id = $1",
+        id
+    )
+    .fetch_optional(&mut *con)
+    .await?)
+}
+
+/// Creates a new item and returns its id
+pub(crate) async fn insert_item(
+    con: &mut SqliteConnection,
+    item: &DbItem,
+) -> Result<i64, ServerError> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO item (enchantment, item_type, effect1, effect2, ident,
+            count, expires, gem_type, gem_power, class, atr_typ1, atr_val1,
+            atr_typ2, atr_val2, atr_typ3, atr_val3, model_id, silver,
+            mushrooms)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14,
+            $15, $16, $17, $18, $19)
+        RETURNING id",
+        item.enchantment,
+        item.item_type,
+        item.effect1,
+        item.effect2,
+        item.ident,
+        item.count,
+        item.expires,
+        item.gem_type,
+        item.gem_power,
+        item.class,
+        item.atr_typ1,
+        item.atr_val1,
+        item.atr_typ2,
+        item.atr_val2,
+        item.atr_typ3,
+        item.atr_val3,
+        item.model_id,
+        item.silver,
+        item.mushrooms
+    )
+    .fetch_one(&mut *con)
+    .await?)
+}
+
+/// Puts the item into the first free slot of the characters bag. Returns
+/// false, if the bag is full
+pub(crate) async fn add_to_bag(
+    con: &mut SqliteConnection,
+    pid: i64,
+    item_id: i64,
+) -> Result<bool, ServerError> {
+    // All expressions see the values before the update, so only the first
+    // free slot is filled
+    let res = sqlx::query!(
+        "UPDATE bag SET
+            pos1 = coalesce(pos1, $2),
+            pos2 = CASE WHEN pos1 IS NULL THEN pos2
+                ELSE coalesce(pos2, $2) END,
+            pos3 = CASE WHEN pos1 IS NULL OR pos2 IS NULL THEN pos3
+                ELSE coalesce(pos3, $2) END,
+            pos4 = CASE WHEN pos1 IS NULL OR pos2 IS NULL OR pos3 IS NULL
+                THEN pos4 ELSE coalesce(pos4, $2) END,
+            pos5 = CASE WHEN pos1 IS NULL OR pos2 IS NULL OR pos3 IS NULL
+                OR pos4 IS NULL THEN pos5 ELSE coalesce(pos5, $2) END
+        WHERE pid = $1 AND (pos1 IS NULL OR pos2 IS NULL OR pos3 IS NULL
+            OR pos4 IS NULL OR pos5 IS NULL)",
+        pid,
+        item_id
+    )
+    .execute(&mut *con)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Ok(false);
+    }
+    // Everything that ends up in the bag has been found by the character
+    if let Some(item) = load_item(con, item_id).await? {
+        collect_item(con, pid, &item).await?;
+    }
+    Ok(true)
+}
+
+/// The amount of slots a character or companion can equip items in
+pub(crate) const EQUIPMENT_SLOTS: usize = 10;
+/// The amount of slots in the bag
+const BAG_SLOTS: usize = 5;
+
+/// A place, that items can be moved between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ItemPlace {
+    /// The items equipped by the character in the order the client expects
+    /// them: hat, breastplate, gloves, footwear, amulet, belt, ring,
+    /// talisman, weapon, shield
+    Equipment,
+    Bag,
+    /// The equipment of the companion with the given class
+    Companion(i64),
+}
+
+impl ItemPlace {
+    /// Parses the inventory number the client uses in item moves
+    pub(crate) fn from_client(val: i64) -> Option<ItemPlace> {
+        Some(match val {
+            1 => ItemPlace::Equipment,
+            2 => ItemPlace::Bag,
+            101..=103 => ItemPlace::Companion(val - 100),
+            _ => return None,
+        })
+    }
+
+    fn is_equipment(self) -> bool {
+        !matches!(self, ItemPlace::Bag)
+    }
+}
+
+/// Loads the ids of the items in every slot of the place
+pub(crate) async fn load_item_ids(
+    con: &mut SqliteConnection,
+    pid: i64,
+    place: ItemPlace,
+) -> Result<Vec<Option<i64>>, ServerError> {
+    let ids = match place {
+        ItemPlace::Equipment => sqlx::query!(
+            "SELECT hat, breastplate, gloves, footwear, amulet, belt, ring,
+                talisman, weapon, shield
+            FROM equipment WHERE pid = $1",
+            pid
+        )
+        .fetch_optional(&mut *con)
+        .await?
+        .map(|a| {
+            vec![
+                a.hat, a.breastplate, a.gloves, a.footwear, a.amulet, a.belt,
+                a.ring, a.talisman, a.weapon, a.shield,
+            ]
+        }),
+        ItemPlace::Companion(class) => sqlx::query!(
+            "SELECT hat, breastplate, gloves, footwear, amulet, belt, ring,
+                talisman, weapon, shield
+            FROM companion WHERE pid = $1 AND class = $2",
+            pid,
+            class
+        )
+        .fetch_optional(&mut *con)
+        .await?
+        .map(|a| {
+            vec![
+                a.hat, a.breastplate, a.gloves, a.footwear, a.amulet, a.belt,
+                a.ring, a.talisman, a.weapon, a.shield,
+            ]
+        }),
+        ItemPlace::Bag => sqlx::query!(
+            "SELECT pos1, pos2, pos3, pos4, pos5 FROM bag WHERE pid = $1", pid
+        )
+        .fetch_optional(&mut *con)
+        .await?
+        .map(|a| vec![a.pos1, a.pos2, a.pos3, a.pos4, a.pos5]),
+    };
+    Ok(ids.unwrap_or_else(|| match place {
+        ItemPlace::Bag => vec![None; BAG_SLOTS],
+        _ => vec![None; EQUIPMENT_SLOTS],
+    }))
+}
+
+async fn store_item_ids(
+    con: &mut SqliteConnection,
+    pid: i64,
+    place: ItemPlace,
+    ids: &[Option<i64>],
+) -> Result<(), ServerError> {
+    match place {
+        ItemPlace::Equipment => {
+            sqlx::query!(
+                "UPDATE equipment
+                SET hat = $2, breastplate = $3, gloves = $4, footwear = $5,
+                    amulet = $6, belt = $7, ring = $8, talisman = $9,
+                    weapon = $10, shield = $11
+                WHERE pid = $1",
+                pid,
+                ids[0],
+                ids[1],
+                ids[2],
+                ids[3],
+                ids[4],
+                ids[5],
+                ids[6],
+                ids[7],
+                ids[8],
+                ids[9],
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+        ItemPlace::Companion(class) => {
+            sqlx::query!(
+                "UPDATE companion
+                SET hat = $3, breastplate = $4, gloves = $5, footwear = $6,
+                    amulet = $7, belt = $8, ring = $9, talisman = $10,
+                    weapon = $11, shield = $12
+                WHERE pid = $1 AND class = $2",
+                pid,
+                class,
+                ids[0],
+                ids[1],
+                ids[2],
+                ids[3],
+                ids[4],
+                ids[5],
+                ids[6],
+                ids[7],
+                ids[8],
+                ids[9],
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+        ItemPlace::Bag => {
+            sqlx::query!(
+                "UPDATE bag
+                SET pos1 = $2, pos2 = $3, pos3 = $4, pos4 = $5, pos5 = $6
+                WHERE pid = $1",
+                pid,
+                ids[0],
+                ids[1],
+                ids[2],
+                ids[3],
+                ids[4],
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Loads the items in every slot of the place
+pub(crate) async fn load_items(
+    con: &mut SqliteConnection,
+    pid: i64,
+    place: ItemPlace,
+) -> Result<Vec<Option<DbItem>>, ServerError> {
+    let mut res = Vec::new();
+    for id in load_item_ids(con, pid, place).await? {
+        res.push(match id {
+            Some(id) => load_item(con, id).await?,
+            None => None,
+        });
+    }
+    Ok(res)
+}
+
+/// Loads the equipped items of the character
+pub(crate) async fn load_equipment(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Vec<Option<DbItem>>, ServerError> {
+    load_items(con, pid, ItemPlace::Equipment).await
+}
+
+/// Writes all slots of the place
+pub(crate) fn add_items(resp: &mut ResponseBuilder, items: &[Option<DbItem>]) {
+    for item in items {
+        match item {
+            Some(item) => item.write(resp),
+            None => add_empty_item(resp),
+        }
+    }
+}
+
+/// The 1 based equipment slot an item of this type is equipped in
+pub(crate) fn equipment_slot(item_type: i64) -> Option<usize> {
+    Some(match item_type {
+        6 => 1,  // Hat
+        3 => 2,  // BreastPlate
+        5 => 3,  // Gloves
+        4 => 4,  // FootWear
+        8 => 5,  // Amulet
+        7 => 6,  // Belt
+        9 => 7,  // Ring
+        10 => 8, // Talisman
+        1 => 9,  // Weapon
+        2 => 10, // Shield
+        _ => return None,
+    })
+}
+
+/// The item class of the weapons and armor a character class can wear as
+/// (weapon class, armor class)
+fn item_classes(class: i64) -> Option<(i64, i64)> {
+    // Warrior = 1, Mage = 2, Scout = 3
+    Some(match class {
+        1 | 6 | 11 => (1, 1), // Warrior, Berserker, Paladin
+        2 | 10 => (2, 2),     // Mage, Necromancer
+        3 => (3, 3),          // Scout
+        4 => (1, 3),          // Assassin
+        5 => (1, 2),          // BattleMage
+        7 => (3, 1),          // DemonHunter
+        8 | 9 => (2, 3),      // Druid, Bard
+        _ => return None,
+    })
+}
+
+/// Checks, if a character (or companion) of the class can equip the item in
+/// the given 1 based slot
+fn can_equip(item: &DbItem, class: i64, slot: usize) -> bool {
+    if equipment_slot(item.item_type) != Some(slot) {
+        return false;
+    }
+    let is_weapon = slot == 9;
+    let is_shield = slot == 10;
+    if is_shield && !matches!(class, 1 | 11) {
+        return false;
+    }
+    if item.class == 0 {
+        return true;
+    }
+    let Some((weapon_class, armor_class)) = item_classes(class) else {
+        return false;
+    };
+    match is_weapon {
+        true => item.class == weapon_class,
+        false => item.class == armor_class,
+    }
+}
+
+pub(crate) async fn player_item_move(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let from = ItemPlace::from_client(args.get_int(0, "from")?)
+        .ok_or(ServerError::BadRequest)?;
+    let from_pos = args.get_int(1, "from pos")? - 1;
+    let to = args.get_int(2, "to")?;
+    let to_pos = args.get_int(3, "to pos")? - 1;
+
+    let mut tx = db.begin().await?;
+    // Moving an item onto a blacksmith action lets the blacksmith work on it
+    if let Some(action) = BlacksmithAction::from_client(to) {
+        use_blacksmith(&mut tx, session.player_id, from, from_pos, action)
+            .await?;
+        tx.commit().await?;
+        return poll(session, "", db, Default::default()).await;
+    }
+    let to = ItemPlace::from_client(to).ok_or(ServerError::BadRequest)?;
+    // Moving an item onto the character itself uses it
+    if to == ItemPlace::Equipment && to_pos == -1 {
+        use_item(&mut tx, session.player_id, from, from_pos).await?;
+        tx.commit().await?;
+        return poll(session, "", db, Default::default()).await;
+    }
+    let class = sqlx::query_scalar!(
+        "SELECT class FROM character WHERE pid = $1", session.player_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let mut from_ids = load_item_ids(&mut tx, session.player_id, from).await?;
+    let mut to_ids = match from == to {
+        true => from_ids.clone(),
+        false => load_item_ids(&mut tx, session.player_id, to).await?,
+    };
+    let (Ok(from_pos), Ok(to_pos)) =
+        (usize::try_from(from_pos), usize::try_from(to_pos))
+    else {
+        return Err(ServerError::BadRequest);
+    };
+    if from_pos >= from_ids.len() || to_pos >= to_ids.len() {
+        return Err(ServerError::BadRequest);
+    }
+    let item_id = from_ids[from_pos].ok_or(ServerError::BadRequest)?;
+    let swapped_id = to_ids[to_pos];
+
+    // Moving a gem onto an item with an empty gem slot sockets it
+    if let Some(target_id) = swapped_id
+        && socket_gem(&mut tx, item_id, target_id).await?
+    {
+        from_ids[from_pos] = None;
+        store_item_ids(&mut tx, session.player_id, from, &from_ids).await?;
+        sqlx::query!("DELETE FROM item WHERE id = $1", item_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return poll(session, "", db, Default::default()).await;
+    }
+
+    // Both items have to fit into the place they end up in
+    for (id, place, pos) in
+        [(Some(item_id), to, to_pos), (swapped_id, from, from_pos)]
+    {
+        let Some(id) = id else {
+            continue;
+        };
+        if !place.is_equipment() {
+            continue;
+        }
+        let class = match place {
+            ItemPlace::Companion(class) => class,
+            _ => class,
+        };
+        let item = load_item(&mut tx, id)
+            .await?
+            .ok_or(ServerError::BadRequest)?;
+        if !can_equip(&item, class, pos + 1) {
+            return Err(ServerError::BadRequest);
+        }
+    }
+
+    if from == to {
+        from_ids.swap(from_pos, to_pos);
+    } else {
+        from_ids[from_pos] = swapped_id;
+        to_ids[to_pos] = Some(item_id);
+        store_item_ids(&mut tx, session.player_id, to, &to_ids).await?;
+    }
+    store_item_ids(&mut tx, session.player_id, from, &from_ids).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Puts the gem into the empty gem slot of the target. Returns false, if the
+/// item is no gem, or the target has no empty gem slot
+async fn socket_gem(
+    con: &mut SqliteConnection,
+    gem_id: i64,
+    target_id: i64,
+) -> Result<bool, ServerError> {
+    let Some(gem) = load_item(con, gem_id).await? else {
+        return Ok(false);
+    };
+    if gem.item_type != GEM_ITEM_TYPE {
+        return Ok(false);
+    }
+    let res = sqlx::query!(
+        "UPDATE item SET gem_type = $2, gem_power = $3
+        WHERE id = $1 AND gem_type = $4",
+        target_id,
+        gem.ident,
+        gem.gem_power,
+        EMPTY_GEM_SLOT
+    )
+    .execute(&mut *con)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Removes the item at the position from the place and returns its id
+pub(crate) async fn take_item(
+    con: &mut SqliteConnection,
+    pid: i64,
+    place: ItemPlace,
+    pos: i64,
+) -> Result<i64, ServerError> {
+    let mut ids = load_item_ids(con, pid, place).await?;
+    let item_id = usize::try_from(pos)
+        .ok()
+        .and_then(|pos| ids.get_mut(pos))
+        .and_then(|slot| slot.take())
+        .ok_or(ServerError::BadRequest)?;
+    store_item_ids(con, pid, place, &ids).await?;
+    Ok(item_id)
+}
+
+/// Upgrades or dismantles the item at the position
+async fn use_blacksmith(
+    con: &mut SqliteConnection,
+    pid: i64,
+    place: ItemPlace,
+    pos: i64,
+    action: BlacksmithAction,
+) -> Result<(), ServerError> {
+    match action {
+        BlacksmithAction::Upgrade => {
+            let ids = load_item_ids(con, pid, place).await?;
+            let item_id = usize::try_from(pos)
+                .ok()
+                .and_then(|pos| ids.get(pos).copied().flatten())
+                .ok_or(ServerError::BadRequest)?;
+            upgrade_item(con, pid, item_id).await?;
+        }
+        BlacksmithAction::Dismantle => {
+            let item_id = take_item(con, pid, place, pos).await?;
+            dismantle_item(con, pid, item_id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Uses up the item at the position
+async fn use_item(
+    con: &mut SqliteConnection,
+    pid: i64,
+    place: ItemPlace,
+    pos: i64,
+) -> Result<(), ServerError> {
+    let mut ids = load_item_ids(con, pid, place).await?;
+    let item_id = usize::try_from(pos)
+        .ok()
+        .and_then(|pos| ids.get_mut(pos))
+        .and_then(|slot| slot.take())
+        .ok_or(ServerError::BadRequest)?;
+    let item = load_item(con, item_id)
+        .await?
+        .ok_or(ServerError::BadRequest)?;
+
+    match item.item_type {
+        HEART_OF_DARKNESS_ITEM_TYPE => {
+            if !unlock_underworld(con, pid).await? {
+                return Err(ServerError::BadRequest);
+            }
+        }
+        MISC_ITEM_TYPE => {
+            if !use_dungeon_key(con, pid, item.ident).await? {
+                return Err(ServerError::BadRequest);
+            }
+        }
+        PET_ITEM_TYPE => {
+            if !store_fruit(con, pid, item.ident).await? {
+                return Err(ServerError::BadRequest);
+            }
+        }
+        POTION_ITEM_TYPE => {
+            if !drink_potion(con, pid, item.ident).await? {
+                return Err(ServerError::BadRequest);
+            }
+        }
+        _ => return Err(ServerError::BadRequest),
+    }
+
+    store_item_ids(con, pid, place, &ids).await?;
+    sqlx::query!("DELETE FROM item WHERE id = $1", item_id)
+        .execute(&mut *con)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_packs_the_type() {
+        let item = DbItem {
+            item_type: 1,
+            gem_type: 2,
+            enchantment: Enchantment::SwordOfVengeance as i64,
+            ident: 5,
+            class: 2,
+            silver: 100,
+            mushrooms: 3,
+            gem_power: 4,
+            ..Default::default()
+        };
+        let mut resp = ResponseBuilder::default();
+        resp.add_key("item");
+        item.write(&mut resp);
+        let vals = resp.values("item");
+        assert_eq!(vals.len(), 12);
+        let typ = 1 | 2 << 16 | (Enchantment::SwordOfVengeance as i64) << 24;
+        assert_eq!(vals[0], typ.to_string());
+        assert_eq!(vals[1], "2005");
+        assert_eq!(vals[10], "100");
+        assert_eq!(vals[11], (3 | 4 << 16).to_string());
+    }
+
+    #[test]
+    fn write_shows_the_count() {
+        let item = DbItem {
+            count: 7,
+            ..Default::default()
+        };
+        let mut resp = ResponseBuilder::default();
+        resp.add_key("item");
+        item.write(&mut resp);
+        assert_eq!(resp.values("item")[4..10], ["0", "0", "0", "7", "0", "0"]);
+    }
+}
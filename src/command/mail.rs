@@ -0,0 +1,494 @@
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use sf_api::misc::{from_sf_string, to_sf_string};
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse, add_xp,
+    in_seconds,
+    item::{add_empty_item, add_to_bag, load_item},
+    now, poll,
+};
+use crate::request::Session;
+
+/// The amount of mails a character can have in the inbox
+pub(crate) const INBOX_CAPACITY: i64 = 100;
+/// How long the attachments of a mail can be claimed
+const CLAIM_DURATION: i64 = 14 * 24 * 60 * 60;
+/// The name shown as the sender of mails from the game itself
+const SYSTEM_SENDER: &str = "Admin";
+/// The claimable type the client shows for rewards sent by the game
+const CLAIMABLE_GENERIC_DELIVERY: i64 = 13;
+
+pub(crate) const MAIL_NORMAL: i64 = 0;
+pub(crate) const MAIL_GUILD_INVITE: i64 = 5;
+
+/// The resources, that can be attached to a mail. The values are the reward
+/// types the client knows
+#[derive(Debug, Clone, Copy, FromPrimitive)]
+pub(crate) enum RewardTyp {
+    Mushrooms = 3,
+    Silver = 4,
+    LuckyCoins = 5,
+    XP = 24,
+    QuicksandGlass = 26,
+    Honor = 27,
+}
+
+/// Gives the character the resource
+pub(crate) async fn give_reward(
+    con: &mut SqliteConnection,
+    pid: i64,
+    typ: RewardTyp,
+    amount: i64,
+) -> Result<(), ServerError> {
+    match typ {
+        RewardTyp::Mushrooms => {
+            sqlx::query!(
+                "UPDATE character SET mushrooms = mushrooms + $1
+                WHERE pid = $2",
+                amount, pid
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+        RewardTyp::Silver => {
+            sqlx::query!(
+                "UPDATE character SET silver = silver + $1 WHERE pid = $2",
+                amount, pid
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+        RewardTyp::LuckyCoins => {
+            sqlx::query!(
+                "UPDATE tavern SET lucky_coins = lucky_coins + $1
+                WHERE pid = $2",
+                amount,
+                pid
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+        RewardTyp::XP => add_xp(con, pid, amount).await?,
+        RewardTyp::QuicksandGlass => {
+            sqlx::query!(
+                "UPDATE tavern SET quicksand = quicksand + $1 WHERE pid = $2",
+                amount, pid
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+        RewardTyp::Honor => {
+            sqlx::query!(
+                "UPDATE character SET honor = honor + $1 WHERE pid = $2",
+                amount, pid
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends a mail from the game to the character. These are delivered even if
+/// the inbox is full
+pub(crate) async fn send_system_mail(
+    con: &mut SqliteConnection,
+    receiver: i64,
+    typ: i64,
+    title: &str,
+    body: &str,
+) -> Result<i64, ServerError> {
+    let now = now();
+    let id = sqlx::query_scalar!(
+        "INSERT INTO mail (receiver, sender_name, typ, title, body, sent)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id",
+        receiver,
+        SYSTEM_SENDER,
+        typ,
+        title,
+        body,
+        now
+    )
+    .fetch_one(&mut *con)
+    .await?;
+    Ok(id)
+}
+
+/// Sends a mail with claimable resources and items to the character. The
+/// items have to already exist in the item table
+pub(crate) async fn send_reward_mail(
+    con: &mut SqliteConnection,
+    receiver: i64,
+    title: &str,
+    resources: &[(RewardTyp, i64)],
+    items: &[i64],
+) -> Result<(), ServerError> {
+    let mail_id =
+        send_system_mail(con, receiver, MAIL_NORMAL, title, "").await?;
+    let claimable_until = in_seconds(CLAIM_DURATION);
+    sqlx::query!(
+        "UPDATE mail SET claimable_until = $1 WHERE id = $2", claimable_until,
+        mail_id
+    )
+    .execute(&mut *con)
+    .await?;
+
+    for (typ, amount) in resources {
+        let typ = *typ as i64;
+        sqlx::query!(
+            "INSERT INTO mail_attachment (mail_id, resource_typ, amount)
+            VALUES ($1, $2, $3)",
+            mail_id,
+            typ,
+            amount
+        )
+        .execute(&mut *con)
+        .await?;
+    }
+    for item_id in items {
+        sqlx::query!(
+            "INSERT INTO mail_attachment (mail_id, item_id) VALUES ($1, $2)",
+            mail_id, item_id
+        )
+        .execute(&mut *con)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Adds the inbox and the claimable rewards of the character
+pub(crate) async fn add_mailbox(
+    resp: &mut ResponseBuilder,
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<(), ServerError> {
+    // Rewards, that were not claimed in time, are gone together with their
+    // items
+    let now = now();
+    sqlx::query!(
+        "DELETE FROM item WHERE id IN (
+            SELECT a.item_id FROM mail_attachment a
+            JOIN mail m on m.id = a.mail_id
+            WHERE m.receiver = $1 AND NOT m.claimed AND m.claimable_until <= $2
+        )",
+        pid,
+        now
+    )
+    .execute(&mut *con)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM mail WHERE receiver = $1 AND claimable_until <= $2", pid,
+        now
+    )
+    .execute(&mut *con)
+    .await?;
+
+    let mails = sqlx::query!(
+        "SELECT id, sender_name, typ, title, sent, is_read
+        FROM mail
+        WHERE receiver = $1 AND claimable_until IS NULL
+        ORDER BY id DESC
+        LIMIT $2",
+        pid,
+        INBOX_CAPACITY
+    )
+    .fetch_all(&mut *con)
+    .await?;
+
+    let mut list = String::new();
+    for mail in mails {
+        // The client recognizes special mails by their type as the title
+        let title = match mail.typ {
+            MAIL_NORMAL => to_sf_string(&mail.title),
+            typ => typ.to_string(),
+        };
+        list.push_str(&format!(
+            "{},{},{},{title},{};",
+            mail.id, mail.sender_name, mail.is_read as i64, mail.sent
+        ));
+    }
+    if list.is_empty() {
+        list.push(';');
+    }
+    resp.add_key("messagelist.r");
+    resp.add_str(&list);
+
+    resp.add_key("inboxcapacity");
+    resp.add_val(INBOX_CAPACITY);
+
+    let rewards = sqlx::query!(
+        "SELECT id, title, sent, is_read, claimed,
+            claimable_until as `claimable_until!`
+        FROM mail
+        WHERE receiver = $1 AND claimable_until > $2
+        ORDER BY id DESC",
+        pid,
+        now
+    )
+    .fetch_all(&mut *con)
+    .await?;
+
+    if !rewards.is_empty() {
+        resp.add_key("pendingrewards");
+        for reward in rewards {
+            let status = match (reward.claimed, reward.is_read) {
+                (true, _) => 2,
+                (false, true) => 1,
+                (false, false) => 0,
+            };
+            resp.add_val(reward.id);
+            resp.add_val(status);
+            resp.add_val(CLAIMABLE_GENERIC_DELIVERY);
+            resp.add_str(&to_sf_string(&reward.title));
+            resp.add_val(reward.sent);
+            resp.add_val(reward.claimable_until);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn player_message_send(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let to = args.get_str(0, "receiver")?;
+    let message = from_sf_string(args.get_str(1, "message")?);
+    let message = message.trim();
+    if message.is_empty() {
+        return Err(ServerError::BadRequest);
+    }
+    // The first line is used as the title
+    let (title, body) = message.split_once('\n').unwrap_or((message, ""));
+
+    let mut tx = db.begin().await?;
+    let sender = sqlx::query!(
+        "SELECT name, world_id FROM character WHERE pid = $1",
+        session.player_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let receiver = sqlx::query_scalar!(
+        "SELECT pid FROM character
+        WHERE lower(name) = lower($1) AND world_id = $2",
+        to,
+        sender.world_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    let mail_count = sqlx::query_scalar!(
+        "SELECT count(*) FROM mail
+        WHERE receiver = $1 AND claimable_until IS NULL",
+        receiver
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if mail_count >= INBOX_CAPACITY {
+        return Err(ServerError::InboxFull);
+    }
+
+    // Mails of ignored characters are silently dropped
+    let ignored = sqlx::query_scalar!(
+        "SELECT count(*) FROM ignored_player WHERE pid = $1 AND ignored = $2",
+        receiver, session.player_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if ignored == 0 {
+        let now = now();
+        sqlx::query!(
+            "INSERT INTO mail
+            (receiver, sender, sender_name, typ, title, body, sent)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            receiver,
+            session.player_id,
+            sender.name,
+            MAIL_NORMAL,
+            title,
+            body,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+pub(crate) async fn player_message_view(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let pos = args.get_int(0, "msg pos")?;
+    if pos < 1 {
+        return Err(ServerError::BadRequest);
+    }
+    let offset = pos - 1;
+
+    let mut tx = db.begin().await?;
+    let mail = sqlx::query!(
+        "SELECT id, title, body FROM mail
+        WHERE receiver = $1 AND claimable_until IS NULL
+        ORDER BY id DESC
+        LIMIT 1 OFFSET $2",
+        session.player_id,
+        offset
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+
+    sqlx::query!("UPDATE mail SET is_read = TRUE WHERE id = $1", mail.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    let mut resp = ResponseBuilder::default();
+    resp.add_key("messagetext.s");
+    let text = match mail.body.is_empty() {
+        true => mail.title,
+        false => format!("{}\n{}", mail.title, mail.body),
+    };
+    resp.add_str(&to_sf_string(&text));
+    poll(session, "", db, resp).await
+}
+
+pub(crate) async fn player_message_delete(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let pos = args.get_int(0, "msg pos")?;
+    match pos {
+        -1 => {
+            sqlx::query!(
+                "DELETE FROM mail
+                WHERE receiver = $1 AND claimable_until IS NULL",
+                session.player_id
+            )
+            .execute(db)
+            .await?;
+        }
+        1.. => {
+            let offset = pos - 1;
+            sqlx::query!(
+                "DELETE FROM mail WHERE id = (
+                    SELECT id FROM mail
+                    WHERE receiver = $1 AND claimable_until IS NULL
+                    ORDER BY id DESC
+                    LIMIT 1 OFFSET $2
+                )",
+                session.player_id,
+                offset
+            )
+            .execute(db)
+            .await?;
+        }
+        _ => return Err(ServerError::BadRequest),
+    }
+    poll(session, "", db, Default::default()).await
+}
+
+pub(crate) async fn pending_reward_view(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let msg_id = args.get_int(0, "msg_id")?;
+    let mut tx = db.begin().await?;
+
+    let res = sqlx::query!(
+        "UPDATE mail SET is_read = TRUE
+        WHERE id = $1 AND receiver = $2 AND claimable_until IS NOT NULL",
+        msg_id,
+        session.player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(ServerError::BadRequest);
+    }
+
+    let attachments = sqlx::query!(
+        "SELECT resource_typ, amount, item_id FROM mail_attachment
+        WHERE mail_id = $1
+        ORDER BY id",
+        msg_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut resp = ResponseBuilder::default();
+    resp.add_key("pendingrewardressources");
+    for attachment in attachments.iter().filter(|a| a.item_id.is_none()) {
+        resp.add_val(attachment.resource_typ);
+        resp.add_val(attachment.amount);
+    }
+
+    resp.add_key("pendingreward.item(0)");
+    for item_id in attachments.iter().filter_map(|a| a.item_id) {
+        match load_item(&mut tx, item_id).await? {
+            Some(item) => item.write(&mut resp),
+            None => add_empty_item(&mut resp),
+        }
+    }
+    tx.commit().await?;
+
+    resp.build()
+}
+
+pub(crate) async fn pending_reward_claim(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let msg_id = args.get_int(0, "msg_id")?;
+    let mut tx = db.begin().await?;
+
+    let now = now();
+    let res = sqlx::query!(
+        "UPDATE mail SET claimed = TRUE, is_read = TRUE
+        WHERE id = $1 AND receiver = $2 AND NOT claimed
+          AND claimable_until > $3",
+        msg_id,
+        session.player_id,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(ServerError::BadRequest);
+    }
+
+    let attachments = sqlx::query!(
+        "SELECT resource_typ, amount, item_id FROM mail_attachment
+        WHERE mail_id = $1",
+        msg_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for attachment in attachments {
+        if let Some(item_id) = attachment.item_id {
+            if !add_to_bag(&mut tx, session.player_id, item_id).await? {
+                return Err(ServerError::InventoryFull);
+            }
+            continue;
+        }
+        let typ = RewardTyp::from_i64(attachment.resource_typ)
+            .ok_or(ServerError::Internal)?;
+        give_reward(&mut tx, session.player_id, typ, attachment.amount).await?;
+    }
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
@@ -1,20 +1,68 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use account::{account_check, account_create, account_delete, account_login};
-use guild::group_get_hof;
+use calendar::player_open_calender;
+use chat::{chat_poll, group_chat};
+use dungeon::{player_dungeon_battle, player_shadow_battle};
+use fortress::{
+    fortress_attack, fortress_build_finished, fortress_build_start,
+    fortress_build_stop, fortress_build_unit_start, fortress_enemy,
+    fortress_gather, fortress_gem_stone_finished, fortress_gem_stone_start,
+    fortress_gem_stone_stop, fortress_get_hof,
+};
+use friend::player_friend_set;
+use guild::{
+    group_get_hof, group_invite_member, group_pet_battle, group_portal_battle,
+    group_raid_declare, group_ready_attack, group_set_pet,
+};
+use item::player_item_move;
 use log::{debug, error, warn};
+use mail::{
+    pending_reward_claim, pending_reward_view, player_message_delete,
+    player_message_send, player_message_view,
+};
+use pets::{
+    pets_dungeon_fight, pets_get_hof, pets_get_stats, pets_pvp_fight,
+    player_pet_feed,
+};
 use player::*;
-use sqlx::Sqlite;
+use potion::player_potion_kill;
+use sqlx::{Sqlite, SqliteConnection};
+use task::daily_task_claim;
+use tower::player_tower_battle;
+use underworld::{
+    underworld_attack, underworld_build_finished, underworld_build_start,
+    underworld_build_stop, underworld_gather, underworld_get_hof,
+    underworld_upgrade_unit,
+};
 use update::poll;
+use wheel::wheel_of_fortune;
+use witch::{player_witch_enchant_item, player_witch_spend_item};
 
 use crate::{SERVER_VERSION, request::Session, response::*};
 
 mod account;
+mod blacksmith;
+mod calendar;
+mod chat;
 mod debug;
+mod dungeon;
+mod fight;
+mod fortress;
+mod friend;
 mod guild;
 mod item;
+mod mail;
+mod pets;
 mod player;
+mod potion;
+mod scrapbook;
+mod task;
+mod tower;
+mod underworld;
 mod update;
+mod wheel;
+mod witch;
 
 #[derive(Debug)]
 pub struct CommandArguments<'a>(pub Vec<&'a str>);
@@ -66,24 +114,95 @@ pub(crate) async fn handle_command<'a>(
         "AccountDelete" => account_delete(session, db, args).await,
         "AccountLogin" => account_login(session, db, args).await,
         "AccountSetLanguage" => Ok(ServerResponse::Success), // TODO:
+        "DailyTaskClaim" => daily_task_claim(session, db, args).await,
+        "FortressAttack" => fortress_attack(session, db, args).await,
+        "FortressBuildFinished" => {
+            fortress_build_finished(session, db, args).await
+        }
+        "FortressBuildStart" => fortress_build_start(session, db, args).await,
+        "FortressBuildStop" => fortress_build_stop(session, db, args).await,
+        "FortressBuildUnitStart" => {
+            fortress_build_unit_start(session, db, args).await
+        }
+        "FortressEnemy" => fortress_enemy(session, db, args).await,
+        "FortressGather" => fortress_gather(session, db, args).await,
+        "FortressGemStoneStop" => fortress_gem_stone_stop(session, db).await,
+        "FortressGemstoneFinished" => {
+            fortress_gem_stone_finished(session, db, args).await
+        }
+        "FortressGemstoneStart" => fortress_gem_stone_start(session, db).await,
+        "FortressGetHallOfFame" => fortress_get_hof(session, db, args).await,
+        "GroupChat" => group_chat(session, db, args).await,
         "GroupGetHallOfFame" => group_get_hof(session, db, args).await,
+        "GroupInviteMember" => group_invite_member(session, db, args).await,
+        "GroupRaidDeclare" => group_raid_declare(session, db).await,
+        "GroupReadyAttack" => group_ready_attack(session, db).await,
+        "GroupPortalBattle" => group_portal_battle(session, db).await,
+        "GroupPetBattle" => group_pet_battle(session, db, args).await,
+        "GroupSetPet" => group_set_pet(session, db, args).await,
+        "PendingRewardClaim" => pending_reward_claim(session, db, args).await,
         "PendingRewardView" => pending_reward_view(session, db, args).await,
+        "PetsDungeonFight" => pets_dungeon_fight(session, db, args).await,
+        "PetsGetHallOfFame" => pets_get_hof(session, db, args).await,
+        "PetsGetStats" => pets_get_stats(session, db, args).await,
+        "PetsPvPFight" => pets_pvp_fight(session, db, args).await,
         "PlayerAdventureFinished" => player_finish_quest(session, db).await,
         "PlayerAdventureStart" => player_start_quest(session, db, args).await,
         "PlayerArenaEnemy" => poll(session, "", db, Default::default()).await,
         "PlayerArenaFight" => player_arena_fight(session, db, args).await,
+        "PlayerBeerBuy" => player_beer_buy(session, db).await,
+        "PlayerDungeonBattle" => player_dungeon_battle(session, db, args).await,
+        "PlayerItemMove" => player_item_move(session, db, args).await,
         "PlayerLookAt" => player_look_at(session, db, args).await,
+        "PlayerFriendSet" => player_friend_set(session, db, args).await,
         "PlayerGambleGold" => player_gamble_gold(session, db, args).await,
         "PlayerGetHallOfFame" => player_get_hof(session, db, args).await,
         "PlayerHelpshiftAuthtoken" => player_helpshift_auth_token(),
+        "PlayerMessageDelete" => player_message_delete(session, db, args).await,
+        "PlayerMessageSend" => player_message_send(session, db, args).await,
+        "PlayerMessageView" => player_message_view(session, db, args).await,
         "PlayerMountBuy" => player_mount_buy(session, db, args).await,
-        "PlayerPollScrapbook" => Ok(ServerResponse::Success), // TODO:
+        "PlayerOpenCalender" => player_open_calender(session, db).await,
+        "PlayerPetFeed" => player_pet_feed(session, db, args).await,
+        "PlayerPotionKill" => player_potion_kill(session, db, args).await,
+        "PlayerPollScrapbook" => {
+            poll(session, "", db, Default::default()).await
+        }
         "PlayerSetDescription" => player_set_descr(session, db, args).await,
         "PlayerSetFace" => player_set_face(session, db, args).await,
+        "PlayerShadowBattle" => player_shadow_battle(session, db, args).await,
+        "PlayerTowerBattle" => player_tower_battle(session, db, args).await,
         "PlayerTutorialStatus" => player_tutorial(session, db, args).await,
-        "PlayerWhisper" => player_whisper(session, db, args).await,
-        "Poll" => poll(session, "poll", db, Default::default()).await,
+        "PlayerWorkFinished" => player_work_finish(session, db).await,
+        "PlayerWorkStart" => player_work_start(session, db, args).await,
+        "PlayerWorkStop" => player_work_stop(session, db).await,
+        "PlayerWitchEnchantItem" => {
+            player_witch_enchant_item(session, db, args).await
+        }
+        "PlayerWitchSpendItem" => {
+            player_witch_spend_item(session, db, args).await
+        }
+        "PlayerMessageWhisper" | "PlayerWhisper" => {
+            player_whisper(session, db, args).await
+        }
+        "Poll" => chat_poll(session, db, args).await,
+        "UnderworldAttack" => underworld_attack(session, db, args).await,
+        "UnderworldBuildFinished" => {
+            underworld_build_finished(session, db, args).await
+        }
+        "UnderworldBuildStart" => {
+            underworld_build_start(session, db, args).await
+        }
+        "UnderworldBuildStop" => underworld_build_stop(session, db, args).await,
+        "UnderworldGather" => underworld_gather(session, db, args).await,
+        "UnderworldGetHallOfFame" => {
+            underworld_get_hof(session, db, args).await
+        }
+        "UnderworldUpgradeUnit" => {
+            underworld_upgrade_unit(session, db, args).await
+        }
         "UserSettingsUpdate" => Ok(ServerResponse::Success), // TODO:
+        "WheelOfFortune" => wheel_of_fortune(session, db, args).await,
         "getserverversion" => get_server_version(session, db).await,
         _ => {
             error!("Unknown command: {name} - {args:?}");
@@ -92,24 +211,6 @@ pub(crate) async fn handle_command<'a>(
     }
 }
 
-async fn pending_reward_view(
-    _session: Session,
-    _db: &sqlx::Pool<Sqlite>,
-    args: CommandArguments<'_>,
-) -> Result<ServerResponse, ServerError> {
-    let _id = args.get_int(0, "msg_id")?;
-    let mut resp = ResponseBuilder::default();
-    resp.add_key("pendingrewardressources");
-    for v in 1..=6 {
-        let val = v;
-        resp.add_val(val);
-        resp.add_val(999);
-    }
-    resp.add_key("pendingreward.item(0)");
-
-    resp.build()
-}
-
 fn player_helpshift_auth_token() -> Result<ServerResponse, ServerError> {
     ResponseBuilder::default()
         .add_key("helpshiftauthtoken")
@@ -163,6 +264,11 @@ fn in_seconds(secs: i64) -> i64 {
     now() + secs
 }
 
+/// The timestamp at which the next (UTC) day starts
+fn next_day() -> i64 {
+    (now() / 86400 + 1) * 86400
+}
+
 #[allow(unused)]
 fn get_debug_value(name: &str) -> i64 {
     std::fs::read_to_string(format!("values/{name}.txt"))
@@ -219,6 +325,14 @@ impl Portrait {
     }
 }
 
+/// The mount, that only gets bought with mushrooms and grants additional
+/// silver on quests
+const MOUNT_DRAGON: i64 = 4;
+/// The additional quest silver in percent, that the dragon grants
+const DRAGON_GOLD_BONUS: i64 = 30;
+
+/// Clears the mount, if it has expired and returns the factor, that quest
+/// durations are multiplied with
 fn effective_mount(mount_end: &mut i64, mount: &mut i64) -> f32 {
     if *mount_end > 0 && (*mount_end < now() || *mount == 0) {
         *mount = 0;
@@ -234,6 +348,19 @@ fn effective_mount(mount_end: &mut i64, mount: &mut i64) -> f32 {
     }
 }
 
+/// The time a quest takes with the mount effect of `effective_mount`
+fn mounted_quest_length(length: i64, mount_effect: f32) -> i64 {
+    (length as f32 * mount_effect).ceil() as i64
+}
+
+/// The silver a quest rewards with the (already checked) mount
+fn mounted_quest_silver(silver: i64, mount: i64) -> i64 {
+    match mount {
+        MOUNT_DRAGON => silver * (100 + DRAGON_GOLD_BONUS) / 100,
+        _ => silver,
+    }
+}
+
 pub(crate) fn xp_for_next_level(level: i64) -> i64 {
     static LOOKUP: [i64; 392] = [
         400, 900, 1400, 1800, 2200, 2890, 3580, 4405, 5355, 6435, 7515, 8925,
@@ -301,3 +428,42 @@ pub(crate) fn xp_for_next_level(level: i64) -> i64 {
         .and_then(|idx: usize| LOOKUP.get(idx).copied())
         .unwrap_or(1500000000)
 }
+
+async fn character_level(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<i64, ServerError> {
+    Ok(
+        sqlx::query_scalar!("SELECT level FROM character WHERE pid = $1", pid)
+            .fetch_one(&mut *con)
+            .await?,
+    )
+}
+
+/// Gives the character experience and levels it up, if it has enough
+async fn add_xp(
+    con: &mut SqliteConnection,
+    pid: i64,
+    xp: i64,
+) -> Result<(), ServerError> {
+    let character = sqlx::query!(
+        "SELECT level, experience FROM character WHERE pid = $1", pid
+    )
+    .fetch_one(&mut *con)
+    .await?;
+
+    let mut level = character.level;
+    let mut experience = character.experience + xp;
+    while experience > xp_for_next_level(level) {
+        experience -= xp_for_next_level(level);
+        level += 1;
+    }
+
+    sqlx::query!(
+        "UPDATE character SET level = $1, experience = $2 WHERE pid = $3",
+        level, experience, pid
+    )
+    .execute(&mut *con)
+    .await?;
+    Ok(())
+}
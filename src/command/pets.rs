@@ -0,0 +1,739 @@
+use std::fmt::Write;
+
+use fastrand::Rng;
+use log::error;
+use sf_api::gamestate::unlockables::HabitatType;
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse,
+    fight::{Fighter, add_fight, simulate_fight, simulate_team_fight},
+    item::{DbItem, add_to_bag, insert_item},
+    mail::send_reward_mail,
+    next_day, now, poll,
+    task::{TaskType, progress_tasks},
+};
+use crate::request::Session;
+
+/// The highest level a pet can be fed to
+pub(crate) const MAX_PET_LEVEL: i64 = 100;
+/// The amount of habitats. Their order is Shadow, Light, Earth, Fire, Water
+pub(crate) const HABITAT_COUNT: usize = 5;
+/// The amount of pets, that live in every habitat
+const PETS_PER_HABITAT: usize = 20;
+/// The chance in percent to find the next pet of a habitat on its activity
+const PET_FIND_CHANCE: u32 = 5;
+/// The chance in percent to find a fruit on a quest, once pets are unlocked
+const FRUIT_DROP_CHANCE: u32 = 10;
+/// The amount of fruits a single pet can eat per day
+const FRUITS_PER_DAY: i64 = 10;
+/// The level of the first pet in every habitat, that has to be defeated to
+/// explore it
+const EXPLORATION_BASE_LEVEL: i64 = 10;
+/// The level every won exploration fight adds to the next one
+const EXPLORATION_LEVEL_STEP: i64 = 5;
+/// The mushrooms it costs to explore again before the next day
+const EXPLORATION_SKIP_PRICE: i64 = 1;
+/// The honor a pet collection starts with
+const BASE_HONOR: i64 = 100;
+/// The percentage of the honor of the loser, that the winner of a pet fight
+/// gains
+const HONOR_PERCENT: i64 = 10;
+/// The sum of pet levels in a habitat, that grant one percent of bonus to
+/// the attribute of the habitat
+const LEVELS_PER_BONUS: i64 = 100;
+/// Own pets fight with their id as the monster id. Wild pets and the pets of
+/// other characters are offset by this
+const ENEMY_PET_MONSTER_ID: i64 = 600;
+/// The ident of the fruit of the first habitat
+pub(crate) const FRUIT_IDENT: i64 = 31;
+/// The amount of values the client expects in the pet save
+const PETS_SAVE_LEN: usize = 255;
+/// The habitat, that boosts each attribute. Strength, dexterity,
+/// intelligence, constitution, luck
+const ATTRIBUTE_HABITATS: [usize; 5] = [4, 1, 2, 0, 3];
+
+#[derive(Debug, Default)]
+pub(crate) struct Pets {
+    pub honor: i64,
+    pub opponent: Option<i64>,
+    pub opponent_chosen: i64,
+    pub next_exploration: i64,
+    pub daily_reset: i64,
+    /// The level of every pet. 0 => not found yet
+    pub levels: [[i64; PETS_PER_HABITAT]; HABITAT_COUNT],
+    pub fruits_today: [[i64; PETS_PER_HABITAT]; HABITAT_COUNT],
+    pub habitats: [Habitat; HABITAT_COUNT],
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Habitat {
+    pub explored: i64,
+    pub fruits: i64,
+    pub battled: bool,
+}
+
+/// Splits the id of a pet (1..=100) into its habitat and position
+fn pet_position(pet_id: i64) -> Option<(usize, usize)> {
+    let idx = usize::try_from(pet_id - 1).ok()?;
+    let habitat = idx / PETS_PER_HABITAT;
+    (habitat < HABITAT_COUNT).then_some((habitat, idx % PETS_PER_HABITAT))
+}
+
+fn to_pet_id(habitat: usize, pos: usize) -> i64 {
+    (habitat * PETS_PER_HABITAT + pos) as i64 + 1
+}
+
+/// Builds a pet. The classes of the pets in a habitat alternate between
+/// warrior, scout and mage
+fn pet_fighter(monster_id: i64, pet_id: i64, level: i64) -> Fighter {
+    let class = [1, 3, 2][(pet_id - 1) as usize % 3];
+    Fighter::monster(monster_id, level, class)
+}
+
+impl Pets {
+    /// The total amount of pets found
+    pub(crate) fn collected(&self) -> i64 {
+        self.levels.iter().flatten().filter(|a| **a > 0).count() as i64
+    }
+
+    fn habitat_level(&self, habitat: usize) -> i64 {
+        self.levels[habitat].iter().sum()
+    }
+
+    /// The percentage the pets of the habitat increase its attribute by
+    pub(crate) fn habitat_bonus(&self, habitat: usize) -> i64 {
+        self.habitat_level(habitat) / LEVELS_PER_BONUS
+    }
+
+    /// The bonus in percent to strength, dexterity, intelligence,
+    /// constitution and luck
+    pub(crate) fn attribute_bonus(&self) -> [i64; 5] {
+        ATTRIBUTE_HABITATS.map(|habitat| self.habitat_bonus(habitat))
+    }
+
+    /// The level of the wild pet, that has to be defeated next to explore
+    /// the habitat
+    pub(crate) fn exploration_level(&self, habitat: usize) -> i64 {
+        EXPLORATION_BASE_LEVEL
+            + self.habitats[habitat].explored * EXPLORATION_LEVEL_STEP
+    }
+
+    /// The next pet of the habitat, that can be found. Pets can only be
+    /// found, once the habitat has been explored up to them
+    fn findable(&self, habitat: usize) -> Option<usize> {
+        let explored = self.habitats[habitat].explored as usize;
+        (0..PETS_PER_HABITAT)
+            .take_while(|pos| *pos <= explored)
+            .find(|pos| self.levels[habitat][*pos] == 0)
+    }
+
+    /// All found pets of the habitat, the strongest first
+    fn team(&self, habitat: usize, monster_offset: i64) -> Vec<Fighter> {
+        let mut team: Vec<_> = (0..PETS_PER_HABITAT)
+            .filter(|pos| self.levels[habitat][*pos] > 0)
+            .map(|pos| {
+                let id = to_pet_id(habitat, pos);
+                let level = self.levels[habitat][pos];
+                pet_fighter(monster_offset + id, id, level)
+            })
+            .collect();
+        team.sort_by_key(|a| -a.level);
+        team
+    }
+
+    /// The habitat the pets of the character defend in. This is the one
+    /// with the strongest pets
+    pub(crate) fn defense_habitat(&self) -> usize {
+        (0..HABITAT_COUNT)
+            .max_by_key(|habitat| self.habitat_level(*habitat))
+            .unwrap_or_default()
+    }
+
+    /// Resets the fruits eaten and the habitats fought, once a new day has
+    /// started
+    pub(crate) fn update(&mut self) {
+        if self.daily_reset > now() {
+            return;
+        }
+        self.fruits_today = Default::default();
+        for habitat in &mut self.habitats {
+            habitat.battled = false;
+        }
+        self.daily_reset = next_day();
+    }
+}
+
+/// Loads the pets of the character, if any have been found yet
+pub(crate) async fn load_pets(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Option<Pets>, ServerError> {
+    let Some(row) = sqlx::query!("SELECT * FROM pets WHERE pid = $1", pid)
+        .fetch_optional(&mut *con)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let mut pets = Pets {
+        honor: row.honor,
+        opponent: row.opponent,
+        opponent_chosen: row.opponent_chosen,
+        next_exploration: row.next_exploration,
+        daily_reset: row.daily_reset,
+        ..Default::default()
+    };
+
+    let pet_rows = sqlx::query!("SELECT * FROM pet WHERE pid = $1", pid)
+        .fetch_all(&mut *con)
+        .await?;
+    for row in pet_rows {
+        if let Some((habitat, pos)) = pet_position(row.pet_id) {
+            pets.levels[habitat][pos] = row.level;
+            pets.fruits_today[habitat][pos] = row.fruits_today;
+        }
+    }
+
+    let habitat_rows =
+        sqlx::query!("SELECT * FROM pet_habitat WHERE pid = $1", pid)
+            .fetch_all(&mut *con)
+            .await?;
+    for row in habitat_rows {
+        if let Some(habitat) = pets.habitats.get_mut(row.habitat as usize) {
+            *habitat = Habitat {
+                explored: row.explored,
+                fruits: row.fruits,
+                battled: row.battled,
+            };
+        }
+    }
+    Ok(Some(pets))
+}
+
+/// Saves the pets. This also creates the pet collection, if the character
+/// did not have any pets before
+async fn store_pets(
+    con: &mut SqliteConnection,
+    pid: i64,
+    pets: &Pets,
+) -> Result<(), ServerError> {
+    sqlx::query!(
+        "INSERT INTO pets
+            (pid, honor, opponent, opponent_chosen, next_exploration,
+            daily_reset)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT DO UPDATE SET honor = excluded.honor,
+            opponent = excluded.opponent,
+            opponent_chosen = excluded.opponent_chosen,
+            next_exploration = excluded.next_exploration,
+            daily_reset = excluded.daily_reset",
+        pid,
+        pets.honor,
+        pets.opponent,
+        pets.opponent_chosen,
+        pets.next_exploration,
+        pets.daily_reset
+    )
+    .execute(&mut *con)
+    .await?;
+
+    for habitat in 0..HABITAT_COUNT {
+        for pos in 0..PETS_PER_HABITAT {
+            let level = pets.levels[habitat][pos];
+            if level == 0 {
+                continue;
+            }
+            let pet_id = to_pet_id(habitat, pos);
+            let fruits_today = pets.fruits_today[habitat][pos];
+            sqlx::query!(
+                "INSERT INTO pet (pid, pet_id, level, fruits_today)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT DO UPDATE SET level = excluded.level,
+                    fruits_today = excluded.fruits_today",
+                pid,
+                pet_id,
+                level,
+                fruits_today
+            )
+            .execute(&mut *con)
+            .await?;
+        }
+    }
+
+    for (idx, habitat) in pets.habitats.iter().enumerate() {
+        let idx = idx as i64;
+        sqlx::query!(
+            "INSERT INTO pet_habitat (pid, habitat, explored, fruits, battled)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO UPDATE SET explored = excluded.explored,
+                fruits = excluded.fruits, battled = excluded.battled",
+            pid,
+            idx,
+            habitat.explored,
+            habitat.fruits,
+            habitat.battled
+        )
+        .execute(&mut *con)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Loads the pets with the daily reset applied and an opponent chosen
+async fn update_pets(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Pets, ServerError> {
+    let mut pets = load_pets(con, pid).await?.ok_or(ServerError::BadRequest)?;
+    let new_day = pets.daily_reset <= now();
+    pets.update();
+    if new_day || pets.opponent.is_none() {
+        pets.opponent = find_pet_opponent(con, pid).await?;
+        pets.opponent_chosen = now();
+    }
+    Ok(pets)
+}
+
+/// Chooses a random other character of the same world, that has pets
+async fn find_pet_opponent(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Option<i64>, ServerError> {
+    Ok(sqlx::query_scalar!(
+        "SELECT p.pid FROM pets p
+        JOIN character c ON c.pid = p.pid
+        WHERE p.pid != $1
+            AND c.world_id = (SELECT world_id FROM character WHERE pid = $1)
+        ORDER BY random()
+        LIMIT 1",
+        pid
+    )
+    .fetch_optional(&mut *con)
+    .await?)
+}
+
+/// The bonus in percent the pets of the character grant to strength,
+/// dexterity, intelligence, constitution and luck
+pub(crate) async fn pet_attribute_bonus(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<[i64; 5], ServerError> {
+    Ok(load_pets(con, pid)
+        .await?
+        .map(|a| a.attribute_bonus())
+        .unwrap_or_default())
+}
+
+/// Rolls, whether the character finds the next pet of the habitat. Every
+/// habitat has its own activity, on which its pets can be found: Shadow on
+/// quests, Light on guard duty, Earth in dungeons, Fire in fortress attacks
+/// and Water in the underworld
+pub(crate) async fn find_pet(
+    con: &mut SqliteConnection,
+    pid: i64,
+    habitat: HabitatType,
+    rng: &mut Rng,
+) -> Result<(), ServerError> {
+    if rng.u32(0..100) >= PET_FIND_CHANCE {
+        return Ok(());
+    }
+    let habitat = habitat as usize;
+    let mut pets = load_pets(con, pid).await?.unwrap_or_else(|| Pets {
+        honor: BASE_HONOR,
+        ..Default::default()
+    });
+    let Some(pos) = pets.findable(habitat) else {
+        return Ok(());
+    };
+    pets.levels[habitat][pos] = 1;
+    store_pets(con, pid, &pets).await
+}
+
+/// Rolls, whether the character finds a random fruit. Fruits are only found
+/// once the character has found a pet and are sent by mail, if the bag is full
+pub(crate) async fn find_fruit(
+    con: &mut SqliteConnection,
+    pid: i64,
+    rng: &mut Rng,
+) -> Result<(), ServerError> {
+    if rng.u32(0..100) >= FRUIT_DROP_CHANCE
+        || load_pets(con, pid).await?.is_none()
+    {
+        return Ok(());
+    }
+    let habitat = rng.usize(0..HABITAT_COUNT);
+    let fruit =
+        insert_item(con, &DbItem::fruit(FRUIT_IDENT + habitat as i64)).await?;
+    if !add_to_bag(con, pid, fruit).await? {
+        send_reward_mail(con, pid, "Fruit", &[], &[fruit]).await?;
+    }
+    Ok(())
+}
+
+/// Puts the fruit with the item ident into the fruit basket. Returns false,
+/// if the ident is not a fruit
+pub(crate) async fn store_fruit(
+    con: &mut SqliteConnection,
+    pid: i64,
+    ident: i64,
+) -> Result<bool, ServerError> {
+    let habitat = ident - FRUIT_IDENT;
+    if !(0..HABITAT_COUNT as i64).contains(&habitat) {
+        return Ok(false);
+    }
+    let mut pets = update_pets(con, pid).await?;
+    pets.habitats[habitat as usize].fruits += 1;
+    store_pets(con, pid, &pets).await?;
+    Ok(true)
+}
+
+/// Writes the stats of one of the pets of the character
+pub(crate) async fn pets_get_stats(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let pet_id = args.get_int(0, "pet id")?;
+    let (habitat, pos) = pet_position(pet_id).ok_or(ServerError::BadRequest)?;
+    let pets = load_pets(&mut *db.acquire().await?, session.player_id)
+        .await?
+        .ok_or(ServerError::BadRequest)?;
+    let level = pets.levels[habitat][pos];
+    if level == 0 {
+        return Err(ServerError::BadRequest);
+    }
+
+    let fighter = pet_fighter(pet_id, pet_id, level);
+    let mut resp = ResponseBuilder::default();
+    resp.add_key("ownpetsstats");
+    resp.add_val(pet_id);
+    resp.add_val(level);
+    resp.add_val(0); // armor
+    resp.add_val(fighter.class);
+    for attr in fighter.attributes {
+        resp.add_val(attr);
+    }
+    for _ in fighter.attributes {
+        resp.add_val(0); // bonus attributes
+    }
+    resp.add_val(level * 2 + 2); // min damage
+    resp.add_val((level * 2 + 2) * 2); // max damage
+    resp.add_val(habitat + 1);
+    poll(session, "", db, resp).await
+}
+
+/// Feeds the pet with a fruit of its habitat, which levels it up
+pub(crate) async fn player_pet_feed(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let pet_id = args.get_int(0, "pet id")?;
+    let fruit = args.get_int(1, "fruit")?;
+    let (habitat, pos) = pet_position(pet_id).ok_or(ServerError::BadRequest)?;
+    // Pets only eat the fruits of their own habitat
+    if fruit != habitat as i64 + 1 {
+        return Err(ServerError::BadRequest);
+    }
+    let mut tx = db.begin().await?;
+    let mut pets = update_pets(&mut tx, session.player_id).await?;
+
+    let level = pets.levels[habitat][pos];
+    if level == 0 || level >= MAX_PET_LEVEL {
+        return Err(ServerError::BadRequest);
+    }
+    if pets.fruits_today[habitat][pos] >= FRUITS_PER_DAY {
+        return Err(ServerError::StillBusy);
+    }
+    if pets.habitats[habitat].fruits <= 0 {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    pets.habitats[habitat].fruits -= 1;
+    pets.fruits_today[habitat][pos] += 1;
+    pets.levels[habitat][pos] += 1;
+    store_pets(&mut tx, session.player_id, &pets).await?;
+    progress_tasks(&mut tx, session.player_id, TaskType::FeedPets, 1).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Fights the next wild pet of the habitat with one of the pets of that
+/// habitat. Winning explores the habitat further, which allows finding more
+/// pets there
+pub(crate) async fn pets_dungeon_fight(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let use_mushroom = args.get_int(0, "use mushroom").unwrap_or_default();
+    let habitat = args.get_int(1, "habitat")? - 1;
+    let enemy_pos = args.get_int(2, "enemy pos")?;
+    let pet_id = args.get_int(3, "pet id")?;
+
+    let (pet_habitat, pos) =
+        pet_position(pet_id).ok_or(ServerError::BadRequest)?;
+    if habitat != pet_habitat as i64 {
+        return Err(ServerError::BadRequest);
+    }
+    let habitat = pet_habitat;
+    let mut tx = db.begin().await?;
+    let mut pets = update_pets(&mut tx, session.player_id).await?;
+
+    let explored = pets.habitats[habitat].explored;
+    let level = pets.levels[habitat][pos];
+    if level == 0
+        || enemy_pos != explored + 1
+        || explored >= PETS_PER_HABITAT as i64
+    {
+        return Err(ServerError::BadRequest);
+    }
+
+    if pets.next_exploration > now() {
+        if use_mushroom != 1 {
+            return Err(ServerError::StillBusy);
+        }
+        let mushrooms = sqlx::query_scalar!(
+            "UPDATE character SET mushrooms = mushrooms - $2
+            WHERE pid = $1 RETURNING mushrooms",
+            session.player_id,
+            EXPLORATION_SKIP_PRICE
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if mushrooms < 0 {
+            return Err(ServerError::NotEnoughMoney);
+        }
+    }
+    pets.next_exploration = next_day();
+
+    let wild_id = to_pet_id(habitat, explored as usize);
+    let pet = pet_fighter(pet_id, pet_id, level);
+    let wild = pet_fighter(
+        ENEMY_PET_MONSTER_ID + wild_id,
+        wild_id,
+        pets.exploration_level(habitat),
+    );
+    let log = simulate_fight(&pet, pet.max_hp, &wild, wild.max_hp);
+
+    let mut resp = ResponseBuilder::default();
+    add_fight(&mut resp, None, 0, &pet, &wild, &log);
+    resp.add_key("fightversion");
+    resp.add_val(1);
+
+    if log.winner == pet.id {
+        pets.habitats[habitat].explored += 1;
+    }
+    store_pets(&mut tx, session.player_id, &pets).await?;
+    let fight = TaskType::FightInPetHabitat;
+    progress_tasks(&mut tx, session.player_id, fight, 1).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, resp).await
+}
+
+/// Fights the pets of the opponent in the habitat. Every habitat can fight
+/// once per day and the winner gains some of the honor of the loser
+pub(crate) async fn pets_pvp_fight(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let opponent = args.get_int(1, "opponent")?;
+    let habitat = usize::try_from(args.get_int(2, "habitat")? - 1)
+        .ok()
+        .filter(|a| *a < HABITAT_COUNT)
+        .ok_or(ServerError::BadRequest)?;
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+    let mut pets = update_pets(&mut tx, pid).await?;
+
+    if pets.opponent != Some(opponent) {
+        return Err(ServerError::BadRequest);
+    }
+    if pets.habitats[habitat].battled {
+        return Err(ServerError::StillBusy);
+    }
+    let mut enemy = load_pets(&mut tx, opponent)
+        .await?
+        .ok_or(ServerError::BadRequest)?;
+    enemy.update();
+
+    let team = pets.team(habitat, 0);
+    let enemy_team = enemy.team(habitat, ENEMY_PET_MONSTER_ID);
+    if team.is_empty() || enemy_team.is_empty() {
+        return Err(ServerError::BadRequest);
+    }
+    let (fights, won) = simulate_team_fight(&team, &enemy_team);
+
+    let mut resp = ResponseBuilder::default();
+    match fights.as_slice() {
+        [(a, b, log)] => add_fight(&mut resp, None, 0, a, b, log),
+        fights => {
+            for (idx, (a, b, log)) in fights.iter().enumerate() {
+                add_fight(&mut resp, Some(idx + 1), 0, a, b, log);
+            }
+        }
+    }
+    resp.add_key("fightversion");
+    resp.add_val(1);
+
+    let honor = match won {
+        true => enemy.honor * HONOR_PERCENT / 100,
+        false => -(pets.honor * HONOR_PERCENT / 100),
+    };
+    pets.honor += honor;
+    enemy.honor -= honor;
+    pets.habitats[habitat].battled = true;
+    store_pets(&mut tx, pid, &pets).await?;
+    store_pets(&mut tx, opponent, &enemy).await?;
+    progress_tasks(&mut tx, pid, TaskType::FightOtherPets, 1).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, resp).await
+}
+
+/// The rank of the pet collection in the pet hall of fame of its world
+async fn pets_rank(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<i64, ServerError> {
+    Ok(sqlx::query_scalar!(
+        "WITH own AS (
+            SELECT p.honor, c.world_id FROM pets p
+            JOIN character c ON c.pid = p.pid
+            WHERE p.pid = $1
+        )
+        SELECT count(*) FROM pets p
+        JOIN character c ON c.pid = p.pid
+        WHERE c.world_id = (SELECT world_id FROM own)
+            AND (p.honor > (SELECT honor FROM own)
+                OR (p.honor = (SELECT honor FROM own) AND p.pid <= $1))",
+        pid
+    )
+    .fetch_one(&mut *con)
+    .await?)
+}
+
+pub(crate) async fn pets_get_hof(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let rank = args.get_int(0, "rank").unwrap_or_default();
+    let pre = args.get_int(2, "pre").unwrap_or_default();
+    let post = args.get_int(3, "post").unwrap_or_default();
+
+    let rank = match rank {
+        1.. => rank,
+        _ => {
+            let name = args.get_str(1, "name")?;
+            let pid = sqlx::query_scalar!(
+                "SELECT pid FROM character WHERE name = $1 AND world_id = $2",
+                name, session.world_id
+            )
+            .fetch_optional(db)
+            .await?
+            .ok_or(ServerError::BadRequest)?;
+            pets_rank(&mut *db.acquire().await?, pid).await?
+        }
+    };
+
+    let offset = (rank - pre).max(1) - 1;
+    let limit = (pre + post).min(30);
+
+    let res = sqlx::query!(
+        "SELECT c.name, coalesce(g.name, '') as `guild!: String`, p.honor,
+            (SELECT count(*) FROM pet WHERE pet.pid = p.pid)
+            as `collected!: i64`
+        FROM pets p
+        JOIN character c ON c.pid = p.pid
+        LEFT JOIN guild_member gm ON gm.pid = c.pid
+        LEFT JOIN guild g ON g.id = gm.guild_id
+        WHERE c.world_id = $3
+        ORDER BY p.honor DESC, p.pid ASC
+        LIMIT $2 OFFSET $1",
+        offset,
+        limit,
+        session.world_id,
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut entries = String::new();
+    for (entry_idx, pets) in res.into_iter().enumerate() {
+        entries
+            .write_fmt(format_args!(
+                "{},{},{},{},{},0;",
+                offset + entry_idx as i64 + 1,
+                pets.name,
+                pets.guild,
+                pets.collected,
+                pets.honor,
+            ))
+            .map_err(|e| {
+                error!("Error while writing format: {e:?}");
+                ServerError::Internal
+            })?;
+    }
+
+    ResponseBuilder::default()
+        .add_key("RanklistPets")
+        .add_str(&entries)
+        .build()
+}
+
+/// Writes the pet save, the hall of fame rank of the pets and the habitat
+/// the opponent defends in
+pub(crate) async fn add_pets(
+    resp: &mut ResponseBuilder,
+    con: &mut SqliteConnection,
+    pid: i64,
+    pets: Option<&Pets>,
+) -> Result<(), ServerError> {
+    let Some(pets) = pets else {
+        resp.add_key("petsdefensetype");
+        resp.add_val(1);
+        return Ok(());
+    };
+    let rank = pets_rank(con, pid).await?;
+    let enemy = match pets.opponent {
+        Some(opponent) => load_pets(con, opponent).await?,
+        None => None,
+    };
+
+    let mut save = [0; PETS_SAVE_LEN];
+    for habitat in 0..HABITAT_COUNT {
+        for pos in 0..PETS_PER_HABITAT {
+            let id = to_pet_id(habitat, pos) as usize;
+            save[id + 1] = pets.levels[habitat][pos];
+            save[id + 109] = pets.fruits_today[habitat][pos];
+        }
+        save[210 + habitat] = pets.habitats[habitat].explored;
+        save[223 + habitat] = i64::from(pets.habitats[habitat].battled);
+        save[238 + habitat] = pets.exploration_level(habitat);
+    }
+    save[103] = pets.collected();
+    save[231] = pets.opponent.unwrap_or_default();
+    save[232] = pets.next_exploration;
+    save[233] = rank;
+    save[234] = pets.honor;
+    if let Some(enemy) = &enemy {
+        save[235] = enemy.collected();
+        save[236] = enemy.levels.iter().flatten().sum();
+    }
+    save[237] = pets.opponent_chosen;
+    for (idx, bonus) in pets.attribute_bonus().into_iter().enumerate() {
+        save[250 + idx] = bonus;
+    }
+
+    resp.add_key("ownpets");
+    for val in save {
+        resp.add_val(val);
+    }
+    resp.add_key("petsrank");
+    resp.add_val(rank);
+    resp.add_key("petsdefensetype");
+    resp.add_val(enemy.map_or(0, |a| a.defense_habitat()) + 1);
+    Ok(())
+}
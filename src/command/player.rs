@@ -4,80 +4,127 @@ use fastrand::Rng;
 use log::error;
 use num_traits::FromPrimitive;
 use sf_api::{
-    gamestate::character::{Gender, Race},
+    gamestate::{
+        character::{Gender, Race},
+        unlockables::HabitatType,
+    },
     misc::from_sf_string,
 };
-use sqlx::Sqlite;
+use sqlx::{Sqlite, SqliteConnection};
 
 use super::{
-    CommandArguments, Portrait, ResponseBuilder, ServerError, ServerResponse,
+    CommandArguments, MOUNT_DRAGON, Portrait, ResponseBuilder, ServerError,
+    ServerResponse,
+    chat::send_whisper,
     debug::{CheatCmd, handle_cheat_command},
-    effective_mount, in_seconds, now, poll, xp_for_next_level,
+    dungeon::find_dungeon_key,
+    effective_mount,
+    fight::{add_fight, load_attributes, load_player_fighter, simulate_fight},
+    fortress::{add_other_fortress, fortress_rank, load_fortress},
+    friend::relation_to,
+    guild::{portal_damage_bonus, raid_xp_bonus, treasure_silver_bonus},
+    in_seconds,
+    item::{
+        DbItem, add_empty_item, add_items, add_to_bag, insert_item,
+        load_equipment,
+    },
+    mail::send_reward_mail,
+    mounted_quest_length, mounted_quest_silver, next_day, now,
+    pets::{find_fruit, find_pet, load_pets},
+    poll,
+    scrapbook::{
+        LOOKAT_ALBUM_OFFSET, collect_equipment, collect_monster, load_album,
+    },
+    task::{TaskType, progress_tasks},
+    witch::load_enchantment_effects,
+    xp_for_next_level,
 };
 use crate::request::Session;
 
+/// The values of `activity.typ`, that describe what a character is busy with
+const ACTIVITY_IDLE: i64 = 0;
+const ACTIVITY_WORK: i64 = 1;
+const ACTIVITY_QUEST: i64 = 2;
+
+/// The maximum amount of hours a single guard shift can last
+const MAX_WORK_HOURS: i64 = 10;
+
+/// How long a bought mount lasts
+const MOUNT_DURATION: i64 = 60 * 60 * 24 * 14;
+
+/// The most thirst for adventure a character can have, which is also what a
+/// new character starts with
+pub(crate) const MAX_THIRST: i64 = 100 * 60;
+
+/// The tavern beer as in the official game: every beer costs one mushroom
+/// and quenches 20 minutes of thirst for adventure, up to ten beers a day
+const BEER_THIRST: i64 = 20 * 60;
+const BEER_PRICE: i64 = 1;
+const DAILY_BEERS: i64 = 10;
+
+/// The silver and mushrooms a mount costs. These are the flat prices this
+/// server has always charged, as long as the level scaled prices of the
+/// official game are not known
+fn mount_price(mount: i64) -> Option<(i64, i64)> {
+    Some(match mount {
+        1 => (100, 0),
+        2 => (500, 0),
+        3 => (0, 1),
+        MOUNT_DRAGON => (0, 25),
+        _ => return None,
+    })
+}
+
 pub(crate) async fn player_mount_buy(
     session: Session,
     db: &sqlx::Pool<Sqlite>,
     args: CommandArguments<'_>,
 ) -> Result<ServerResponse, ServerError> {
     let mount = args.get_int(0, "mount")?;
+    if mount == 0 {
+        // There is nothing to buy, so the current mount is left as it is
+        return poll(session, "", db, Default::default()).await;
+    }
     let mut tx = db.begin().await?;
 
     let character = sqlx::query!(
-        "SELECT silver, mushrooms, mount, mount_end FROM CHARACTER WHERE pid \
-         = $1",
+        "SELECT silver, mushrooms, mount, mount_end
+        FROM character WHERE pid = $1",
         session.player_id
     )
     .fetch_one(&mut *tx)
     .await?;
 
-    let mut silver = character.silver;
-    let mut mushrooms = character.mushrooms;
-
-    let price = match mount {
-        0 => 0,
-        1 => 100,
-        2 => 500,
-        3 => 0,
-        4 => 0, // TODO: Silver reward
-        _ => return Err(ServerError::BadRequest),
-    };
-
-    let mush_price = match mount {
-        3 => 1,
-        4 => 25,
-        _ => 0,
-    };
-    if mushrooms < mush_price {
+    let (price, mush_price) =
+        mount_price(mount).ok_or(ServerError::BadRequest)?;
+    if character.silver < price || character.mushrooms < mush_price {
         return Err(ServerError::NotEnoughMoney);
     }
-    mushrooms -= mush_price;
 
-    if silver < price {
-        return Err(ServerError::NotEnoughMoney);
-    }
-    silver -= price;
-
-    let now = now();
-    let mount_duration = 60 * 60 * 24 * 14;
-    let mount_end = if mount != character.mount || character.mount_end < now {
-        now + mount_duration
+    // Buying the active mount again extends it, any other mount replaces it
+    let mut current_end = character.mount_end;
+    let mut current = character.mount;
+    effective_mount(&mut current_end, &mut current);
+    let mount_end = if mount == current {
+        current_end + MOUNT_DURATION
     } else {
-        character.mount_end + mount_duration
+        in_seconds(MOUNT_DURATION)
     };
 
     sqlx::query!(
-        "UPDATE Character SET mount = $1, mount_end = $2, mushrooms = $4, \
-         silver = $5 WHERE pid = $3",
+        "UPDATE character
+        SET mount = $2, mount_end = $3, silver = silver - $4,
+            mushrooms = mushrooms - $5
+        WHERE pid = $1",
+        session.player_id,
         mount,
         mount_end,
-        session.player_id,
-        mushrooms,
-        silver,
+        price,
+        mush_price,
     )
     .execute(&mut *tx)
     .await?;
+    progress_tasks(&mut tx, session.player_id, TaskType::LeaseMount, 1).await?;
 
     tx.commit().await?;
     poll(session, "", db, Default::default()).await
@@ -108,7 +155,10 @@ pub(crate) async fn player_whisper(
 ) -> Result<ServerResponse, ServerError> {
     let name = args.get_str(0, "name")?.to_lowercase();
     if name != "server" {
-        todo!()
+        if session.player_id < 0 {
+            return Err(ServerError::InvalidAuth);
+        }
+        return send_whisper(session, db, &name, args.get_str(1, "msg")?).await;
     }
     use clap::Parser;
     let command = CheatCmd::try_parse_from(args.get_str(1, "args")?.split(' '))
@@ -132,7 +182,6 @@ pub(crate) async fn player_finish_quest(
         activity.sub_type,
 
         q1.item as q1item,
-        q1.length as q1length,
         q1.Location as q1location,
         q1.Monster as q1monster,
         q1.Mushrooms as q1mush,
@@ -140,7 +189,6 @@ pub(crate) async fn player_finish_quest(
         q1.XP as q1xp,
 
         q2.item as q2item,
-        q2.length as q2length,
         q2.Location as q2location,
         q2.Monster as q2monster,
         q2.Mushrooms as q2mush,
@@ -148,7 +196,6 @@ pub(crate) async fn player_finish_quest(
         q2.XP as q2xp,
 
         q3.item as q3item,
-        q3.length as q3length,
         q3.Location as q3location,
         q3.Monster as q3monster,
         q3.Mushrooms as q3mush,
@@ -157,6 +204,8 @@ pub(crate) async fn player_finish_quest(
 
         level,
         name,
+        mount,
+        mount_end,
 
         portrait.mouth,
         portrait.hair,
@@ -187,7 +236,7 @@ pub(crate) async fn player_finish_quest(
     .fetch_one(&mut *tx)
     .await?;
 
-    if row.typ != 2 {
+    if row.typ != ACTIVITY_QUEST {
         // We are not actually questing
         return Err(ServerError::StillBusy);
     }
@@ -216,6 +265,25 @@ pub(crate) async fn player_finish_quest(
         _ => todo!(),
     };
 
+    let mut mount = row.mount;
+    let mut mount_end = row.mount_end;
+    effective_mount(&mut mount_end, &mut mount);
+    let silver = mounted_quest_silver(silver, mount);
+
+    // Every completed guild raid gives one percent more experience
+    let quest_xp = quest_xp
+        * (100 + raid_xp_bonus(&mut tx, session.player_id).await?)
+        / 100;
+    let album = load_album(&mut tx, session.player_id).await?;
+    let quest_xp = quest_xp * (100 + album.xp_bonus()) / 100;
+
+    let mut rng = Rng::new();
+    let enchantments =
+        load_enchantment_effects(&mut tx, session.player_id).await?;
+    let quest_xp = quest_xp * (100 + enchantments.quest_xp) / 100;
+    let silver = silver * (100 + enchantments.quest_silver) / 100;
+    let mush = mush + i64::from(rng.i64(0..100) < enchantments.mushroom_chance);
+
     let honor_won = 10;
 
     let mut resp = ResponseBuilder::default();
@@ -360,6 +428,21 @@ pub(crate) async fn player_finish_quest(
     .execute(&mut *tx)
     .await?;
 
+    // Characters with an enchanted amulet sometimes find an item on the way
+    if rng.i64(0..100) < enchantments.quest_item_chance {
+        let item = DbItem::random_equipment(&mut rng, character_lvl, row.class);
+        let item = insert_item(&mut tx, &item).await?;
+        if !add_to_bag(&mut tx, session.player_id, item).await? {
+            send_reward_mail(&mut tx, session.player_id, "Quest", &[], &[item])
+                .await?;
+        }
+    }
+    find_dungeon_key(&mut tx, session.player_id, character_lvl, &mut rng)
+        .await?;
+    find_pet(&mut tx, session.player_id, HabitatType::Shadow, &mut rng).await?;
+    find_fruit(&mut tx, session.player_id, &mut rng).await?;
+    collect_monster(&mut tx, session.player_id, monster).await?;
+
     // TODO: Reroll quests, add item & save fight somewhere for rewatch (save)
 
     tx.commit().await?;
@@ -402,7 +485,7 @@ pub(crate) async fn player_start_quest(
     .fetch_one(&mut *tx)
     .await?;
 
-    if row.typ != 0 {
+    if row.typ != ACTIVITY_IDLE {
         return Err(ServerError::StillBusy);
     }
 
@@ -410,14 +493,17 @@ pub(crate) async fn player_start_quest(
     let mut mount_end = row.mount_end;
     let mount_effect = effective_mount(&mut mount_end, &mut mount);
 
-    let quest_length = match quest {
-        1 => row.ql1,
-        2 => row.ql2,
-        _ => row.ql3,
-    } as f32
-        * mount_effect.ceil().max(0.0);
-
-    let quest_length = quest_length as i64;
+    let quest_length = mounted_quest_length(
+        match quest {
+            1 => row.ql1,
+            2 => row.ql2,
+            _ => row.ql3,
+        },
+        mount_effect,
+    );
+    let enchantments =
+        load_enchantment_effects(&mut tx, session.player_id).await?;
+    let quest_length = enchantments.quest_length(quest_length);
     let tfa = row.tfa;
 
     if tfa < quest_length {
@@ -427,14 +513,15 @@ pub(crate) async fn player_start_quest(
     let busy_until = in_seconds(quest_length);
     sqlx::query!(
         "UPDATE activity
-                    SET typ = 2,
+                    SET typ = $4,
                     sub_type = $2,
                     busy_until = $3,
                     started = CURRENT_TIMESTAMP
                 WHERE pid = $1",
         session.player_id,
         quest,
-        busy_until
+        busy_until,
+        ACTIVITY_QUEST
     )
     .execute(&mut *tx)
     .await?;
@@ -450,12 +537,68 @@ pub(crate) async fn player_start_quest(
     )
     .execute(&mut *tx)
     .await?;
+    let thirst = TaskType::ConsumeThirstForAdventure;
+    progress_tasks(&mut tx, session.player_id, thirst, quest_length / 60)
+        .await?;
 
     tx.commit().await?;
 
     poll(session, "", db, Default::default()).await
 }
 
+/// Drinks a beer in the tavern to quench the thirst for adventure. A beer
+/// can only be drunk, while it does not push the thirst above the maximum
+pub(crate) async fn player_beer_buy(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+) -> Result<ServerResponse, ServerError> {
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+
+    let row = sqlx::query!(
+        "SELECT mushrooms, tfa, beer_drunk, beer_reset
+        FROM character NATURAL JOIN tavern WHERE pid = $1",
+        pid
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let beers = match row.beer_reset > now() {
+        true => row.beer_drunk,
+        false => 0,
+    };
+    let enchantments = load_enchantment_effects(&mut tx, pid).await?;
+    if beers >= DAILY_BEERS + enchantments.extra_beers
+        || row.tfa + BEER_THIRST > MAX_THIRST
+    {
+        return Err(ServerError::BadRequest);
+    }
+    if row.mushrooms < BEER_PRICE {
+        return Err(ServerError::NotEnoughMoney);
+    }
+
+    sqlx::query!(
+        "UPDATE character SET mushrooms = mushrooms - $2 WHERE pid = $1", pid,
+        BEER_PRICE
+    )
+    .execute(&mut *tx)
+    .await?;
+    let beers = beers + 1;
+    let reset = next_day();
+    sqlx::query!(
+        "UPDATE tavern SET tfa = tfa + $2, beer_drunk = $3, beer_reset = $4
+        WHERE pid = $1",
+        pid,
+        BEER_THIRST,
+        beers,
+        reset
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
 pub(crate) async fn player_gamble_gold(
     session: Session,
     db: &sqlx::Pool<Sqlite>,
@@ -471,12 +614,14 @@ pub(crate) async fn player_gamble_gold(
     .fetch_one(&mut *tx)
     .await?;
 
-    if silver < 0 || character_silver < silver {
+    if silver <= 0 || character_silver < silver {
         return Err(ServerError::BadRequest);
     }
 
     if rng.bool() {
         silver *= 2;
+        let won = TaskType::DefeatGambler;
+        progress_tasks(&mut tx, session.player_id, won, 1).await?;
     } else {
         silver = -silver;
     }
@@ -647,39 +792,202 @@ pub(crate) async fn player_set_face(
     Ok(ServerResponse::Success)
 }
 
+/// The silver a character earns for every hour of guard duty
+pub(crate) fn guard_wage(level: i64, treasure_bonus: i64) -> i64 {
+    let base = 10 + level * level * 2;
+    base * (100 + treasure_bonus) / 100
+}
+
+pub(crate) async fn player_work_start(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let hours = args.get_int(0, "work hours")?;
+    if !(1..=MAX_WORK_HOURS).contains(&hours) {
+        return Err(ServerError::BadRequest);
+    }
+
+    let mut tx = db.begin().await?;
+    let busy_until = in_seconds(hours * 60 * 60);
+    let started = now();
+    let res = sqlx::query!(
+        "UPDATE activity
+        SET typ = $2, sub_type = $3, started = $4, busy_until = $5
+        WHERE pid = $1 AND typ = $6",
+        session.player_id,
+        ACTIVITY_WORK,
+        hours,
+        started,
+        busy_until,
+        ACTIVITY_IDLE
+    )
+    .execute(&mut *tx)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(ServerError::StillBusy);
+    }
+    tx.commit().await?;
+
+    poll(session, "", db, Default::default()).await
+}
+
+pub(crate) async fn player_work_finish(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+) -> Result<ServerResponse, ServerError> {
+    let mut tx = db.begin().await?;
+    let row = sqlx::query!(
+        "SELECT typ, sub_type, busy_until, level
+        FROM character NATURAL JOIN activity
+        WHERE pid = $1",
+        session.player_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if row.typ != ACTIVITY_WORK || row.busy_until > now() {
+        return Err(ServerError::StillBusy);
+    }
+
+    let bonus = treasure_silver_bonus(&mut tx, session.player_id).await?;
+    let wage = guard_wage(row.level, bonus) * row.sub_type;
+
+    sqlx::query!(
+        "UPDATE character SET silver = silver + $2 WHERE pid = $1",
+        session.player_id, wage
+    )
+    .execute(&mut *tx)
+    .await?;
+    reset_activity(&mut tx, session.player_id).await?;
+    find_pet(
+        &mut tx,
+        session.player_id,
+        HabitatType::Light,
+        &mut Rng::new(),
+    )
+    .await?;
+    let hours = TaskType::CityGuardHours;
+    progress_tasks(&mut tx, session.player_id, hours, row.sub_type).await?;
+    tx.commit().await?;
+
+    let mut resp = ResponseBuilder::default();
+    resp.add_key("workreward");
+    resp.add_val(wage);
+    poll(session, "", db, resp).await
+}
+
+/// Ends the guard duty early. Unfinished shifts are not paid
+pub(crate) async fn player_work_stop(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+) -> Result<ServerResponse, ServerError> {
+    let mut tx = db.begin().await?;
+    let typ = sqlx::query_scalar!(
+        "SELECT typ FROM activity WHERE pid = $1", session.player_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if typ != ACTIVITY_WORK {
+        return Err(ServerError::BadRequest);
+    }
+    reset_activity(&mut tx, session.player_id).await?;
+    tx.commit().await?;
+
+    poll(session, "", db, Default::default()).await
+}
+
+async fn reset_activity(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<(), ServerError> {
+    sqlx::query!(
+        "UPDATE activity
+        SET typ = $2, sub_type = 0, started = 0, busy_until = 0
+        WHERE pid = $1",
+        pid,
+        ACTIVITY_IDLE
+    )
+    .execute(&mut *con)
+    .await?;
+    Ok(())
+}
+
 pub(crate) async fn player_look_at(
-    _session: Session,
+    session: Session,
     db: &sqlx::Pool<Sqlite>,
     args: CommandArguments<'_>,
 ) -> Result<ServerResponse, ServerError> {
+    let mut con = db.acquire().await?;
+    // Only characters on the same world as the session can be looked at
     let pid = match args.get_int(0, "") {
-        Ok(x) => x,
+        Ok(x) => sqlx::query_scalar!(
+            "SELECT pid FROM character WHERE pid = $1 AND world_id = $2", x,
+            session.world_id
+        )
+        .fetch_optional(&mut *con)
+        .await?
+        .ok_or(ServerError::BadRequest)?,
         Err(_) => {
             let name = args.get_str(0, "look at pid or name")?;
             sqlx::query_scalar!(
-                "SELECT pid FROM character WHERE name = $1", name
+                "SELECT pid FROM character
+                WHERE lower(name) = lower($1) AND world_id = $2",
+                name,
+                session.world_id
             )
-            .fetch_one(db)
+            .fetch_optional(&mut *con)
             .await?
+            .ok_or(ServerError::BadRequest)?
         }
     };
 
     let mut resp = ResponseBuilder::default();
     let info = sqlx::query!(
         "
-        SELECT name, level, honor, experience, race, portrait.*, gender, class,
-            a.*, description
+        SELECT c.name, c.level, c.honor, c.experience, c.race, portrait.*,
+            c.gender, c.class, c.description, c.mount, c.mount_end,
+            coalesce(g.name, '') as `guild_name!: String`,
+            g.demon_portal_act,
+            (
+            SELECT count(*)
+            FROM character AS x
+            WHERE x.world_id = c.world_id
+              AND (x.honor > c.honor
+                   OR (x.honor = c.honor AND x.pid <= c.pid))
+            ) as `rank!: i64`
         FROM character c
         NATURAL JOIN portrait
-        JOIN attributes a on a.id = c.attributes
-        WHERE pid = $1",
+        LEFT JOIN guild_member gm on gm.pid = c.pid
+        LEFT JOIN guild g on g.id = gm.guild_id
+        WHERE c.pid = $1",
         pid
     )
-    .fetch_one(db)
+    .fetch_one(&mut *con)
     .await?;
 
+    let attributes = load_attributes(&mut con, pid).await?;
+    let equipment = load_equipment(&mut con, pid).await?;
+    let mut armor = 0;
+    for (slot, item) in equipment.iter().enumerate() {
+        let Some(item) = item else {
+            continue;
+        };
+        // The weapon and shield use their effects for damage and blocking
+        if slot < 8 {
+            armor += item.effect1;
+        }
+    }
+    let (min_damage, max_damage) = match &equipment[8] {
+        Some(weapon) => (weapon.effect1, weapon.effect2),
+        None => (1, 2),
+    };
+
+    let mut mount_end = info.mount_end;
+    let mut mount = info.mount;
+    effective_mount(&mut mount_end, &mut mount);
+
     resp.add_key("otherplayergroupname.r");
-    resp.add_val("");
+    resp.add_str(&info.guild_name);
     resp.add_key("otherplayer.playerlookat");
     resp.add_val(pid);
     resp.add_val(0);
@@ -687,7 +995,7 @@ pub(crate) async fn player_look_at(
     resp.add_val(info.experience); // xp
     resp.add_val(xp_for_next_level(info.level)); // xp next lvl
     resp.add_val(info.honor);
-    resp.add_val(10); // TODO: Rank
+    resp.add_val(info.rank);
     resp.add_val(0); // ?
     resp.add_val(info.mouth);
     resp.add_val(info.hair);
@@ -702,70 +1010,78 @@ pub(crate) async fn player_look_at(
     resp.add_val(info.race);
     resp.add_val(info.gender);
     resp.add_val(info.class);
-    resp.add_val(info.strength);
-    resp.add_val(info.dexterity);
-    resp.add_val(info.intelligence);
-    resp.add_val(info.stamina);
-    resp.add_val(info.luck);
-    // TODO: Bonus attrs
-    resp.add_val(0);
-    resp.add_val(0);
-    resp.add_val(0);
-    resp.add_val(0);
-    resp.add_val(0);
+    for attribute in attributes.base {
+        resp.add_val(attribute);
+    }
+    for addition in attributes.additions() {
+        resp.add_val(addition);
+    }
 
     for _ in 0..8 {
         resp.add_val(0);
     }
 
-    // Equipment
-    for _ in 0..10 {
-        for _ in 0..12 {
-            resp.add_val(0);
-        }
+    // 39 Equipment
+    add_items(&mut resp, &equipment);
+    resp.add_val(mount); // 159 mount
+    for _ in 0..3 {
+        resp.add_val(0);
     }
-    resp.add_val(0); // 159 mount
-    resp.add_val(58);
-    resp.add_val(37408);
-    resp.add_val(2723);
-    resp.add_val(11901);
-    resp.add_val(0);
-    resp.add_val(0);
-    resp.add_val(1393194397);
-    resp.add_val(1);
-    resp.add_val(4165);
-    resp.add_val(958);
-    resp.add_val(2642);
-    resp.add_val(3906638);
-    for _ in 0..36 {
+    let album = load_album(&mut con, pid).await?;
+    resp.add_val(LOOKAT_ALBUM_OFFSET + album.entries); // 163 scrapbook count
+    for _ in 0..4 {
         resp.add_val(0);
     }
-    // Mainly fortress stuff
-    for _ in 0..53 {
+    resp.add_val(armor); // 168
+    resp.add_val(min_damage); // 169
+    resp.add_val(max_damage); // 170
+    for _ in 0..37 {
+        resp.add_val(0);
+    }
+    // 208 Mainly fortress stuff
+    let fortress = load_fortress(&mut con, pid).await?;
+    add_other_fortress(&mut resp, fortress.as_ref(), info.level);
+    let portal_dmg_bonus = info
+        .demon_portal_act
+        .map(portal_damage_bonus)
+        .unwrap_or_default();
+    // 252 character_hp_bonus << 24, damage_bonus << 16
+    resp.add_val(portal_dmg_bonus << 16);
+    for _ in 0..8 {
         resp.add_val(0);
     }
     resp.add_key("otherdescription.s");
     resp.add_str(&info.description);
     resp.add_key("otherplayername.r");
     resp.add_val(info.name);
+    // Units fight at the level of the character
     resp.add_key("otherplayerunitlevel(4)");
-    resp.add_val(190);
-    resp.add_val(140);
-    resp.add_val(145);
-    resp.add_val(145);
+    resp.add_val(fortress.as_ref().map_or(0, |a| a.wall_level()));
+    for _ in 0..3 {
+        resp.add_val(info.level);
+    }
     resp.add_key("otherplayerfriendstatus");
-    resp.add_val(0);
+    resp.add_val(relation_to(&mut con, session.player_id, pid).await?);
+    let fortress_rank = match &fortress {
+        Some(_) => fortress_rank(&mut con, pid).await?,
+        None => -1,
+    };
     resp.add_key("otherplayerfortressrank");
-    resp.add_val(0);
+    resp.add_val(fortress_rank);
+    let [strength, dexterity, intelligence, constitution, luck] =
+        load_pets(&mut con, pid)
+            .await?
+            .map(|a| a.attribute_bonus())
+            .unwrap_or_default();
+    // The client expects the bonuses in its own order after a value, that
+    // it does not read
     resp.add_key("otherplayerpetbonus.petbonus");
-    resp.add_val(207011);
-    resp.add_val(7);
-    resp.add_val(6);
-    resp.add_val(6);
-    resp.add_val(6);
-    resp.add_val(6);
+    resp.add_val(0);
+    for bonus in [constitution, dexterity, intelligence, luck, strength] {
+        resp.add_val(bonus);
+    }
     resp.add_key("soldieradvice");
-    resp.add_val(18);
+    resp.add_val(fortress.as_ref().map_or(0, |a| a.soldier_advice()));
     resp.build()
 }
 
@@ -776,116 +1092,60 @@ pub(crate) async fn player_arena_fight(
 ) -> Result<ServerResponse, ServerError> {
     let enemy_name = args.get_str(0, "arena enemy name")?;
 
+    let mut tx = db.begin().await?;
     let enemy_id = sqlx::query_scalar!(
         "SELECT pid FROM character WHERE name = $1", enemy_name
     )
-    .fetch_one(db)
-    .await?;
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+    if enemy_id == session.player_id {
+        return Err(ServerError::BadRequest);
+    }
+
+    let player = load_player_fighter(&mut tx, session.player_id).await?;
+    let enemy = load_player_fighter(&mut tx, enemy_id).await?;
+    let log = simulate_fight(&player, player.max_hp, &enemy, enemy.max_hp);
+    let won = log.winner == session.player_id;
 
     let mut resp = ResponseBuilder::default();
+    add_fight(&mut resp, None, 0, &player, &enemy, &log);
     resp.add_key("fightversion");
-    resp.add_val(2);
-
-    resp.add_key("fightheader.fighters");
-    resp.add_val(0);
-    resp.add_val(0);
-    resp.add_val(0);
-    resp.add_val(0);
     resp.add_val(1);
 
-    let fighters = [session.player_id, enemy_id];
-
-    let starting_hp = 10_000;
-
-    for pid in fighters {
-        let fighter = sqlx::query!(
-            "SELECT name, portrait.*, a.*, level, class, race, gender
-            FROM character c
-            NATURAL JOIN portrait
-            JOIN attributes a on a.id = c.attributes
-            WHERE pid = $1",
-            pid
-        )
-        .fetch_one(db)
-        .await?;
-
-        // Player a info
-        resp.add_val(fighter.pid);
-        resp.add_str(&fighter.name);
-        resp.add_val(fighter.level);
-        resp.add_val(starting_hp); // TODO: Calc their hp
-        resp.add_val(starting_hp); // TODO: Calc their hp
-        resp.add_val(fighter.strength); // str
-        resp.add_val(fighter.dexterity); // dex
-        resp.add_val(fighter.intelligence); // int
-        resp.add_val(fighter.stamina); // const
-        resp.add_val(fighter.luck); // luck
-        resp.add_val(fighter.mouth); // mouth
-        resp.add_val(fighter.hair); // hair
-        resp.add_val(fighter.eyes); // brows
-        resp.add_val(fighter.eyes); // eyes
-        resp.add_val(fighter.beards); // beards
-        resp.add_val(fighter.nose); // nose
-        resp.add_val(fighter.ears); // ears
-        resp.add_val(fighter.extra); // extra
-        resp.add_val(fighter.horns); // horns
-        resp.add_val(fighter.influencer); // influencer
-        resp.add_val(fighter.race);
-        resp.add_val(fighter.gender);
-        resp.add_val(fighter.class);
-        // Dont know, don't care (yet)
-        resp.add_val(185204737);
-        resp.add_val(327703);
-        resp.add_val(494);
-        resp.add_val(962);
-        resp.add_val(4);
-        resp.add_val(1);
-        resp.add_val(2);
-        resp.add_val(709);
-        resp.add_val(0);
-        resp.add_val(0);
-        resp.add_val(110873491);
-        resp.add_val(23396352);
-        // Some item i think
-        for _ in 0..12 {
-            resp.add_val(0);
+    // Beating another character collects everything they wear
+    let mut loot = None;
+    if won {
+        collect_equipment(&mut tx, session.player_id, enemy_id).await?;
+        let won = TaskType::WinFightsInArena;
+        progress_tasks(&mut tx, session.player_id, won, 1).await?;
+
+        // An enchanted talisman sometimes loots an item. It is a new random
+        // item of the own level, not one the enemy wears
+        let enchantments =
+            load_enchantment_effects(&mut tx, session.player_id).await?;
+        let mut rng = Rng::new();
+        if rng.i64(0..100) < enchantments.arena_loot_chance {
+            let item =
+                DbItem::random_equipment(&mut rng, player.level, player.class);
+            let item_id = insert_item(&mut tx, &item).await?;
+            if !add_to_bag(&mut tx, session.player_id, item_id).await? {
+                send_reward_mail(
+                    &mut tx,
+                    session.player_id,
+                    "Arena",
+                    &[],
+                    &[item_id],
+                )
+                .await?;
+            }
+            loot = Some(item);
         }
     }
+    tx.commit().await?;
 
-    resp.add_key("fight.r");
-
-    let mut player_a_hp = starting_hp;
-    let mut player_b_hp = starting_hp;
-
-    let mut rng = fastrand::Rng::new();
-    for round in 0.. {
-        let (pid, our_hp, enemy_hp) = if round % 2 == 0 {
-            (fighters[0], &mut player_a_hp, &mut player_b_hp)
-        } else {
-            (fighters[1], &mut player_b_hp, &mut player_a_hp)
-        };
-        *enemy_hp -= rng.i32(2_000..=4_000);
-        resp.add_val(pid);
-        resp.add_val(0);
-        resp.add_val(1); // Attack type (weapon, catapult, etc.)
-        resp.add_val(0); // Enemy reaction (repelled/dodged)
-        resp.add_val(0);
-        resp.add_val(*our_hp);
-        resp.add_val(*enemy_hp);
-        resp.add_val(0);
-        resp.add_val(0);
-        if player_a_hp <= 0 || player_b_hp <= 0 {
-            break;
-        }
-    }
-    resp.add_key("winnerid");
-    resp.add_val(if player_a_hp > 0 {
-        fighters[0]
-    } else {
-        fighters[1]
-    });
     resp.add_key("fightresult.battlereward");
-    resp.add_val((player_a_hp > 0) as i32); // have we won?
+    resp.add_val(won as i32); // have we won?
     resp.add_val(1);
     resp.add_val(0); // silver
     resp.add_val(1000); // xp won
@@ -894,9 +1154,24 @@ pub(crate) async fn player_arena_fight(
     resp.add_val(0);
     resp.add_val(2); // rank pre
     resp.add_val(2); // rank post
-    // Item
-    for _ in 0..12 {
-        resp.add_val(0);
+    match &loot {
+        Some(item) => item.write(&mut resp),
+        None => add_empty_item(&mut resp),
     }
     resp.build()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_prices() {
+        assert_eq!(mount_price(1), Some((100, 0)));
+        assert_eq!(mount_price(2), Some((500, 0)));
+        assert_eq!(mount_price(3), Some((0, 1)));
+        assert_eq!(mount_price(MOUNT_DRAGON), Some((0, 25)));
+        assert_eq!(mount_price(0), None);
+        assert_eq!(mount_price(5), None);
+    }
+}
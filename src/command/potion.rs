@@ -0,0 +1,176 @@
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse, now, poll,
+};
+use crate::request::Session;
+
+/// The amount of potions, that can be active at the same time
+const POTION_SLOTS: usize = 3;
+/// How long a potion lasts after it has been drunk
+const POTION_DURATION: i64 = 3 * 24 * 60 * 60;
+/// The item ident of the potion of eternal life. It increases the life
+/// instead of an attribute
+const ETERNAL_LIFE_POTION: i64 = 16;
+/// The life bonus in percent of the potion of eternal life
+const ETERNAL_LIFE_BONUS: i64 = 25;
+/// The attribute bonus in percent of small, medium and large potions
+const POTION_BONUS: [i64; 3] = [10, 15, 25];
+/// The size of the smallest potions
+pub(crate) const SMALL_POTION: usize = 0;
+
+/// A potion, that a character has drunk
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ActivePotion {
+    /// The item ident of the potion
+    pub potion: i64,
+    pub expires: i64,
+}
+
+/// The item ident of the potion of the attribute (0..5, strength first) in
+/// the size
+pub(crate) fn potion_ident(attribute: usize, size: usize) -> i64 {
+    (size * 5 + attribute + 1) as i64
+}
+
+/// The attribute a potion increases (none for the life) and the bonus in
+/// percent. Other item idents have no effect
+fn potion_effect(ident: i64) -> Option<(Option<usize>, i64)> {
+    match ident {
+        ETERNAL_LIFE_POTION => Some((None, ETERNAL_LIFE_BONUS)),
+        1..=15 => {
+            let attribute = (ident - 1) % 5;
+            let size = (ident - 1) / 5;
+            Some((Some(attribute as usize), POTION_BONUS[size as usize]))
+        }
+        _ => None,
+    }
+}
+
+/// Loads the potions of the character, that are still active. Expired ones
+/// are removed
+pub(crate) async fn load_potions(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<[Option<ActivePotion>; POTION_SLOTS], ServerError> {
+    let now = now();
+    sqlx::query!(
+        "DELETE FROM potion WHERE pid = $1 AND expires <= $2", pid, now
+    )
+    .execute(&mut *con)
+    .await?;
+    let rows = sqlx::query!(
+        "SELECT slot, potion, expires FROM potion WHERE pid = $1", pid
+    )
+    .fetch_all(&mut *con)
+    .await?;
+    let mut potions = [None; POTION_SLOTS];
+    for row in rows {
+        if let Some(slot) = potions.get_mut(row.slot as usize) {
+            *slot = Some(ActivePotion {
+                potion: row.potion,
+                expires: row.expires,
+            });
+        }
+    }
+    Ok(potions)
+}
+
+/// The bonus in percent the potions grant to every attribute (strength
+/// first) and to the life
+pub(crate) fn potion_bonus(
+    potions: &[Option<ActivePotion>],
+) -> ([i64; 5], i64) {
+    let mut attributes = [0; 5];
+    let mut life = 0;
+    for potion in potions.iter().flatten() {
+        match potion_effect(potion.potion) {
+            Some((Some(attribute), bonus)) => attributes[attribute] += bonus,
+            Some((None, bonus)) => life += bonus,
+            None => {}
+        }
+    }
+    (attributes, life)
+}
+
+/// Drinks the potion with the item ident. It replaces an active potion of
+/// the same attribute, if it is at least as large. Otherwise it needs a free
+/// slot. Returns false, if the potion can not be drunk
+pub(crate) async fn drink_potion(
+    con: &mut SqliteConnection,
+    pid: i64,
+    ident: i64,
+) -> Result<bool, ServerError> {
+    let Some((attribute, bonus)) = potion_effect(ident) else {
+        return Ok(false);
+    };
+    let potions = load_potions(con, pid).await?;
+    let same = potions.iter().position(|potion| {
+        potion.is_some_and(|potion| {
+            potion_effect(potion.potion).map(|a| a.0) == Some(attribute)
+        })
+    });
+    let slot = match same {
+        Some(slot) => {
+            let active = potions[slot].and_then(|a| potion_effect(a.potion));
+            if active.is_some_and(|(_, active)| active > bonus) {
+                return Ok(false);
+            }
+            slot
+        }
+        None => match potions.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => return Ok(false),
+        },
+    };
+
+    let slot = slot as i64;
+    let expires = now() + POTION_DURATION;
+    sqlx::query!(
+        "INSERT INTO potion (pid, slot, potion, expires)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO UPDATE SET potion = excluded.potion,
+            expires = excluded.expires",
+        pid,
+        slot,
+        ident,
+        expires
+    )
+    .execute(&mut *con)
+    .await?;
+    Ok(true)
+}
+
+/// Throws away the active potion in the 1 based slot
+pub(crate) async fn player_potion_kill(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let slot = args.get_int(0, "potion slot")? - 1;
+    let res = sqlx::query!(
+        "DELETE FROM potion WHERE pid = $1 AND slot = $2", session.player_id,
+        slot
+    )
+    .execute(db)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(ServerError::BadRequest);
+    }
+    poll(session, "", db, Default::default()).await
+}
+
+pub(crate) fn add_potions(
+    resp: &mut ResponseBuilder,
+    potions: &[Option<ActivePotion>],
+) {
+    for potion in potions {
+        resp.add_val(potion.map_or(0, |a| a.potion)); // typ & size
+    }
+    for potion in potions {
+        resp.add_val(potion.map_or(0, |a| a.expires)); // expires
+    }
+    for _ in potions {
+        resp.add_val(0); // ??
+    }
+}
@@ -0,0 +1,223 @@
+use base64::Engine;
+use sqlx::SqliteConnection;
+
+use super::{
+    ResponseBuilder, ServerError,
+    item::{DbItem, load_equipment},
+};
+
+/// The amount of bytes the client expects in the album
+const ALBUM_BYTES: usize = 529;
+/// The highest tavern monster id, that has an album position. Monsters use
+/// their id as the position
+const MAX_ALBUM_MONSTER: i64 = 800;
+/// The amount of positions, that can actually be collected
+const ALBUM_SIZE: i64 = 4194;
+/// The experience bonus in percent a complete album grants on quests
+const MAX_ALBUM_XP_BONUS: i64 = 10;
+/// The amount of epic items every slot has in the album
+const EPIC_POSITIONS: i64 = 40;
+/// The first model id of epic items
+const FIRST_EPIC_MODEL: i64 = 50;
+/// The amount of colors every normal item comes in
+const ITEM_COLORS: i64 = 5;
+/// The item type of talismans. They only have one color
+const TALISMAN_ITEM_TYPE: i64 = 10;
+/// The client only shows the scrapbook count of another character, if it is
+/// offset by this
+pub(crate) const LOOKAT_ALBUM_OFFSET: i64 = 10000;
+
+/// The first album position and amount of normal items of every equipment
+/// slot as (item type, class, start, normal items). The epics of a slot come
+/// directly after its normal items. Jewelry can be worn by every class
+const ALBUM_ITEMS: [(i64, i64, i64, i64); 22] = [
+    (8, 0, 801, 210),
+    (9, 0, 1051, 160),
+    (10, 0, 1251, 74),
+    (1, 1, 1365, 300),
+    (2, 1, 1705, 100),
+    (3, 1, 1845, 100),
+    (4, 1, 1985, 100),
+    (5, 1, 2125, 100),
+    (6, 1, 2265, 100),
+    (7, 1, 2405, 100),
+    (1, 2, 2545, 100),
+    (3, 2, 2685, 100),
+    (4, 2, 2825, 100),
+    (5, 2, 2965, 100),
+    (6, 2, 3105, 100),
+    (7, 2, 3245, 100),
+    (1, 3, 3385, 100),
+    (3, 3, 3525, 100),
+    (4, 3, 3665, 100),
+    (5, 3, 3805, 100),
+    (6, 3, 3945, 100),
+    (7, 3, 4085, 100),
+];
+
+/// The album of a character
+#[derive(Debug)]
+pub(crate) struct Album {
+    pub album: Vec<u8>,
+    pub entries: i64,
+}
+
+impl Default for Album {
+    fn default() -> Self {
+        Album {
+            album: vec![0; ALBUM_BYTES],
+            entries: 0,
+        }
+    }
+}
+
+impl Album {
+    /// Marks the 1 based album position as collected. Returns false, if it
+    /// already was
+    fn collect(&mut self, pos: i64) -> bool {
+        let Some(byte) = usize::try_from(pos - 1)
+            .ok()
+            .and_then(|idx| self.album.get_mut(idx / 8))
+        else {
+            return false;
+        };
+        let bit = 0x80 >> ((pos - 1) % 8);
+        if *byte & bit != 0 {
+            return false;
+        }
+        *byte |= bit;
+        self.entries += 1;
+        true
+    }
+
+    /// The experience bonus in percent the album grants on quests
+    pub(crate) fn xp_bonus(&self) -> i64 {
+        self.entries * MAX_ALBUM_XP_BONUS / ALBUM_SIZE
+    }
+}
+
+/// The album position of an equipment item. Other items have none
+fn item_position(item: &DbItem) -> Option<i64> {
+    let &(_, _, start, normal) =
+        ALBUM_ITEMS.iter().find(|(typ, class, _, _)| {
+            *typ == item.item_type && (*class == 0 || *class == item.class)
+        })?;
+    let model = item.model_id;
+    if model >= FIRST_EPIC_MODEL {
+        let pos = model - FIRST_EPIC_MODEL;
+        // The armor of every class skips the 10th and 11th epic
+        let skipped = (3..=7).contains(&item.item_type);
+        if pos >= EPIC_POSITIONS || (skipped && (9..=10).contains(&pos)) {
+            return None;
+        }
+        return Some(start + normal + pos);
+    }
+    let pos = match item.item_type {
+        TALISMAN_ITEM_TYPE => model - 1,
+        _ => {
+            // The client derives the color from the stats of the item
+            let stats = item.effect1
+                + item.effect2
+                + item.atr_typ1
+                + item.atr_typ2
+                + item.atr_typ3
+                + item.atr_val1
+                + item.atr_val2
+                + item.atr_val3;
+            (model - 1) * ITEM_COLORS + stats % ITEM_COLORS
+        }
+    };
+    (0..normal).contains(&pos).then_some(start + pos)
+}
+
+/// Loads the album of the character
+pub(crate) async fn load_album(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Album, ServerError> {
+    let row = sqlx::query!(
+        "SELECT album, entries FROM scrapbook WHERE pid = $1", pid
+    )
+    .fetch_optional(&mut *con)
+    .await?;
+    let Some(row) = row else {
+        return Ok(Album::default());
+    };
+    let mut album = row.album;
+    album.resize(ALBUM_BYTES, 0);
+    Ok(Album {
+        album,
+        entries: row.entries,
+    })
+}
+
+/// Adds all the album positions to the album of the character
+async fn collect(
+    con: &mut SqliteConnection,
+    pid: i64,
+    positions: impl IntoIterator<Item = i64>,
+) -> Result<(), ServerError> {
+    let mut album = load_album(con, pid).await?;
+    let mut changed = false;
+    for pos in positions {
+        changed |= album.collect(pos);
+    }
+    if !changed {
+        return Ok(());
+    }
+    sqlx::query!(
+        "INSERT INTO scrapbook (pid, album, entries) VALUES ($1, $2, $3)
+        ON CONFLICT DO UPDATE SET album = excluded.album,
+            entries = excluded.entries",
+        pid,
+        album.album,
+        album.entries
+    )
+    .execute(&mut *con)
+    .await?;
+    Ok(())
+}
+
+/// Adds the tavern monster to the album of the character
+pub(crate) async fn collect_monster(
+    con: &mut SqliteConnection,
+    pid: i64,
+    monster_id: i64,
+) -> Result<(), ServerError> {
+    if !(1..=MAX_ALBUM_MONSTER).contains(&monster_id) {
+        return Ok(());
+    }
+    collect(con, pid, [monster_id]).await
+}
+
+/// Adds the item to the album of the character, if it is an equipment item
+pub(crate) async fn collect_item(
+    con: &mut SqliteConnection,
+    pid: i64,
+    item: &DbItem,
+) -> Result<(), ServerError> {
+    collect(con, pid, item_position(item)).await
+}
+
+/// Adds everything the defeated character has equipped to the album of the
+/// character
+pub(crate) async fn collect_equipment(
+    con: &mut SqliteConnection,
+    pid: i64,
+    defeated: i64,
+) -> Result<(), ServerError> {
+    let equipment = load_equipment(con, defeated).await?;
+    let positions: Vec<_> = equipment
+        .iter()
+        .flatten()
+        .filter_map(item_position)
+        .collect();
+    collect(con, pid, positions).await
+}
+
+pub(crate) fn add_scrapbook(resp: &mut ResponseBuilder, album: &Album) {
+    resp.add_key("scrapbook.r");
+    resp.add_str(
+        &base64::engine::general_purpose::URL_SAFE.encode(&album.album),
+    );
+}
@@ -0,0 +1,376 @@
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse,
+    mail::{RewardTyp, give_reward},
+    next_day, now, poll,
+};
+use crate::request::Session;
+
+/// The things a character can do, that count towards tasks. The values are
+/// the task types the client knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TaskType {
+    /// Counted in minutes spent on quests
+    ConsumeThirstForAdventure = 2,
+    WinFightsInArena = 3,
+    SpinWheelOfFortune = 4,
+    FeedPets = 7,
+    FightOtherPets = 8,
+    BlacksmithDismantle = 9,
+    LureHeroesIntoUnderworld = 12,
+    DefeatGambler = 14,
+    FindGemInFortress = 20,
+    FightInPetHabitat = 22,
+    CollectGoldFromPit = 81,
+    GainArcaneFromDismantle = 86,
+    GainMetalFromDismantle = 87,
+    UpgradeItemAttributes = 88,
+    ClaimSoulsFromExtractor = 90,
+    FightInDungeons = 95,
+    CollectWood = 104,
+    CollectStone = 105,
+    CommandFortressBattle = 106,
+    LeaseMount = 127,
+    CityGuardHours = 129,
+}
+
+/// A task as (type, target, points)
+type Task = (TaskType, i64, i64);
+/// A reward chest as (required points, rewards)
+type Chest = (i64, &'static [(RewardTyp, i64)]);
+
+/// The tasks every character can do each day
+const DAILY_TASKS: [Task; 14] = {
+    use TaskType::*;
+    [
+        (ConsumeThirstForAdventure, 60, 2),
+        (WinFightsInArena, 3, 1),
+        (FightInDungeons, 2, 1),
+        (CityGuardHours, 4, 1),
+        (FeedPets, 3, 1),
+        (FightInPetHabitat, 1, 1),
+        (LureHeroesIntoUnderworld, 2, 1),
+        (CollectWood, 1, 1),
+        (DefeatGambler, 1, 1),
+        (LeaseMount, 1, 1),
+        (BlacksmithDismantle, 2, 1),
+        (GainMetalFromDismantle, 50, 1),
+        (GainArcaneFromDismantle, 10, 1),
+        (UpgradeItemAttributes, 1, 1),
+    ]
+};
+
+const DAILY_CHESTS: [Chest; 3] = [
+    (3, &[(RewardTyp::Silver, 1000), (RewardTyp::XP, 500)]),
+    (
+        6,
+        &[
+            (RewardTyp::Mushrooms, 1),
+            (RewardTyp::Silver, 2500),
+            (RewardTyp::LuckyCoins, 2),
+        ],
+    ),
+    (
+        10,
+        &[(RewardTyp::Mushrooms, 3), (RewardTyp::QuicksandGlass, 2)],
+    ),
+];
+
+const EVENT_CHESTS: [Chest; 3] = [
+    (
+        5,
+        &[
+            (RewardTyp::Silver, 5000),
+            (RewardTyp::XP, 2000),
+            (RewardTyp::LuckyCoins, 5),
+        ],
+    ),
+    (
+        12,
+        &[(RewardTyp::Mushrooms, 5), (RewardTyp::QuicksandGlass, 5)],
+    ),
+    (20, &[(RewardTyp::Mushrooms, 15), (RewardTyp::Honor, 100)]),
+];
+
+/// The tasks of the event with the theme. Themes without tasks of their own
+/// have none
+fn event_tasks(theme: i64) -> &'static [Task] {
+    use TaskType::*;
+    match theme {
+        // Gambler
+        2 => &[
+            (DefeatGambler, 10, 5),
+            (SpinWheelOfFortune, 10, 5),
+            (LeaseMount, 1, 2),
+            (ConsumeThirstForAdventure, 300, 8),
+        ],
+        // Underworld figure
+        11 => &[
+            (LureHeroesIntoUnderworld, 10, 8),
+            (CollectGoldFromPit, 3, 4),
+            (ClaimSoulsFromExtractor, 3, 4),
+            (FightInDungeons, 10, 4),
+        ],
+        // Pet trainer
+        15 => &[
+            (FeedPets, 20, 8),
+            (FightOtherPets, 5, 4),
+            (FightInPetHabitat, 5, 4),
+            (ConsumeThirstForAdventure, 300, 4),
+        ],
+        // Fortress master
+        16 => &[
+            (CommandFortressBattle, 5, 8),
+            (FindGemInFortress, 2, 4),
+            (CollectWood, 5, 4),
+            (CollectStone, 5, 4),
+        ],
+        _ => &[],
+    }
+}
+
+/// The event of the world as (theme, start, end)
+async fn load_event(
+    con: &mut SqliteConnection,
+    world_id: i64,
+) -> Result<(i64, i64, i64), ServerError> {
+    let row = sqlx::query!(
+        "SELECT event_task_theme, event_task_start, event_task_end
+        FROM world WHERE world_id = $1",
+        world_id
+    )
+    .fetch_one(&mut *con)
+    .await?;
+    Ok((
+        row.event_task_theme, row.event_task_start, row.event_task_end,
+    ))
+}
+
+/// The tasks of the event, that is currently running on the world and the
+/// time at which it ends
+async fn running_event(
+    con: &mut SqliteConnection,
+    world_id: i64,
+) -> Result<(&'static [Task], i64), ServerError> {
+    let (theme, start, end) = load_event(con, world_id).await?;
+    let tasks = match (start..end).contains(&now()) {
+        true => event_tasks(theme),
+        false => &[],
+    };
+    Ok((tasks, end))
+}
+
+/// Counts the amount towards the daily and event tasks of the type
+pub(crate) async fn progress_tasks(
+    con: &mut SqliteConnection,
+    pid: i64,
+    typ: TaskType,
+    amount: i64,
+) -> Result<(), ServerError> {
+    let world_id = sqlx::query_scalar!(
+        "SELECT world_id FROM character WHERE pid = $1", pid
+    )
+    .fetch_one(&mut *con)
+    .await?;
+    let (event, event_end) = running_event(con, world_id).await?;
+
+    for (tasks, is_event, reset) in [
+        (&DAILY_TASKS[..], false, next_day()),
+        (event, true, event_end),
+    ] {
+        if !tasks.iter().any(|task| task.0 == typ) {
+            continue;
+        }
+        let typ = typ as i64;
+        let now = now();
+        sqlx::query!(
+            "INSERT INTO task (pid, is_event, typ, current, reset)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO UPDATE SET
+                current = CASE WHEN reset <= $6 THEN excluded.current
+                    ELSE current + excluded.current END,
+                reset = excluded.reset",
+            pid,
+            is_event,
+            typ,
+            amount,
+            reset,
+            now
+        )
+        .execute(&mut *con)
+        .await?;
+    }
+    Ok(())
+}
+
+/// The progress of the character towards every task of the list
+async fn load_progress(
+    con: &mut SqliteConnection,
+    pid: i64,
+    is_event: bool,
+    tasks: &[Task],
+) -> Result<Vec<i64>, ServerError> {
+    let now = now();
+    let rows = sqlx::query!(
+        "SELECT typ, current FROM task
+        WHERE pid = $1 AND is_event = $2 AND reset > $3",
+        pid,
+        is_event,
+        now
+    )
+    .fetch_all(&mut *con)
+    .await?;
+    Ok(tasks
+        .iter()
+        .map(|(typ, _, _)| {
+            rows.iter()
+                .find(|row| row.typ == *typ as i64)
+                .map_or(0, |row| row.current)
+        })
+        .collect())
+}
+
+/// The positions of the chests, that the character has already opened
+async fn load_opened_chests(
+    con: &mut SqliteConnection,
+    pid: i64,
+    is_event: bool,
+) -> Result<Vec<i64>, ServerError> {
+    let now = now();
+    Ok(sqlx::query_scalar!(
+        "SELECT chest FROM task_chest
+        WHERE pid = $1 AND is_event = $2 AND reset > $3",
+        pid,
+        is_event,
+        now
+    )
+    .fetch_all(&mut *con)
+    .await?)
+}
+
+/// The points the character has earned with the completed tasks
+fn earned_points(tasks: &[Task], progress: &[i64]) -> i64 {
+    tasks
+        .iter()
+        .zip(progress)
+        .filter(|((_, target, _), current)| **current >= *target)
+        .map(|((_, _, points), _)| points)
+        .sum()
+}
+
+/// Opens a reward chest of the daily (1) or event (2) tasks, once enough
+/// points have been earned
+pub(crate) async fn daily_task_claim(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let is_event = match args.get_int(0, "task kind")? {
+        1 => false,
+        2 => true,
+        _ => return Err(ServerError::BadRequest),
+    };
+    let chest = args.get_int(1, "chest")? - 1;
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+
+    let (tasks, chests, reset) = match is_event {
+        true => {
+            let (tasks, end) = running_event(&mut tx, session.world_id).await?;
+            (tasks, &EVENT_CHESTS, end)
+        }
+        false => (&DAILY_TASKS[..], &DAILY_CHESTS, next_day()),
+    };
+    let (required, rewards) = usize::try_from(chest)
+        .ok()
+        .and_then(|idx| chests.get(idx))
+        .ok_or(ServerError::BadRequest)?;
+    if load_opened_chests(&mut tx, pid, is_event)
+        .await?
+        .contains(&chest)
+    {
+        return Err(ServerError::BadRequest);
+    }
+    let progress = load_progress(&mut tx, pid, is_event, tasks).await?;
+    if earned_points(tasks, &progress) < *required {
+        return Err(ServerError::BadRequest);
+    }
+
+    sqlx::query!(
+        "INSERT INTO task_chest (pid, is_event, chest, reset)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO UPDATE SET reset = excluded.reset",
+        pid,
+        is_event,
+        chest,
+        reset
+    )
+    .execute(&mut *tx)
+    .await?;
+    for (typ, amount) in *rewards {
+        give_reward(&mut tx, pid, *typ, *amount).await?;
+    }
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+fn add_chests(resp: &mut ResponseBuilder, chests: &[Chest], opened: &[i64]) {
+    for (idx, (required, rewards)) in chests.iter().enumerate() {
+        resp.add_val(u8::from(opened.contains(&(idx as i64))));
+        resp.add_val(required);
+        resp.add_val(rewards.len());
+        for (typ, amount) in *rewards {
+            resp.add_val(*typ as i64);
+            resp.add_val(amount);
+        }
+    }
+}
+
+/// Writes the daily and event tasks with their progress, the reward chests
+/// and the event of the world
+pub(crate) async fn add_tasks(
+    resp: &mut ResponseBuilder,
+    con: &mut SqliteConnection,
+    pid: i64,
+    world_id: i64,
+) -> Result<(), ServerError> {
+    let (event, _) = running_event(con, world_id).await?;
+
+    let daily_progress = load_progress(con, pid, false, &DAILY_TASKS).await?;
+    resp.add_key("dailytasklist");
+    resp.add_val(98);
+    for ((typ, target, points), current) in
+        DAILY_TASKS.iter().zip(&daily_progress)
+    {
+        resp.add_val(*typ as i64);
+        resp.add_val(current);
+        resp.add_val(target);
+        resp.add_val(points);
+    }
+
+    let event_progress = load_progress(con, pid, true, event).await?;
+    resp.add_key("eventtasklist");
+    for ((typ, target, points), current) in event.iter().zip(&event_progress) {
+        resp.add_val(*typ as i64);
+        resp.add_val(current);
+        resp.add_val(target);
+        resp.add_val(points);
+    }
+
+    let opened = load_opened_chests(con, pid, false).await?;
+    resp.add_key("dailytaskrewardpreview");
+    add_chests(resp, &DAILY_CHESTS, &opened);
+
+    let opened = load_opened_chests(con, pid, true).await?;
+    resp.add_key("eventtaskrewardpreview");
+    add_chests(resp, &EVENT_CHESTS, &opened);
+
+    let (theme, start, end) = load_event(con, world_id).await?;
+    resp.add_key("eventtaskinfo");
+    resp.add_val(start);
+    resp.add_val(end);
+    resp.add_val(theme);
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use sf_api::gamestate::underworld::UnderworldUnitType;
+use sqlx::{Sqlite, SqliteConnection};
+use strum::IntoEnumIterator;
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse,
+    character_level,
+    dungeon::{TOWER, dungeon_battle},
+    fight::Fighter,
+    item::{DbItem, ItemPlace, add_items, load_items},
+    underworld::{Underworld, add_underworld_save, add_underworld_unit},
+};
+use crate::request::Session;
+
+/// The classes of the companions: Warrior, Mage, Scout
+const COMPANION_CLASSES: [i64; 3] = [1, 2, 3];
+/// The monster id of the first companion
+const COMPANION_MONSTER_ID: i64 = 390;
+/// The amount of values the client expects in the tower save
+const TOWER_SAVE_LEN: usize = 480;
+/// The amount of values each companion takes up in the tower save
+const COMPANION_SAVE_LEN: usize = 148;
+
+pub(crate) async fn player_tower_battle(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let level = args.get_int(0, "tower level")?;
+    let use_mushroom = args.get_int(1, "use mushroom").unwrap_or_default();
+
+    // Make sure the client did not get out of sync with the actual progress
+    let progress = sqlx::query_scalar!(
+        "SELECT progress FROM dungeon
+        WHERE pid = $1 AND NOT is_shadow AND dungeon = $2",
+        session.player_id,
+        TOWER
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(ServerError::BadRequest)?;
+    if progress != level {
+        return Err(ServerError::BadRequest);
+    }
+
+    dungeon_battle(session, db, TOWER, false, use_mushroom == 1).await
+}
+
+/// Gives the character the companions, that fight with it in the tower
+pub(crate) async fn unlock_companions(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<(), ServerError> {
+    for class in COMPANION_CLASSES {
+        sqlx::query!(
+            "INSERT INTO companion (pid, class) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING",
+            pid,
+            class
+        )
+        .execute(&mut *con)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Companions level up with the character and get stronger with the items
+/// they are equipped with
+fn companion_fighter(
+    level: i64,
+    class: i64,
+    equipment: &[Option<DbItem>],
+) -> Fighter {
+    let mut fighter =
+        Fighter::monster(COMPANION_MONSTER_ID + class, level, class);
+    for item in equipment.iter().flatten() {
+        for (total, bonus) in
+            fighter.attributes.iter_mut().zip(item.attributes())
+        {
+            *total += bonus;
+        }
+    }
+    fighter.max_hp = fighter.calc_hp();
+    fighter
+}
+
+/// Loads the companions of the character, that fight in the tower. Empty, if
+/// the tower has not been unlocked yet
+pub(crate) async fn load_companion_fighters(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Vec<Fighter>, ServerError> {
+    let classes = sqlx::query_scalar!(
+        "SELECT class FROM companion WHERE pid = $1 ORDER BY class", pid
+    )
+    .fetch_all(&mut *con)
+    .await?;
+    let level = character_level(con, pid).await?;
+
+    let mut fighters = Vec::new();
+    for class in classes {
+        let equipment =
+            load_items(con, pid, ItemPlace::Companion(class)).await?;
+        fighters.push(companion_fighter(level, class, &equipment));
+    }
+    Ok(fighters)
+}
+
+/// Adds the tower progress and the companions of the character. The client
+/// expects the underworld to be part of the tower save
+pub(crate) async fn add_tower(
+    resp: &mut ResponseBuilder,
+    con: &mut SqliteConnection,
+    pid: i64,
+    underworld: Option<&Underworld>,
+) -> Result<(), ServerError> {
+    let progress = sqlx::query_scalar!(
+        "SELECT progress FROM dungeon
+        WHERE pid = $1 AND NOT is_shadow AND dungeon = $2",
+        pid,
+        TOWER
+    )
+    .fetch_optional(&mut *con)
+    .await?;
+    resp.add_key("owntowerlevel");
+    resp.add_val(progress.unwrap_or_default());
+
+    if progress.is_none() && underworld.is_none() {
+        return Ok(());
+    }
+
+    let level = character_level(con, pid).await?;
+    resp.add_key("owntower");
+    for _ in 0..3 {
+        resp.add_val(0);
+    }
+    for (class, unit) in COMPANION_CLASSES
+        .into_iter()
+        .zip(UnderworldUnitType::iter())
+    {
+        if progress.is_some() {
+            let equipment =
+                load_items(con, pid, ItemPlace::Companion(class)).await?;
+            let fighter = companion_fighter(level, class, &equipment);
+
+            resp.add_val(level);
+            for _ in 1..4 {
+                resp.add_val(0);
+            }
+            for attr in fighter.attributes {
+                resp.add_val(attr);
+            }
+            for _ in 9..22 {
+                resp.add_val(0);
+            }
+            add_items(resp, &equipment);
+        } else {
+            for _ in 0..142 {
+                resp.add_val(0);
+            }
+        }
+        resp.add_val(0); // 142
+        add_underworld_unit(resp, underworld, unit); // 143..=146
+        for _ in 147..COMPANION_SAVE_LEN {
+            resp.add_val(0);
+        }
+    }
+    resp.add_val(0); // 447
+    add_underworld_save(resp, underworld); // 448..=475
+    for _ in 476..TOWER_SAVE_LEN {
+        resp.add_val(0);
+    }
+    Ok(())
+}
@@ -0,0 +1,918 @@
+use std::fmt::Write;
+
+use fastrand::Rng;
+use log::error;
+use num_traits::FromPrimitive;
+use sf_api::gamestate::{
+    underworld::{
+        UnderWorldResourceType, UnderworldBuildingType, UnderworldUnitType,
+    },
+    unlockables::HabitatType,
+};
+use sqlx::{Sqlite, SqliteConnection};
+use strum::IntoEnumIterator;
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse, add_xp,
+    character_level,
+    fight::{Fighter, add_fight, load_player_fighter, simulate_team_fight},
+    fortress::skip_with_mushrooms,
+    mail::{MAIL_NORMAL, send_system_mail},
+    next_day, now,
+    pets::find_pet,
+    player::MAX_THIRST,
+    poll,
+    task::{TaskType, progress_tasks},
+    xp_for_next_level,
+};
+use crate::request::Session;
+
+/// The highest level any building can be upgraded to
+const MAX_BUILDING_LEVEL: i64 = 15;
+/// The amount of buildings an underworld has
+const BUILDING_COUNT: usize = 10;
+/// The amount of hours the gold pit and soul extractor can produce, before
+/// they are full
+const PRODUCTION_HOURS: i64 = 12;
+/// The amount of souls, that can be saved per level of the heart of darkness
+const SOUL_STORAGE: i64 = 1000;
+/// The amount of heroes, that can be lured each day
+const LURES_PER_DAY: i64 = 5;
+/// The honor gained for defeating a lured hero
+const LURE_HONOR: i64 = 10;
+/// The percentage of the experience needed for the next level, that each
+/// level of the torture chamber grants for defeating a lured hero
+const TORTURE_XP_PERCENT: i64 = 1;
+/// The percentage each level of the gladiator trainer increases the damage
+/// of the units by
+const GLADIATOR_BONUS: i64 = 5;
+/// The level of a unit, that has never been upgraded
+const UNIT_BASE_LEVEL: i64 = 10;
+/// The levels a unit gains per upgrade
+const LEVELS_PER_UPGRADE: i64 = 5;
+/// The monster id of the first unit type
+const UNIT_MONSTER_ID: i64 = 910;
+/// All resources in the order they are stored in
+const RESOURCES: [UnderWorldResourceType; 3] = [
+    UnderWorldResourceType::Silver,
+    UnderWorldResourceType::Souls,
+    UnderWorldResourceType::ThirstForAdventure,
+];
+
+/// The time, silver and souls it costs to upgrade a building to level 1
+const BASE_PRICES: [[i64; 3]; BUILDING_COUNT] = [
+    [3600, 5000, 50],
+    [1800, 2000, 100],
+    [1800, 3000, 50],
+    [1800, 1500, 0],
+    [1800, 1000, 100],
+    [3600, 4000, 150],
+    [3600, 6000, 200],
+    [3600, 3000, 200],
+    [3600, 5000, 250],
+    [7200, 10000, 500],
+];
+
+/// The time, silver and souls an upgrade costs
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct UnderworldPrice {
+    pub time: i64,
+    pub silver: i64,
+    pub souls: i64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Underworld {
+    /// The level of every building, indexed by `UnderworldBuildingType`
+    pub levels: [i64; BUILDING_COUNT],
+    pub souls: i64,
+    /// Produced, but not yet collected silver, souls and thirst for
+    /// adventure
+    pub stored: [i64; 3],
+    pub produced_until: i64,
+    pub upgrade: Option<UnderworldBuildingType>,
+    pub upgrade_began: i64,
+    pub upgrade_finish: i64,
+    /// The amount of upgrades of every unit, indexed by `UnderworldUnitType`
+    pub unit_upgrades: [i64; 3],
+    pub honor: i64,
+    pub lured_today: i64,
+    pub lures_reset: i64,
+}
+
+impl Underworld {
+    pub(crate) fn level(&self, building: UnderworldBuildingType) -> i64 {
+        self.levels[building as usize]
+    }
+
+    /// The price to upgrade the building to its next level
+    pub(crate) fn upgrade_price(
+        &self,
+        building: UnderworldBuildingType,
+    ) -> UnderworldPrice {
+        let next = self.level(building) + 1;
+        let [time, silver, souls] = BASE_PRICES[building as usize];
+        UnderworldPrice {
+            time: time * next,
+            silver: silver * next * next,
+            souls: souls * next * next,
+        }
+    }
+
+    /// The maximum amount of souls, that can be saved
+    pub(crate) fn soul_limit(&self) -> i64 {
+        SOUL_STORAGE * self.level(UnderworldBuildingType::HeartOfDarkness)
+    }
+
+    /// The amount of units of the type, that fight lured heroes
+    pub(crate) fn unit_count(&self, unit: UnderworldUnitType) -> i64 {
+        let level = self.level(unit_building(unit));
+        match unit {
+            UnderworldUnitType::Goblin => level.min(5),
+            UnderworldUnitType::Troll => level.min(4),
+            UnderworldUnitType::Keeper => level.min(1),
+        }
+    }
+
+    pub(crate) fn unit_level(&self, unit: UnderworldUnitType) -> i64 {
+        UNIT_BASE_LEVEL + self.unit_upgrades[unit as usize] * LEVELS_PER_UPGRADE
+    }
+
+    /// The price to upgrade the unit once
+    pub(crate) fn unit_upgrade_price(
+        &self,
+        unit: UnderworldUnitType,
+    ) -> UnderworldPrice {
+        let next = self.unit_level(unit) + LEVELS_PER_UPGRADE;
+        UnderworldPrice {
+            time: 0,
+            silver: next * next * 10,
+            souls: next * 5,
+        }
+    }
+
+    /// A single unit of the type with the bonus of the gladiator trainer
+    pub(crate) fn unit_fighter(&self, unit: UnderworldUnitType) -> Fighter {
+        let class = match unit {
+            UnderworldUnitType::Goblin => 3,
+            UnderworldUnitType::Troll => 1,
+            UnderworldUnitType::Keeper => 2,
+        };
+        let mut fighter = Fighter::monster(
+            UNIT_MONSTER_ID + unit as i64,
+            self.unit_level(unit),
+            class,
+        );
+        let trainer = self.level(UnderworldBuildingType::GladiatorTrainer);
+        fighter.add_damage_bonus(trainer * GLADIATOR_BONUS);
+        fighter
+    }
+
+    /// The amount produced per hour. Thirst for adventure is produced per
+    /// day instead
+    pub(crate) fn per_hour(&self, resource: UnderWorldResourceType) -> i64 {
+        let level = self.level(production_building(resource));
+        let per_level = match resource {
+            UnderWorldResourceType::Silver => 500,
+            UnderWorldResourceType::Souls => 20,
+            UnderWorldResourceType::ThirstForAdventure => 10 * 60,
+        };
+        per_level * level
+    }
+
+    /// The amount of a resource the production building can hold
+    pub(crate) fn production_limit(
+        &self,
+        resource: UnderWorldResourceType,
+    ) -> i64 {
+        match resource {
+            UnderWorldResourceType::ThirstForAdventure => {
+                self.per_hour(resource)
+            }
+            _ => self.per_hour(resource) * PRODUCTION_HOURS,
+        }
+    }
+
+    /// The amount of a resource, that can be collected at the given time
+    pub(crate) fn collectable(
+        &self,
+        resource: UnderWorldResourceType,
+        time: i64,
+    ) -> i64 {
+        let elapsed = (time - self.produced_until).max(0);
+        let produced = match resource {
+            UnderWorldResourceType::ThirstForAdventure => {
+                self.per_hour(resource) * elapsed / 86400
+            }
+            _ => self.per_hour(resource) * elapsed / 3600,
+        };
+        (self.stored[resource as usize] + produced)
+            .min(self.production_limit(resource))
+    }
+
+    /// Adds everything produced up to the given time to the stored resources
+    fn produce(&mut self, time: i64) {
+        for resource in RESOURCES {
+            self.stored[resource as usize] = self.collectable(resource, time);
+        }
+        self.produced_until = self.produced_until.max(time);
+    }
+
+    /// Produces resources up to now, finishes the upgrade, if it is done and
+    /// resets the lures of the day
+    pub(crate) fn update(&mut self) {
+        if let Some(building) = self.upgrade
+            && self.upgrade_finish <= now()
+        {
+            self.produce(self.upgrade_finish);
+            self.finish_upgrade(building);
+        }
+        self.produce(now());
+        if self.lures_reset <= now() {
+            self.lured_today = 0;
+            self.lures_reset = next_day();
+        }
+    }
+
+    fn finish_upgrade(&mut self, building: UnderworldBuildingType) {
+        self.levels[building as usize] += 1;
+        self.upgrade = None;
+        self.upgrade_began = 0;
+        self.upgrade_finish = 0;
+    }
+
+    /// Checks if the building can be upgraded right now, ignoring its price
+    fn can_upgrade(&self, building: UnderworldBuildingType) -> bool {
+        let heart = self.level(UnderworldBuildingType::HeartOfDarkness);
+        let level = self.level(building);
+        if self.upgrade.is_some() || level >= MAX_BUILDING_LEVEL {
+            return false;
+        }
+        if building != UnderworldBuildingType::HeartOfDarkness && level >= heart
+        {
+            return false;
+        }
+        // Every unit needs the units before it
+        let required = match building {
+            UnderworldBuildingType::TrollBlock => {
+                Some(UnderworldBuildingType::GoblinPit)
+            }
+            UnderworldBuildingType::Keeper => {
+                Some(UnderworldBuildingType::TrollBlock)
+            }
+            _ => None,
+        };
+        required.is_none_or(|a| self.level(a) > 0)
+    }
+}
+
+/// The building, that houses the unit
+fn unit_building(unit: UnderworldUnitType) -> UnderworldBuildingType {
+    match unit {
+        UnderworldUnitType::Goblin => UnderworldBuildingType::GoblinPit,
+        UnderworldUnitType::Troll => UnderworldBuildingType::TrollBlock,
+        UnderworldUnitType::Keeper => UnderworldBuildingType::Keeper,
+    }
+}
+
+/// The building, that produces the resource
+fn production_building(
+    resource: UnderWorldResourceType,
+) -> UnderworldBuildingType {
+    match resource {
+        UnderWorldResourceType::Silver => UnderworldBuildingType::GoldPit,
+        UnderWorldResourceType::Souls => UnderworldBuildingType::SoulExtractor,
+        UnderWorldResourceType::ThirstForAdventure => {
+            UnderworldBuildingType::Adventuromatic
+        }
+    }
+}
+
+/// Loads the underworld of the character, if it has been unlocked
+pub(crate) async fn load_underworld(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Option<Underworld>, ServerError> {
+    let Some(row) =
+        sqlx::query!("SELECT * FROM underworld WHERE pid = $1", pid)
+            .fetch_optional(&mut *con)
+            .await?
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(Underworld {
+        levels: [
+            row.heart_of_darkness, row.gate, row.gold_pit, row.soul_extractor,
+            row.goblin_pit, row.torture_chamber, row.gladiator_trainer,
+            row.troll_block, row.adventuromatic, row.keeper,
+        ],
+        souls: row.souls,
+        stored: [row.stored_silver, row.stored_souls, row.stored_thirst],
+        produced_until: row.produced_until,
+        upgrade: row
+            .upgrade_building
+            .and_then(UnderworldBuildingType::from_i64),
+        upgrade_began: row.upgrade_began,
+        upgrade_finish: row.upgrade_finish,
+        unit_upgrades: [
+            row.goblin_upgrades, row.troll_upgrades, row.keeper_upgrades,
+        ],
+        honor: row.honor,
+        lured_today: row.lured_today,
+        lures_reset: row.lures_reset,
+    }))
+}
+
+async fn store_underworld(
+    con: &mut SqliteConnection,
+    pid: i64,
+    underworld: &Underworld,
+) -> Result<(), ServerError> {
+    let [
+        heart,
+        gate,
+        gold_pit,
+        soul_extractor,
+        goblin_pit,
+        torture_chamber,
+        gladiator_trainer,
+        troll_block,
+        adventuromatic,
+        keeper,
+    ] = underworld.levels;
+    let [stored_silver, stored_souls, stored_thirst] = underworld.stored;
+    let [goblin_upgrades, troll_upgrades, keeper_upgrades] =
+        underworld.unit_upgrades;
+    let upgrade = underworld.upgrade.map(|a| a as i64);
+
+    sqlx::query!(
+        "UPDATE underworld
+        SET heart_of_darkness = $2, gate = $3, gold_pit = $4,
+            soul_extractor = $5, goblin_pit = $6, torture_chamber = $7,
+            gladiator_trainer = $8, troll_block = $9, adventuromatic = $10,
+            keeper = $11, souls = $12, stored_silver = $13,
+            stored_souls = $14, stored_thirst = $15, produced_until = $16,
+            upgrade_building = $17, upgrade_began = $18,
+            upgrade_finish = $19, goblin_upgrades = $20,
+            troll_upgrades = $21, keeper_upgrades = $22, honor = $23,
+            lured_today = $24, lures_reset = $25
+        WHERE pid = $1",
+        pid,
+        heart,
+        gate,
+        gold_pit,
+        soul_extractor,
+        goblin_pit,
+        torture_chamber,
+        gladiator_trainer,
+        troll_block,
+        adventuromatic,
+        keeper,
+        underworld.souls,
+        stored_silver,
+        stored_souls,
+        stored_thirst,
+        underworld.produced_until,
+        upgrade,
+        underworld.upgrade_began,
+        underworld.upgrade_finish,
+        goblin_upgrades,
+        troll_upgrades,
+        keeper_upgrades,
+        underworld.honor,
+        underworld.lured_today,
+        underworld.lures_reset,
+    )
+    .execute(&mut *con)
+    .await?;
+    Ok(())
+}
+
+/// Unlocks the underworld of the character. Returns false, if it has
+/// already been unlocked
+pub(crate) async fn unlock_underworld(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<bool, ServerError> {
+    let time = now();
+    let res = sqlx::query!(
+        "INSERT INTO underworld (pid, produced_until) VALUES ($1, $2)
+        ON CONFLICT DO NOTHING",
+        pid,
+        time
+    )
+    .execute(&mut *con)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Loads the underworld with everything produced up to now
+async fn update_underworld(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<Underworld, ServerError> {
+    let mut underworld = load_underworld(con, pid)
+        .await?
+        .ok_or(ServerError::BadRequest)?;
+    underworld.update();
+    Ok(underworld)
+}
+
+/// Adds souls to the underworld of the character, as far as the heart of
+/// darkness can hold them. Characters without an underworld get nothing
+pub(crate) async fn add_souls(
+    con: &mut SqliteConnection,
+    pid: i64,
+    souls: i64,
+) -> Result<(), ServerError> {
+    let Some(mut underworld) = load_underworld(con, pid).await? else {
+        return Ok(());
+    };
+    underworld.update();
+    let limit = underworld.soul_limit().max(underworld.souls);
+    underworld.souls = (underworld.souls + souls).min(limit);
+    store_underworld(con, pid, &underworld).await
+}
+
+fn building_arg(
+    args: &CommandArguments<'_>,
+) -> Result<UnderworldBuildingType, ServerError> {
+    let building = args.get_int(0, "building")?;
+    UnderworldBuildingType::from_i64(building - 1)
+        .ok_or(ServerError::BadRequest)
+}
+
+pub(crate) async fn underworld_build_start(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let building = building_arg(&args)?;
+    let mut tx = db.begin().await?;
+    let mut underworld = update_underworld(&mut tx, session.player_id).await?;
+
+    if !underworld.can_upgrade(building) {
+        return Err(ServerError::BadRequest);
+    }
+    let price = underworld.upgrade_price(building);
+    if underworld.souls < price.souls {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    let silver = sqlx::query_scalar!(
+        "UPDATE character SET silver = silver - $2
+        WHERE pid = $1 RETURNING silver",
+        session.player_id,
+        price.silver
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if silver < 0 {
+        return Err(ServerError::NotEnoughMoney);
+    }
+
+    underworld.souls -= price.souls;
+    underworld.upgrade = Some(building);
+    underworld.upgrade_began = now();
+    underworld.upgrade_finish = now() + price.time;
+    store_underworld(&mut tx, session.player_id, &underworld).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Cancels the current upgrade and refunds its price
+pub(crate) async fn underworld_build_stop(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let building = building_arg(&args)?;
+    let mut tx = db.begin().await?;
+    let mut underworld = update_underworld(&mut tx, session.player_id).await?;
+
+    if underworld.upgrade != Some(building) {
+        return Err(ServerError::BadRequest);
+    }
+    let price = underworld.upgrade_price(building);
+    sqlx::query!(
+        "UPDATE character SET silver = silver + $2 WHERE pid = $1",
+        session.player_id, price.silver
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    underworld.souls += price.souls;
+    underworld.upgrade = None;
+    underworld.upgrade_began = 0;
+    underworld.upgrade_finish = 0;
+    store_underworld(&mut tx, session.player_id, &underworld).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Finishes the current upgrade. Upgrades, that are not done yet, can be
+/// finished with mushrooms
+pub(crate) async fn underworld_build_finished(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let building = building_arg(&args)?;
+    let use_mushrooms = args.get_int(1, "mushrooms").unwrap_or_default();
+    let mut tx = db.begin().await?;
+    let mut underworld = update_underworld(&mut tx, session.player_id).await?;
+
+    // Updating the underworld already finished the upgrade, if it is done
+    if underworld.upgrade == Some(building) {
+        if use_mushrooms <= 0 {
+            return Err(ServerError::StillBusy);
+        }
+        skip_with_mushrooms(
+            &mut tx, session.player_id, underworld.upgrade_finish,
+        )
+        .await?;
+        underworld.finish_upgrade(building);
+    }
+    store_underworld(&mut tx, session.player_id, &underworld).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Collects the resource from its production building. Souls can only be
+/// collected until the storage is full
+pub(crate) async fn underworld_gather(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let resource = match args.get_int(0, "resource")? {
+        1 => UnderWorldResourceType::Silver,
+        2 => UnderWorldResourceType::Souls,
+        3 => UnderWorldResourceType::ThirstForAdventure,
+        _ => return Err(ServerError::BadRequest),
+    };
+    let mut tx = db.begin().await?;
+    let mut underworld = update_underworld(&mut tx, session.player_id).await?;
+
+    let limit = underworld.soul_limit();
+    let stored = &mut underworld.stored[resource as usize];
+    match resource {
+        UnderWorldResourceType::Silver => {
+            sqlx::query!(
+                "UPDATE character SET silver = silver + $2 WHERE pid = $1",
+                session.player_id, *stored
+            )
+            .execute(&mut *tx)
+            .await?;
+            *stored = 0;
+            let gold = TaskType::CollectGoldFromPit;
+            progress_tasks(&mut tx, session.player_id, gold, 1).await?;
+        }
+        UnderWorldResourceType::Souls => {
+            let amount = (*stored).min(limit - underworld.souls).max(0);
+            underworld.souls += amount;
+            *stored -= amount;
+            let souls = TaskType::ClaimSoulsFromExtractor;
+            progress_tasks(&mut tx, session.player_id, souls, 1).await?;
+        }
+        UnderWorldResourceType::ThirstForAdventure => {
+            let tfa = sqlx::query_scalar!(
+                "SELECT tfa FROM tavern WHERE pid = $1", session.player_id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            let amount = (*stored).min(MAX_THIRST - tfa).max(0);
+            sqlx::query!(
+                "UPDATE tavern SET tfa = tfa + $2 WHERE pid = $1",
+                session.player_id, amount
+            )
+            .execute(&mut *tx)
+            .await?;
+            *stored -= amount;
+        }
+    }
+    store_underworld(&mut tx, session.player_id, &underworld).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Upgrades the unit by one step. Units can not get stronger than the
+/// character
+pub(crate) async fn underworld_upgrade_unit(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let unit = match args.get_int(0, "unit")? {
+        1 => UnderworldUnitType::Goblin,
+        2 => UnderworldUnitType::Troll,
+        3 => UnderworldUnitType::Keeper,
+        _ => return Err(ServerError::BadRequest),
+    };
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+    let mut underworld = update_underworld(&mut tx, pid).await?;
+
+    let next_level = underworld.unit_level(unit) + LEVELS_PER_UPGRADE;
+    if underworld.unit_count(unit) == 0
+        || next_level > character_level(&mut tx, pid).await?
+    {
+        return Err(ServerError::BadRequest);
+    }
+    let price = underworld.unit_upgrade_price(unit);
+    if underworld.souls < price.souls {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    let silver = sqlx::query_scalar!(
+        "UPDATE character SET silver = silver - $2
+        WHERE pid = $1 RETURNING silver",
+        pid,
+        price.silver
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if silver < 0 {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    underworld.souls -= price.souls;
+    underworld.unit_upgrades[unit as usize] += 1;
+    store_underworld(&mut tx, pid, &underworld).await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Lures the hero of another character into the underworld, where it has to
+/// fight all units one after the other. Defeating the hero grants honor and
+/// experience from the torture chamber
+pub(crate) async fn underworld_attack(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let target = args.get_int(0, "player id")?;
+    let pid = session.player_id;
+    if target == pid {
+        return Err(ServerError::BadRequest);
+    }
+    let mut tx = db.begin().await?;
+    let mut underworld = update_underworld(&mut tx, pid).await?;
+
+    if underworld.level(UnderworldBuildingType::Gate) == 0 {
+        return Err(ServerError::BadRequest);
+    }
+    if underworld.lured_today >= LURES_PER_DAY {
+        return Err(ServerError::StillBusy);
+    }
+    let same_world = sqlx::query_scalar!(
+        "SELECT pid FROM character WHERE pid = $1 AND world_id = $2", target,
+        session.world_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if same_world.is_none() {
+        return Err(ServerError::BadRequest);
+    }
+
+    let mut units = Vec::new();
+    for unit in UnderworldUnitType::iter() {
+        for _ in 0..underworld.unit_count(unit) {
+            units.push(underworld.unit_fighter(unit));
+        }
+    }
+    if units.is_empty() {
+        return Err(ServerError::BadRequest);
+    }
+    let hero = [load_player_fighter(&mut tx, target).await?];
+    let (fights, won) = simulate_team_fight(&units, &hero);
+
+    let mut resp = ResponseBuilder::default();
+    match fights.as_slice() {
+        [(a, b, log)] => add_fight(&mut resp, None, 0, a, b, log),
+        fights => {
+            for (idx, (a, b, log)) in fights.iter().enumerate() {
+                add_fight(&mut resp, Some(idx + 1), 0, a, b, log);
+            }
+        }
+    }
+    resp.add_key("fightversion");
+    resp.add_val(1);
+
+    underworld.lured_today += 1;
+    let lure = TaskType::LureHeroesIntoUnderworld;
+    progress_tasks(&mut tx, pid, lure, 1).await?;
+    if won {
+        underworld.honor += LURE_HONOR;
+        let level = character_level(&mut tx, pid).await?;
+        let torture = underworld.level(UnderworldBuildingType::TortureChamber);
+        let xp = xp_for_next_level(level) * torture * TORTURE_XP_PERCENT / 100;
+        add_xp(&mut tx, pid, xp).await?;
+        find_pet(&mut tx, pid, HabitatType::Water, &mut Rng::new()).await?;
+    }
+    store_underworld(&mut tx, pid, &underworld).await?;
+
+    let name =
+        sqlx::query_scalar!("SELECT name FROM character WHERE pid = $1", pid)
+            .fetch_one(&mut *tx)
+            .await?;
+    let title = match won {
+        true => format!("{name} has defeated your hero in the underworld"),
+        false => format!("Your hero has escaped the underworld of {name}"),
+    };
+    send_system_mail(&mut tx, target, MAIL_NORMAL, &title, "").await?;
+
+    tx.commit().await?;
+    poll(session, "", db, resp).await
+}
+
+pub(crate) async fn underworld_get_hof(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let rank = args.get_int(0, "rank").unwrap_or_default();
+    let pre = args.get_int(2, "pre").unwrap_or_default();
+    let post = args.get_int(3, "post").unwrap_or_default();
+
+    let rank = match rank {
+        1.. => rank,
+        _ => {
+            let name = args.get_str(1, "name")?;
+            let pid = sqlx::query_scalar!(
+                "SELECT pid FROM character WHERE name = $1 AND world_id = $2",
+                name, session.world_id
+            )
+            .fetch_optional(db)
+            .await?
+            .ok_or(ServerError::BadRequest)?;
+            underworld_rank(&mut *db.acquire().await?, pid).await?
+        }
+    };
+
+    let offset = (rank - pre).max(1) - 1;
+    let limit = (pre + post).min(30);
+
+    let res = sqlx::query!(
+        "SELECT c.name, coalesce(g.name, '') as `guild!: String`, u.honor,
+            u.heart_of_darkness + u.gate + u.gold_pit + u.soul_extractor
+            + u.goblin_pit + u.torture_chamber + u.gladiator_trainer
+            + u.troll_block + u.adventuromatic + u.keeper
+            as `upgrades!: i64`
+        FROM underworld u
+        JOIN character c ON c.pid = u.pid
+        LEFT JOIN guild_member gm ON gm.pid = c.pid
+        LEFT JOIN guild g ON g.id = gm.guild_id
+        WHERE c.world_id = $3
+        ORDER BY u.honor DESC, u.pid ASC
+        LIMIT $2 OFFSET $1",
+        offset,
+        limit,
+        session.world_id,
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut underworlds = String::new();
+    for (entry_idx, underworld) in res.into_iter().enumerate() {
+        underworlds
+            .write_fmt(format_args!(
+                "{},{},{},{},{},0;",
+                offset + entry_idx as i64 + 1,
+                underworld.name,
+                underworld.guild,
+                underworld.upgrades,
+                underworld.honor,
+            ))
+            .map_err(|e| {
+                error!("Error while writing format: {e:?}");
+                ServerError::Internal
+            })?;
+    }
+
+    ResponseBuilder::default()
+        .add_key("ranklistunderworld")
+        .add_str(&underworlds)
+        .build()
+}
+
+/// The rank of the underworld in the underworld hall of fame of its world
+async fn underworld_rank(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<i64, ServerError> {
+    Ok(sqlx::query_scalar!(
+        "WITH own AS (
+            SELECT u.honor, c.world_id FROM underworld u
+            JOIN character c ON c.pid = u.pid
+            WHERE u.pid = $1
+        )
+        SELECT count(*) FROM underworld u
+        JOIN character c ON c.pid = u.pid
+        WHERE c.world_id = (SELECT world_id FROM own)
+            AND (u.honor > (SELECT honor FROM own)
+                OR (u.honor = (SELECT honor FROM own) AND u.pid <= $1))",
+        pid
+    )
+    .fetch_one(&mut *con)
+    .await?)
+}
+
+/// Writes the upgrades, count, total attributes and level of the unit, that
+/// are part of the companion block in the tower save
+pub(crate) fn add_underworld_unit(
+    resp: &mut ResponseBuilder,
+    underworld: Option<&Underworld>,
+    unit: UnderworldUnitType,
+) {
+    let Some(underworld) = underworld else {
+        for _ in 0..4 {
+            resp.add_val(0);
+        }
+        return;
+    };
+    let fighter = underworld.unit_fighter(unit);
+    resp.add_val(underworld.unit_upgrades[unit as usize]);
+    resp.add_val(underworld.unit_count(unit));
+    resp.add_val(fighter.attributes.iter().sum::<i64>());
+    resp.add_val(fighter.level);
+}
+
+/// Writes the part of the tower save from the building levels at index 448
+/// to the thirst for adventure production at 475
+pub(crate) fn add_underworld_save(
+    resp: &mut ResponseBuilder,
+    underworld: Option<&Underworld>,
+) {
+    let Some(underworld) = underworld else {
+        for _ in 448..476 {
+            resp.add_val(0);
+        }
+        return;
+    };
+    let time = now();
+    for level in underworld.levels {
+        resp.add_val(level); // 448..=457
+    }
+    resp.add_val(0); // 458
+
+    let souls = UnderWorldResourceType::Souls;
+    resp.add_val(underworld.collectable(souls, time)); // 459
+    resp.add_val(underworld.production_limit(souls)); // 460
+    resp.add_val(underworld.soul_limit()); // 461
+    resp.add_val(0); // 462
+    resp.add_val(underworld.per_hour(souls)); // 463
+
+    let silver = UnderWorldResourceType::Silver;
+    resp.add_val(underworld.collectable(silver, time)); // 464
+    resp.add_val(underworld.production_limit(silver)); // 465
+    resp.add_val(underworld.per_hour(silver)); // 466
+
+    resp.add_val(time); // 467 collectable updated
+    resp.add_val(underworld.upgrade.map_or(0, |a| a as i64 + 1)); // 468
+    resp.add_val(underworld.upgrade_finish); // 469
+    resp.add_val(underworld.upgrade_began); // 470
+    resp.add_val(underworld.honor); // 471
+    resp.add_val(underworld.lured_today); // 472
+
+    let thirst = UnderWorldResourceType::ThirstForAdventure;
+    resp.add_val(underworld.collectable(thirst, time)); // 473
+    resp.add_val(underworld.production_limit(thirst)); // 474
+    resp.add_val(underworld.per_hour(thirst)); // 475
+}
+
+/// Writes the price of the next upgrade of every building and unit
+pub(crate) fn add_underworld_prices(
+    resp: &mut ResponseBuilder,
+    underworld: Option<&Underworld>,
+) {
+    resp.add_key("underworldprice");
+    for building in UnderworldBuildingType::iter() {
+        let price = underworld
+            .map(|a| a.upgrade_price(building))
+            .unwrap_or_default();
+        resp.add_val(price.time);
+        resp.add_val(price.silver);
+        resp.add_val(price.souls);
+    }
+
+    resp.add_key("underworldupgradeprice");
+    for unit in UnderworldUnitType::iter() {
+        let Some(underworld) = underworld else {
+            for _ in 0..3 {
+                resp.add_val(0);
+            }
+            continue;
+        };
+        let price = underworld.unit_upgrade_price(unit);
+        resp.add_val(underworld.unit_level(unit) + LEVELS_PER_UPGRADE);
+        resp.add_val(price.silver);
+        resp.add_val(price.souls);
+    }
+
+    resp.add_key("underworldmaxsouls");
+    resp.add_val(underworld.map_or(0, |a| a.soul_limit()));
+}
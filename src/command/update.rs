@@ -1,12 +1,40 @@
 use sf_api::{
-    command::AttributeType, gamestate::items::EquipmentSlot, misc::to_sf_string,
+    command::AttributeType,
+    gamestate::fortress::{FortressResourceType, FortressUnitType},
+    misc::to_sf_string,
 };
 use sqlx::Sqlite;
 use strum::IntoEnumIterator;
 
 use super::{
-    ResponseBuilder, ServerError, ServerResponse, effective_mount,
-    get_debug_value_default, in_seconds, item::add_debug_item, now,
+    DRAGON_GOLD_BONUS, ResponseBuilder, ServerError, ServerResponse,
+    blacksmith::{add_smith, load_blacksmith},
+    calendar::{add_calendar_info, load_calendar},
+    dungeon::{add_dungeon_progress, load_dungeon_progress},
+    effective_mount,
+    fight::load_attributes,
+    fortress::{
+        MAX_BUILDING_LEVEL, REROLL_PRICE, add_fortress_prices,
+        add_fortress_save, add_unit_prices, fortress_rank, load_fortress,
+    },
+    friend::add_friend_list,
+    get_debug_value_default,
+    guild::{
+        add_guild_save, fought_today, portal_damage_bonus,
+        treasure_silver_bonus, update_guild,
+    },
+    in_seconds,
+    item::{ItemPlace, add_debug_item, add_items, load_equipment, load_items},
+    mail::add_mailbox,
+    mounted_quest_length, mounted_quest_silver, next_day, now,
+    pets::{MAX_PET_LEVEL, add_pets, load_pets},
+    player::guard_wage,
+    potion::{add_potions, load_potions},
+    scrapbook::{add_scrapbook, load_album},
+    task::add_tasks,
+    tower::add_tower,
+    underworld::{add_underworld_prices, load_underworld},
+    witch::{EnchantmentEffects, add_witch, enchantment_price},
     xp_for_next_level,
 };
 use crate::{SERVER_VERSION, request::Session};
@@ -25,6 +53,23 @@ pub(crate) async fn poll(
         .add_val(0)
         .skip_key();
 
+    let mut tx = db.begin().await?;
+    let last_active = now();
+    sqlx::query!(
+        "UPDATE session SET last_active = $1 WHERE crypto_id = $2",
+        last_active, session.crypto_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE guild_member SET last_active = $1 WHERE pid = $2", last_active,
+        session.player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    update_guild(resp, &mut tx, session.player_id).await?;
+    tx.commit().await?;
+
     let char = sqlx::query!(
         "SELECT
         character.pid, --0
@@ -83,6 +128,7 @@ pub(crate) async fn poll(
 
         tavern.tfa,
         tavern.Beer_Drunk,
+        tavern.beer_reset,
 
         Tutorial_Status,
 
@@ -92,12 +138,28 @@ pub(crate) async fn poll(
         character.mushrooms,
         character.silver,
         tavern.QuickSand, -- 50
+        tavern.lucky_coins,
+        tavern.wheel_spins_today,
+        tavern.wheel_reset,
+        tavern.wheel_next_free_spin,
 
         description,
         character.name,
 
         portrait.influencer,
 
+        character.dungeon_timer,
+        guild_member.joined as guild_joined,
+        guild_member.hydra_fought,
+        guild_member.hydra_last_fought,
+        (
+        SELECT demon_portal_act
+        FROM guild
+        WHERE guild.id = guild_member.guild_id
+        ) as demon_portal_act,
+        guild_upgrade.treasure,
+        guild_upgrade.instructor,
+
         (
         SELECT count(*)
         FROM CHARACTER AS x
@@ -116,6 +178,8 @@ pub(crate) async fn poll(
          NATURAL JOIN activity
          NATURAL JOIN tavern
          NATURAL JOIN portrait
+         NATURAL JOIN guild_upgrade
+         LEFT JOIN guild_member on guild_member.pid = character.pid
          JOIN quest as q1 on tavern.quest1 = q1.id
          JOIN quest as q2 on tavern.quest2 = q2.id
          JOIN quest as q3 on tavern.quest2 = q3.id
@@ -125,17 +189,46 @@ pub(crate) async fn poll(
     .fetch_one(db)
     .await?;
 
-    let calendar_info = "12/1/8/1/3/1/25/1/5/1/2/1/3/2/1/1/24/1/18/5/6/1/22/1/\
-                         7/1/6/2/8/2/22/2/5/2/2/2/3/3/21/1";
-
-    resp.add_key("messagelist.r");
-    resp.add_str(";");
+    // The save shows everything produced up to now
+    let mut fortress =
+        load_fortress(&mut *db.acquire().await?, session.player_id).await?;
+    if let Some(fortress) = &mut fortress {
+        fortress.update();
+    }
+    let fortress = fortress.as_ref();
+    let fortress_rank = match fortress {
+        Some(_) => {
+            fortress_rank(&mut *db.acquire().await?, session.player_id).await?
+        }
+        None => 0,
+    };
+    let mut underworld =
+        load_underworld(&mut *db.acquire().await?, session.player_id).await?;
+    if let Some(underworld) = &mut underworld {
+        underworld.update();
+    }
+    let underworld = underworld.as_ref();
+    let mut pets =
+        load_pets(&mut *db.acquire().await?, session.player_id).await?;
+    if let Some(pets) = &mut pets {
+        pets.update();
+    }
+    let pets = pets.as_ref();
+    let album =
+        load_album(&mut *db.acquire().await?, session.player_id).await?;
+    let calendar =
+        load_calendar(&mut *db.acquire().await?, session.player_id).await?;
+    let potions =
+        load_potions(&mut *db.acquire().await?, session.player_id).await?;
+    let smith =
+        load_blacksmith(&mut *db.acquire().await?, session.player_id).await?;
+
+    add_mailbox(resp, &mut *db.acquire().await?, session.player_id).await?;
 
     resp.add_key("combatloglist.s");
     resp.add_str(";");
 
-    resp.add_key("friendlist.r");
-    resp.add_str(";");
+    add_friend_list(resp, &mut *db.acquire().await?, session.player_id).await?;
 
     resp.add_key("login count");
     resp.add_val(session.login_count);
@@ -155,10 +248,9 @@ pub(crate) async fn poll(
     resp.add_key("languagecodelist.r");
 
     resp.add_key("maxpetlevel");
-    resp.add_val(100);
+    resp.add_val(MAX_PET_LEVEL);
 
-    resp.add_key("calenderinfo");
-    resp.add_val(calendar_info);
+    add_calendar_info(resp);
 
     resp.skip_key();
 
@@ -181,17 +273,16 @@ pub(crate) async fn poll(
     resp.add_key("attbonus5(3)");
     resp.add_str("0/0/0/0");
 
+    let next_level =
+        |resource| fortress.map_or(0, |a| a.per_hour_next_level(resource));
     resp.add_key("stoneperhournextlevel");
-    resp.add_val(50);
+    resp.add_val(next_level(FortressResourceType::Stone));
 
     resp.add_key("woodperhournextlevel");
-    resp.add_val(150);
+    resp.add_val(next_level(FortressResourceType::Wood));
 
     resp.add_key("fortresswalllevel");
-    resp.add_val(5);
-
-    resp.add_key("inboxcapacity");
-    resp.add_val(100);
+    resp.add_val(fortress.map_or(0, |a| a.wall_level()));
 
     resp.add_key("ownplayersave.playerSave");
     resp.add_val(403127023); // What is this?
@@ -232,13 +323,15 @@ pub(crate) async fn poll(
     resp.add_val(char.class); // class
 
     // Attributes
-    for _ in AttributeType::iter() {
-        resp.add_val(100); // 30..=34
+    let mut con = db.acquire().await?;
+    let attributes = load_attributes(&mut con, session.player_id).await?;
+    for attribute in attributes.base {
+        resp.add_val(attribute); // 30..=34
     }
 
-    // attribute_additions (aggregate from equipment)
-    for _ in AttributeType::iter() {
-        resp.add_val(0); // 35..=38
+    // attribute_additions (aggregate from equipment, gems, pets and potions)
+    for addition in attributes.additions() {
+        resp.add_val(addition); // 35..=39
     }
 
     // attribute_times_bought
@@ -251,25 +344,11 @@ pub(crate) async fn poll(
     resp.add_val(char.busy_until); // Busy until
 
     // Equipment
-    for slot in [
-        EquipmentSlot::Hat,
-        EquipmentSlot::BreastPlate,
-        EquipmentSlot::Gloves,
-        EquipmentSlot::FootWear,
-        EquipmentSlot::Amulet,
-        EquipmentSlot::Belt,
-        EquipmentSlot::Ring,
-        EquipmentSlot::Talisman,
-        EquipmentSlot::Weapon,
-        EquipmentSlot::Shield,
-    ] {
-        add_debug_item(resp, format!("{slot:?}").to_lowercase());
-    }
-    add_debug_item(resp, "inventory1");
-    add_debug_item(resp, "inventory2");
-    add_debug_item(resp, "inventory3");
-    add_debug_item(resp, "inventory4");
-    add_debug_item(resp, "inventory5");
+    let equipment = load_equipment(&mut con, session.player_id).await?;
+    add_items(resp, &equipment);
+    let enchantments = EnchantmentEffects::of_items(&equipment);
+    let bag = load_items(&mut con, session.player_id, ItemPlace::Bag).await?;
+    add_items(resp, &bag);
 
     resp.add_val(in_seconds(60 * 60)); // 228
 
@@ -298,9 +377,21 @@ pub(crate) async fn poll(
 
     let mount_effect = effective_mount(&mut mount_end, &mut mount);
 
-    resp.add_val((char.q1length as f32 * mount_effect) as i32); // 241 quest 1 length
-    resp.add_val((char.q2length as f32 * mount_effect) as i32); // 242 quest 2 length
-    resp.add_val((char.q3length as f32 * mount_effect) as i32); // 243 quest 3 length
+    // 241 quest 1 length
+    resp.add_val(
+        enchantments
+            .quest_length(mounted_quest_length(char.q1length, mount_effect)),
+    );
+    // 242 quest 2 length
+    resp.add_val(
+        enchantments
+            .quest_length(mounted_quest_length(char.q2length, mount_effect)),
+    );
+    // 243 quest 3 length
+    resp.add_val(
+        enchantments
+            .quest_length(mounted_quest_length(char.q3length, mount_effect)),
+    );
 
     // Quest 1..=3 items
     for _ in 0..3 {
@@ -313,9 +404,12 @@ pub(crate) async fn poll(
     resp.add_val(char.q2xp); // 281 quest 2 xp
     resp.add_val(char.q3xp); // 282 quest 3 xp
 
-    resp.add_val(char.q1silver); // 283 quest 1 silver
-    resp.add_val(char.q2silver); // 284 quest 2 silver
-    resp.add_val(char.q3silver); // 285 quest 3 silver
+    // 283 quest 1 silver
+    resp.add_val(mounted_quest_silver(char.q1silver, mount));
+    // 284 quest 2 silver
+    resp.add_val(mounted_quest_silver(char.q2silver, mount));
+    // 285 quest 3 silver
+    resp.add_val(mounted_quest_silver(char.q3silver, mount));
 
     resp.add_val(mount); // Mount?
 
@@ -337,15 +431,20 @@ pub(crate) async fn poll(
     resp.add_val(0); // 436
     resp.add_val(0); // 437
 
-    resp.add_val(0); // 438 scrapbook count
+    resp.add_val(album.entries); // 438 scrapbook count
     resp.add_val(0); // 439
     resp.add_val(0); // 440
     resp.add_val(0); // 441
     resp.add_val(0); // 442
 
-    resp.add_val(0); // 443 guild join date
+    resp.add_val(char.guild_joined.unwrap_or_default()); // 443 guild join date
     resp.add_val(0); // 444
-    resp.add_val(0); // 445 character_hp_bonus << 24, damage_bonus << 16
+    let portal_dmg_bonus = char
+        .demon_portal_act
+        .map(portal_damage_bonus)
+        .unwrap_or_default();
+    // 445 character_hp_bonus << 24, damage_bonus << 16
+    resp.add_val(portal_dmg_bonus << 16);
     resp.add_val(0); // 446
     resp.add_val(0); // 447  Armor
     resp.add_val(6); // 448  Min damage
@@ -357,9 +456,13 @@ pub(crate) async fn poll(
     resp.add_val(0); // 454
     resp.add_val(1708336503); // 455
     resp.add_val(char.tfa); // 456 Alu secs
-    resp.add_val(char.beer_drunk); // 457 Beer drunk
+    // 457 Beer drunk
+    resp.add_val(match char.beer_reset > now() {
+        true => char.beer_drunk,
+        false => 0,
+    });
     resp.add_val(0); // 458
-    resp.add_val(0); // 459 dungeon_timer
+    resp.add_val(char.dungeon_timer); // 459 dungeon_timer
     resp.add_val(1708336503); // 460 Next free fight
     resp.add_val(0); // 461
     resp.add_val(0); // 462
@@ -395,16 +498,7 @@ pub(crate) async fn poll(
     resp.add_val(0); // 491 aura_level (0 == locked)
     resp.add_val(0); // 492 aura_now
 
-    // Active potions
-    for _ in 0..3 {
-        resp.add_val(0); // typ & size
-    }
-    for _ in 0..3 {
-        resp.add_val(0); // ??
-    }
-    for _ in 0..3 {
-        resp.add_val(0); // expires
-    }
+    add_potions(resp, &potions); // 493..=501
     resp.add_val(0); // 502
     resp.add_val(0); // 503
     resp.add_val(0); // 504
@@ -422,101 +516,43 @@ pub(crate) async fn poll(
     resp.add_val(0); // 516
     resp.add_val(0); // 517
     resp.add_val(0); // 518
-    resp.add_val(100); // 519
+    resp.add_val(enchantment_price(char.level)); // 519 Enchantment price
     resp.add_val(0); // 520
     resp.add_val(0); // 521
     resp.add_val(0); // 522
     resp.add_val(0); // 523
 
     // Fortress
-    // Building levels
-    resp.add_val(0); // 524
-    resp.add_val(0); // 525
-    resp.add_val(0); // 526
-    resp.add_val(0); // 527
-    resp.add_val(0); // 528
-    resp.add_val(0); // 529
-    resp.add_val(0); // 530
-    resp.add_val(0); // 531
-    resp.add_val(0); // 532
-    resp.add_val(0); // 533
-    resp.add_val(0); // 534
-    resp.add_val(0); // 535
-    resp.add_val(0); // 536
-    resp.add_val(0); // 537
-    resp.add_val(0); // 538
-    resp.add_val(0); // 539
-    resp.add_val(0); // 540
-    resp.add_val(0); // 541
-    resp.add_val(0); // 542
-    resp.add_val(0); // 543
-    resp.add_val(0); // 544
-    resp.add_val(0); // 545
-    resp.add_val(0); // 546
-    // unit counts
-    resp.add_val(0); // 547
-    resp.add_val(0); // 548
-    resp.add_val(0); // 549
-    // upgrade_began
-    resp.add_val(0); // 550
-    resp.add_val(0); // 551
-    resp.add_val(0); // 552
-    // upgrade_finish
-    resp.add_val(0); // 553
-    resp.add_val(0); // 554
-    resp.add_val(0); // 555
-
-    resp.add_val(0); // 556
-    resp.add_val(0); // 557
-    resp.add_val(0); // 558
-    resp.add_val(0); // 559
-    resp.add_val(0); // 560
-    resp.add_val(0); // 561
-
-    // Current resource in store
-    resp.add_val(0); // 562
-    resp.add_val(0); // 563
-    resp.add_val(0); // 564
-    // max_in_building
-    resp.add_val(0); // 565
-    resp.add_val(0); // 566
-    resp.add_val(0); // 567
-    // max saved
-    resp.add_val(0); // 568
-    resp.add_val(0); // 569
-    resp.add_val(0); // 570
-
-    resp.add_val(0); // 571 building_upgraded
-    resp.add_val(0); // 572 building_upgrade_finish
-    resp.add_val(0); // 573 building_upgrade_began
-    // per hour
-    resp.add_val(0); // 574
-    resp.add_val(0); // 575
-    resp.add_val(0); // 576
-    resp.add_val(0); // 577 unknown time stamp
+    add_fortress_save(resp, fortress);
     resp.add_val(0); // 578
 
-    resp.add_val(0); // 579 wheel_spins_today
-    resp.add_val(now() + 60 * 10); // 580  wheel_next_free_spin
-
-    resp.add_val(0); // 581 ft level
-    resp.add_val(100); // 582 ft honor
-    resp.add_val(0); // 583 rank
-    resp.add_val(900); // 584
-    resp.add_val(300); // 585
-    resp.add_val(0); // 586
-
-    resp.add_val(0); // 587 attack target
-    resp.add_val(0); // 588 attack_free_reroll
+    let wheel_spins_today = match char.wheel_reset > now() {
+        true => char.wheel_spins_today,
+        false => 0,
+    };
+    resp.add_val(wheel_spins_today); // 579
+    resp.add_val(char.wheel_next_free_spin); // 580
+
+    resp.add_val(fortress.map_or(0, |a| a.upgrades())); // 581 ft level
+    resp.add_val(fortress.map_or(0, |a| a.honor)); // 582 ft honor
+    resp.add_val(fortress_rank); // 583 rank
+    resp.add_val(next_level(FortressResourceType::Wood)); // 584
+    resp.add_val(next_level(FortressResourceType::Stone)); // 585
+    resp.add_val(fortress.map_or(0, |a| a.attack_reroll)); // 586 free reroll
+
+    let attack_target = fortress.and_then(|a| a.attack_target);
+    resp.add_val(attack_target.unwrap_or_default()); // 587 attack target
+    resp.add_val(0); // 588
     resp.add_val(0); // 589
     resp.add_val(0); // 590
     resp.add_val(0); // 591
     resp.add_val(0); // 592
     resp.add_val(3); // 593
 
-    resp.add_val(0); // 594 gem_stone_target
-    resp.add_val(0); // 595 gem_search_finish
-    resp.add_val(0); // 596 gem_search_began
+    // 594 gem_stone_target, 595 gem_search_finish, 596 gem_search_began
+    resp.add_val(fortress.map_or(0, |a| a.gem_target));
+    resp.add_val(fortress.map_or(0, |a| a.gem_search_finish));
+    resp.add_val(fortress.map_or(0, |a| a.gem_search_began));
     resp.add_val(char.tutorial_status); // 597 Pretty sure this is a bit map of which messages have been seen
     resp.add_val(0); // 598
 
@@ -546,12 +582,25 @@ pub(crate) async fn poll(
     resp.add_val(0); // 620
     resp.add_val(0); // 621
     resp.add_val(0); // 622
-    resp.add_val(0); // 623 own_treasure_skill
-    resp.add_val(0); // 624 own_instr_skill
+    resp.add_val(char.treasure); // 623 own_treasure_skill
+    resp.add_val(char.instructor); // 624 own_instr_skill
     resp.add_val(0); // 625
     resp.add_val(30); // 626
-    resp.add_val(0); // 627 hydra_next_battle
-    resp.add_val(0); // 628 remaining_pet_battles
+    let hydra_last_fought = char.hydra_last_fought.unwrap_or_default();
+    match char.hydra_fought {
+        Some(fought) if fought_today(fought, hydra_last_fought) => {
+            resp.add_val(next_day()); // 627 hydra_next_battle
+            resp.add_val(0); // 628 remaining_pet_battles
+        }
+        Some(_) => {
+            resp.add_val(0); // 627 hydra_next_battle
+            resp.add_val(1); // 628 remaining_pet_battles
+        }
+        None => {
+            resp.add_val(0); // 627 hydra_next_battle
+            resp.add_val(0); // 628 remaining_pet_battles
+        }
+    }
     resp.add_val(0); // 629
     resp.add_val(0); // 630
     resp.add_val(0); // 631
@@ -571,8 +620,8 @@ pub(crate) async fn poll(
     resp.add_val(0); // 645
     resp.add_val(0); // 646
     resp.add_val(0); // 647
-    resp.add_val(0); // 648
-    resp.add_val(in_seconds(60 * 60)); // 649 calendar_next_possible
+    resp.add_val(calendar.collected << 16); // 648
+    resp.add_val(calendar.next_possible); // 649
     resp.add_val(char.dice_game_next_free); // 650 dice_games_next_free
     resp.add_val(char.dice_games_remaining); // 651 dice_games_remaining
     resp.add_val(0); // 652
@@ -583,7 +632,7 @@ pub(crate) async fn poll(
     resp.add_val(6); // 657
     resp.add_val(0); // 658
     resp.add_val(2); // 659
-    resp.add_val(0); // 660 pet dungeon timer
+    resp.add_val(pets.map_or(0, |a| a.next_exploration)); // 660
     resp.add_val(0); // 661
     resp.add_val(0); // 662
     resp.add_val(0); // 663
@@ -687,20 +736,22 @@ pub(crate) async fn poll(
     resp.add_val(session.player_id); // pid
     resp.add_val(char.mushrooms); // mushrooms
     resp.add_val(char.silver); // silver
-    resp.add_val(0); // lucky coins
+    resp.add_val(char.lucky_coins); // lucky coins
     resp.add_val(char.quicksand); // quicksand glasses
-    resp.add_val(0); // wood
+    resp.add_val(fortress.map_or(0, |a| a.wood)); // wood
     resp.add_val(0); // ??
-    resp.add_val(0); // stone
+    resp.add_val(fortress.map_or(0, |a| a.stone)); // stone
     resp.add_val(0); // ??
-    resp.add_val(0); // metal
-    resp.add_val(0); // arcane
-    resp.add_val(0); // souls
+    resp.add_val(smith.metal); // metal
+    resp.add_val(smith.arcane); // arcane
+    resp.add_val(underworld.map_or(0, |a| a.souls)); // souls
     // Fruits
-    for _ in 0..5 {
-        resp.add_val(0);
+    for habitat in 0..5 {
+        resp.add_val(pets.map_or(0, |a| a.habitats[habitat].fruits));
     }
 
+    add_guild_save(resp, &mut *db.acquire().await?, session.player_id).await?;
+
     resp.add_key("owndescription.s");
     resp.add_str(&to_sf_string(&char.description));
 
@@ -719,54 +770,56 @@ pub(crate) async fn poll(
     resp.add_val(0);
 
     resp.add_key("fortresspricereroll");
-    resp.add_val(18);
+    resp.add_val(REROLL_PRICE);
 
     resp.add_key("timestamp");
 
     resp.add_val(now());
 
-    resp.add_key("fortressprice.fortressPrice(13)");
-    resp.add_str(
-        "900/1000/0/0/900/500/35/12/900/200/0/0/900/300/22/0/900/1500/50/17/\
-         900/700/7/9/900/500/41/7/900/400/20/14/900/600/61/20/900/2500/40/13/\
-         900/400/25/8/900/15000/30/13/0/0/0/0",
-    );
+    add_fortress_prices(resp, fortress);
 
     resp.skip_key();
 
-    resp.add_key("unitprice.fortressPrice(3)");
-    resp.add_str("600/0/15/5/600/0/11/6/300/0/19/3/");
+    add_unit_prices(resp, fortress);
+
+    add_underworld_prices(resp, underworld);
 
     resp.add_key("upgradeprice.upgradePrice(3)");
     resp.add_val("28/270/210/28/720/60/28/360/180/");
 
+    // Units fight at the level of the character
     resp.add_key("unitlevel(4)");
-    resp.add_val("5/25/25/25/");
+    resp.add_val(fortress.map_or(0, |a| a.wall_level()));
+    for _ in FortressUnitType::iter() {
+        resp.add_val(char.level);
+    }
 
     resp.skip_key();
     resp.skip_key();
 
-    resp.add_key("petsdefensetype");
-    resp.add_val(3);
+    add_pets(resp, &mut *db.acquire().await?, session.player_id, pets).await?;
 
     resp.add_key("singleportalenemylevel");
     resp.add_val(0);
 
     resp.skip_key();
 
+    let treasure_bonus =
+        treasure_silver_bonus(&mut *db.acquire().await?, session.player_id)
+            .await?;
     resp.add_key("wagesperhour");
-    resp.add_val(10);
+    resp.add_val(guard_wage(char.level, treasure_bonus));
 
     resp.skip_key();
 
     resp.add_key("dragongoldbonus");
-    resp.add_val(30);
+    resp.add_val(DRAGON_GOLD_BONUS);
 
     resp.add_key("toilettfull");
     resp.add_val(0);
 
     resp.add_key("maxupgradelevel");
-    resp.add_val(20);
+    resp.add_val(MAX_BUILDING_LEVEL);
 
     resp.add_key("cidstring");
     resp.add_str("no_cid");
@@ -776,8 +829,7 @@ pub(crate) async fn poll(
         resp.add_str(tracking);
     }
 
-    resp.add_key("calenderinfo");
-    resp.add_str(calendar_info);
+    add_calendar_info(resp);
 
     resp.skip_key();
 
@@ -795,189 +847,36 @@ pub(crate) async fn poll(
          0/0/0/0/",
     );
 
-    resp.add_key("scrapbook.r");
-    resp.add_str("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA==");
+    add_scrapbook(resp, &album);
 
-    resp.add_key("smith");
-    resp.add_str("5/0");
+    add_smith(resp, &smith);
 
-    resp.add_key("owntowerlevel");
-    resp.add_val(0);
+    add_witch(resp, &mut con, session.world_id).await?;
+
+    add_tower(resp, &mut con, session.player_id, underworld).await?;
 
     resp.add_key("webshopid");
     resp.add_str("Q7tGCJhe$r464");
 
-    resp.add_key("dailytasklist");
-    resp.add_val(98);
-    for typ_id in 1..=10 {
-        resp.add_val(typ_id); // typ
-        resp.add_val(0); // current
-        resp.add_val(typ_id); // target
-        resp.add_val(10); // reward
-    }
-
-    resp.add_key("eventtasklist");
-    for typ_id in 1..=99 {
-        if typ_id == 73 {
-            continue;
-        }
-        resp.add_val(typ_id); // typ
-        resp.add_val(0); // current
-        resp.add_val(typ_id); // target
-        resp.add_val(typ_id); // reward
-    }
-
-    resp.add_key("dailytaskrewardpreview");
-    add_reward_previews(resp);
-
-    resp.add_key("eventtaskrewardpreview");
-
-    add_reward_previews(resp);
-
-    resp.add_key("eventtaskinfo");
-    resp.add_val(1708300800);
-    resp.add_val(1798646399);
-    resp.add_val(2); // event typ
+    add_tasks(resp, &mut con, session.player_id, session.world_id).await?;
 
     resp.add_key("unlockfeature");
 
-    let dungeon_progress_light = [
-        0,  // DesecratedCatacombs = 0,
-        0,  // MinesOfGloria = 1,
-        0,  // RuinsOfGnark = 2,
-        0,  // CutthroatGrotto = 3,
-        0,  // EmeraldScaleAltar = 4,
-        0,  // ToxicTree = 5,
-        0,  // MagmaStream = 6,
-        0,  // FrostBloodTemple = 7,
-        0,  // PyramidsofMadness = 8,
-        0,  // BlackSkullFortress = 9,
-        0,  // CircusOfHorror = 10,
-        0,  // Hell = 11,
-        0,  // The13thFloor = 12,
-        6,  // Easteros = 13,
-        86, // Tower = 14,
-        1,  // TimeHonoredSchoolofMagic = 15,
-        2,  // Hemorridor = 16,
-        27, // Portal = 17
-        1,  // NordicGods = 18,
-        1,  // MountOlympus = 19,
-        0,  // TavernoftheDarkDoppelgangers = 20,
-        3,  // DragonsHoard = 21,
-        0,  // HouseOfHorrors = 22,
-        0,  // ThirdLeagueOfSuperheroes = 23,
-        1,  // DojoOfChildhoodHeroes = 24,
-        1,  // MonsterGrotto = 25,
-        1,  // CityOfIntrigues = 26,
-        1,  // SchoolOfMagicExpress = 27,
-        1,  // AshMountain = 28,
-        0,  // PlayaGamesHQ = 29,
-        10, // TrainingCamp = 30,
-        1,  // Sandstorm = 31,
-        1,  // Old Pixel = 32
-        2,  // Server Room = 33
-        3,  // Workshop = 34
-        4,  // Retro TV = 35
-        5,  // Meeting room = 36
-    ];
-
-    resp.add_key(&format!(
-        "dungeonprogresslight({})",
-        dungeon_progress_light.len()
-    ));
-    for val in dungeon_progress_light {
-        resp.add_val(val);
-    }
-
-    let dungeon_progress_shadow = [
-        10,  // DesecratedCatacombs = 0,
-        10,  // MinesOfGloria = 1,
-        10,  // RuinsOfGnark = 2,
-        10,  // CutthroatGrotto = 3,
-        10,  // EmeraldScaleAltar = 4,
-        10,  // ToxicTree = 5,
-        10,  // MagmaStream = 6,
-        10,  // FrostBloodTemple = 7,
-        10,  // PyramidsOfMadness = 8,
-        4,   // BlackSkullFortress = 9,
-        1,   // CircusOfHorror = 10,
-        1,   // Hell = 11,
-        1,   // The13thFloor = 12,
-        1,   // Easteros = 13,
-        316, // Twister = 14,
-        1,   // TimeHonoredSchoolOfMagic = 15,
-        1,   // Hemorridor = 16,
-        0,   // ContinuousLoopofIdols = 17,
-        0,   // NordicGods = 18,
-        0,   // MountOlympus = 19,
-        1,   // TavernOfTheDarkDoppelgangers = 20,
-        1,   // DragonsHoard = 21,
-        1,   // HouseOfHorrors = 22,
-        1,   // ThirdLeagueofSuperheroes = 23,
-        1,   // DojoOfChildhoodHeroes = 24,
-        1,   // MonsterGrotto = 25,
-        1,   // CityOfIntrigues = 26,
-        1,   // SchoolOfMagicExpress = 27,
-        1,   // AshMountain = 28,
-        1,   // PlayaGamesHQ = 29,
-        0,   // ?
-        0,   // ?
-        1,   // Old Pixel = 32
-        2,   // Server Room = 33
-        3,   // Workshop = 34
-        4,   // Retro TV = 35
-        5,   // Meeting room = 36
-    ];
-
-    resp.add_key(&format!(
-        "dungeonprogressshadow({})",
-        dungeon_progress_shadow.len()
-    ));
-    for val in dungeon_progress_shadow {
-        resp.add_val(val);
-    }
-
-    let dungeon_info = calc_dungeon_progress(&dungeon_progress_light, false);
-
-    resp.add_key(&format!("dungeonenemieslight({})", dungeon_info.len()));
-    for dungeon in &dungeon_info {
-        resp.add_val(dungeon.enemy.monster_name);
-        resp.add_val(dungeon.id);
-        resp.add_val(dungeon.enemy.loot);
-    }
-
-    resp.add_key(&format!(
-        "currentdungeonenemieslight({})",
-        dungeon_info.iter().filter(|a| a.is_current).count()
-    ));
-    for dungeon in dungeon_info.iter().filter(|a| a.is_current) {
-        resp.add_val(dungeon.enemy.monster_name);
-        resp.add_val(dungeon.id);
-        resp.add_val(dungeon.enemy.level);
-        resp.add_val(dungeon.enemy.class);
-        resp.add_val(dungeon.enemy.element);
-    }
-
-    let dungeon_info = calc_dungeon_progress(&dungeon_progress_shadow, true);
-
-    resp.add_key(&format!("dungeonenemiesshadow({})", dungeon_info.len()));
-    for dungeon in &dungeon_info {
-        resp.add_val(dungeon.enemy.monster_name);
-        resp.add_val(dungeon.id);
-        resp.add_val(dungeon.enemy.loot);
-    }
+    let dungeon_progress_light = load_dungeon_progress(
+        &mut *db.acquire().await?,
+        session.player_id,
+        false,
+    )
+    .await?;
+    add_dungeon_progress(resp, &dungeon_progress_light, false);
 
-    resp.add_key(&format!(
-        "currentdungeonenemiesshadow({})",
-        dungeon_info.iter().filter(|a| a.is_current).count()
-    ));
-    for dungeon in dungeon_info.iter().filter(|a| a.is_current) {
-        resp.add_val(dungeon.enemy.monster_name);
-        resp.add_val(dungeon.id);
-        resp.add_val(dungeon.enemy.level);
-        resp.add_val(dungeon.enemy.class);
-        resp.add_val(dungeon.enemy.element);
-    }
+    let dungeon_progress_shadow = load_dungeon_progress(
+        &mut *db.acquire().await?,
+        session.player_id,
+        true,
+    )
+    .await?;
+    add_dungeon_progress(resp, &dungeon_progress_shadow, true);
 
     resp.add_key("portalprogress(3)");
     resp.add_val("27/100/194");
@@ -1026,97 +925,5 @@ pub(crate) async fn poll(
     resp.add_key("cryptokey");
     resp.add_val(session.crypto_key);
 
-    // resp.add_key("pendingrewards");
-    // for i in 0..10 {
-    //     resp.add_val(9999 + i);
-    //     resp.add_val(2);
-    //     resp.add_val(i);
-    //     resp.add_val("Reward Name");
-    //     resp.add_val(1717777586);
-    //     resp.add_val(1718382386);
-    // }
-
     resp.build()
 }
-
-#[derive(Debug, Clone, Copy)]
-struct DungeonEnemy {
-    monster_name: u16,
-    loot: u8,
-    level: u16,
-    class: u8,
-    element: u8,
-}
-
-fn lookup_dungeon(dungeon_id: usize, is_shadow: bool) -> Vec<DungeonEnemy> {
-    let limit = match dungeon_id {
-        14 if !is_shadow => 100,  // Tower
-        17 if !is_shadow => 40,   // Portal
-        31 if !is_shadow => 1000, // Sandstorm
-        _ => 10,
-    };
-
-    (0..limit)
-        .map(|_floor| DungeonEnemy {
-            monster_name: 1150,
-            loot: 0,
-            level: dungeon_id as u16,
-            class: 1,
-            element: 0,
-        })
-        .collect()
-}
-
-struct DungeonInfo {
-    id: usize,
-    enemy: DungeonEnemy,
-    is_current: bool,
-}
-
-fn calc_dungeon_progress(
-    dungeon_progress: &[i32],
-    is_shadow: bool,
-) -> Vec<DungeonInfo> {
-    let mut dungeon_enemies = Vec::new();
-
-    for (idx, &progress) in dungeon_progress.iter().enumerate() {
-        let dungeon = lookup_dungeon(idx, is_shadow);
-        if progress >= dungeon.len() as i32 || progress < 0 {
-            continue;
-        }
-
-        for offset in &[-2, -1, 0, 1, 2] {
-            let pos = progress + offset;
-            if pos < 0 {
-                continue;
-            }
-            if let Some(enemy) = dungeon.get(pos as usize).copied() {
-                dungeon_enemies.push(DungeonInfo {
-                    id: idx + 1,
-                    enemy,
-                    is_current: *offset == 0,
-                });
-            }
-        }
-    }
-
-    dungeon_enemies
-}
-
-fn add_reward_previews(resp: &mut ResponseBuilder) {
-    for i in 1..=3 {
-        resp.add_val(0);
-        resp.add_val(match i {
-            1 => 400,
-            2 => 123,
-            _ => 999,
-        });
-        let count = 16;
-        resp.add_val(count);
-        // amount of rewards
-        for i in 0..count {
-            resp.add_val(i + 1); // typ
-            resp.add_val(1000); // typ amount
-        }
-    }
-}
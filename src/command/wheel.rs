@@ -0,0 +1,324 @@
+use fastrand::Rng;
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse,
+    blacksmith::add_arcane,
+    fortress::{add_fortress_resources, load_fortress},
+    item::{DbItem, add_to_bag, insert_item},
+    mail::{RewardTyp, give_reward, send_reward_mail},
+    next_day, now,
+    pets::{FRUIT_IDENT, HABITAT_COUNT, load_pets},
+    player::guard_wage,
+    poll,
+    potion::{SMALL_POTION, potion_ident},
+    task::{TaskType, progress_tasks},
+    underworld::{add_souls, load_underworld},
+    xp_for_next_level,
+};
+use crate::request::Session;
+
+/// The amount of spins, that can be paid for each day. The free spin does
+/// not count towards this
+const MAX_DAILY_SPINS: i64 = 20;
+/// The mushrooms or lucky coins a paid spin costs
+const SPIN_PRICE: i64 = 1;
+/// The mushrooms the mushroom reward grants
+const MUSHROOMS: i64 = 2;
+/// The hours of guard wage the silver reward is worth
+const SILVER_HOURS: i64 = 2;
+/// The share of the experience to the next level in percent, that the
+/// experience reward is worth
+const XP_PERCENT: i64 = 2;
+/// The wood and stone the resource rewards grant per level
+const RESOURCES_PER_LEVEL: i64 = 10;
+/// The factor, that the big version of a reward is worth more
+const XL_FACTOR: i64 = 5;
+/// The lucky coins the lucky coin reward grants
+const LUCKY_COINS: i64 = 2;
+/// The arcane crystals the arcane reward of the upgraded wheel grants
+const ARCANE: i64 = 10;
+/// The level, from which on the wheel is upgraded for characters with pets
+/// and an underworld
+const UPGRADE_LEVEL: i64 = 95;
+
+/// The fields of the wheel. The values are the reward types the client knows.
+/// On the upgraded wheel, wood is arcane crystals, stone is a pet fruit and
+/// silver is souls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WheelReward {
+    Mushrooms = 0,
+    Wood = 1,
+    ExperienceXL = 2,
+    Stone = 3,
+    SilverXL = 4,
+    /// An equipment item (amount 1) or a potion (amount 2)
+    Item = 5,
+    WoodXL = 6,
+    Experience = 7,
+    StoneXL = 8,
+    Silver = 9,
+    /// The client does not know a field for lucky coins, so it shows this
+    /// one as an unknown reward
+    LuckyCoins = 10,
+}
+
+impl WheelReward {
+    /// Wood and stone can only be won with a fortress
+    fn needs_fortress(self, upgraded: bool) -> bool {
+        use WheelReward::*;
+        match self {
+            WoodXL | StoneXL => true,
+            Wood | Stone => !upgraded,
+            _ => false,
+        }
+    }
+}
+
+/// The fields of the wheel with the weight they are chosen with
+const WHEEL: [(WheelReward, u32); 11] = {
+    use WheelReward::*;
+    [
+        (Mushrooms, 4),
+        (Wood, 12),
+        (ExperienceXL, 4),
+        (Stone, 12),
+        (SilverXL, 4),
+        (Item, 10),
+        (WoodXL, 4),
+        (Experience, 20),
+        (StoneXL, 4),
+        (Silver, 22),
+        (LuckyCoins, 4),
+    ]
+};
+
+/// Whether the wheel of the character is upgraded. These are the
+/// requirements the client checks to show the upgraded wheel
+fn is_upgraded(level: i64, has_pets: bool, has_underworld: bool) -> bool {
+    level >= UPGRADE_LEVEL && has_pets && has_underworld
+}
+
+/// Chooses a field of the wheel. Characters without a fortress can not land
+/// on wood and stone
+fn spin(rng: &mut Rng, has_fortress: bool, upgraded: bool) -> WheelReward {
+    let fields: Vec<_> = WHEEL
+        .iter()
+        .filter(|(reward, _)| has_fortress || !reward.needs_fortress(upgraded))
+        .collect();
+    let total: u32 = fields.iter().map(|(_, weight)| weight).sum();
+    let mut roll = rng.u32(0..total);
+    for (reward, weight) in fields {
+        if roll < *weight {
+            return *reward;
+        }
+        roll -= weight;
+    }
+    WheelReward::Silver
+}
+
+/// Gives the character the reward of the field and returns the amount the
+/// client shows
+async fn give_wheel_reward(
+    con: &mut SqliteConnection,
+    pid: i64,
+    level: i64,
+    class: i64,
+    reward: WheelReward,
+    upgraded: bool,
+    rng: &mut Rng,
+) -> Result<i64, ServerError> {
+    use WheelReward::*;
+    let factor = match reward {
+        ExperienceXL | SilverXL | WoodXL | StoneXL => XL_FACTOR,
+        _ => 1,
+    };
+    Ok(match reward {
+        Mushrooms => {
+            give_reward(con, pid, RewardTyp::Mushrooms, MUSHROOMS).await?;
+            MUSHROOMS
+        }
+        LuckyCoins => {
+            give_reward(con, pid, RewardTyp::LuckyCoins, LUCKY_COINS).await?;
+            LUCKY_COINS
+        }
+        Wood if upgraded => {
+            add_arcane(con, pid, ARCANE).await?;
+            ARCANE
+        }
+        Stone if upgraded => {
+            let ident = FRUIT_IDENT + rng.i64(0..HABITAT_COUNT as i64);
+            let fruit = insert_item(con, &DbItem::fruit(ident)).await?;
+            if !add_to_bag(con, pid, fruit).await? {
+                send_reward_mail(con, pid, "Wheel of Fortune", &[], &[fruit])
+                    .await?;
+            }
+            // The client shows the pet item, that has been won
+            ident
+        }
+        Silver if upgraded => {
+            let souls = level * RESOURCES_PER_LEVEL;
+            add_souls(con, pid, souls).await?;
+            souls
+        }
+        Silver | SilverXL => {
+            let silver = guard_wage(level, 0) * SILVER_HOURS * factor;
+            give_reward(con, pid, RewardTyp::Silver, silver).await?;
+            silver
+        }
+        Experience | ExperienceXL => {
+            let xp = xp_for_next_level(level) * XP_PERCENT / 100 * factor;
+            give_reward(con, pid, RewardTyp::XP, xp).await?;
+            xp
+        }
+        Wood | WoodXL => {
+            let wood = level * RESOURCES_PER_LEVEL * factor;
+            add_fortress_resources(con, pid, wood, 0).await?;
+            wood
+        }
+        Stone | StoneXL => {
+            let stone = level * RESOURCES_PER_LEVEL * factor;
+            add_fortress_resources(con, pid, 0, stone).await?;
+            stone
+        }
+        Item => {
+            let (item, kind) = match rng.bool() {
+                true => (DbItem::random_equipment(rng, level, class), 1),
+                false => {
+                    let attribute = rng.usize(0..5);
+                    let ident = potion_ident(attribute, SMALL_POTION);
+                    (DbItem::potion(ident), 2)
+                }
+            };
+            let item = insert_item(con, &item).await?;
+            if !add_to_bag(con, pid, item).await? {
+                send_reward_mail(con, pid, "Wheel of Fortune", &[], &[item])
+                    .await?;
+            }
+            kind
+        }
+    })
+}
+
+/// Spins the wheel of fortune. The payment is lucky coins (0), mushrooms (1)
+/// or the free spin of the day (2)
+pub(crate) async fn wheel_of_fortune(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let payment = args.get_int(0, "payment")?;
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+
+    let row = sqlx::query!(
+        "SELECT level, class, mushrooms, lucky_coins, wheel_spins_today,
+            wheel_reset, wheel_next_free_spin
+        FROM character NATURAL JOIN tavern WHERE pid = $1",
+        pid
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let now = now();
+    let mut spins_today = match row.wheel_reset > now {
+        true => row.wheel_spins_today,
+        false => 0,
+    };
+    let mut next_free_spin = row.wheel_next_free_spin;
+    let (mut mushrooms, mut lucky_coins) = (0, 0);
+    match payment {
+        0 | 1 if spins_today >= MAX_DAILY_SPINS => {
+            return Err(ServerError::BadRequest);
+        }
+        0 if row.lucky_coins >= SPIN_PRICE => lucky_coins = SPIN_PRICE,
+        1 if row.mushrooms >= SPIN_PRICE => mushrooms = SPIN_PRICE,
+        2 if next_free_spin <= now => next_free_spin = next_day(),
+        _ => return Err(ServerError::BadRequest),
+    }
+    if payment != 2 {
+        spins_today += 1;
+    }
+
+    sqlx::query!(
+        "UPDATE character SET mushrooms = mushrooms - $2 WHERE pid = $1", pid,
+        mushrooms
+    )
+    .execute(&mut *tx)
+    .await?;
+    let reset = next_day();
+    sqlx::query!(
+        "UPDATE tavern
+        SET lucky_coins = lucky_coins - $2, wheel_spins_today = $3,
+            wheel_reset = $4, wheel_next_free_spin = $5
+        WHERE pid = $1",
+        pid,
+        lucky_coins,
+        spins_today,
+        reset,
+        next_free_spin
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let mut rng = Rng::new();
+    let has_fortress = load_fortress(&mut tx, pid).await?.is_some();
+    let upgraded = is_upgraded(
+        row.level,
+        load_pets(&mut tx, pid).await?.is_some(),
+        load_underworld(&mut tx, pid).await?.is_some(),
+    );
+    let reward = spin(&mut rng, has_fortress, upgraded);
+    let amount = give_wheel_reward(
+        &mut tx, pid, row.level, row.class, reward, upgraded, &mut rng,
+    )
+    .await?;
+    progress_tasks(&mut tx, pid, TaskType::SpinWheelOfFortune, 1).await?;
+
+    tx.commit().await?;
+    let mut resp = ResponseBuilder::default();
+    resp.add_key("wheelresult");
+    resp.add_val(reward as i64);
+    resp.add_val(amount);
+    poll(session, "", db, resp).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wheel_weights_sum_to_100() {
+        let total: u32 = WHEEL.iter().map(|(_, weight)| weight).sum();
+        assert_eq!(total, 100);
+    }
+
+    #[test]
+    fn upgraded_wheel_gives_wood_and_stone_without_fortress() {
+        assert!(WheelReward::Wood.needs_fortress(false));
+        assert!(WheelReward::Stone.needs_fortress(false));
+        assert!(!WheelReward::Wood.needs_fortress(true));
+        assert!(!WheelReward::Stone.needs_fortress(true));
+        assert!(WheelReward::WoodXL.needs_fortress(true));
+        assert!(WheelReward::StoneXL.needs_fortress(true));
+        assert!(!WheelReward::Silver.needs_fortress(false));
+    }
+
+    #[test]
+    fn upgrade_requirements() {
+        assert!(is_upgraded(UPGRADE_LEVEL, true, true));
+        assert!(!is_upgraded(UPGRADE_LEVEL - 1, true, true));
+        assert!(!is_upgraded(UPGRADE_LEVEL, false, true));
+        assert!(!is_upgraded(UPGRADE_LEVEL, true, false));
+    }
+
+    #[test]
+    fn spin_without_fortress() {
+        let mut rng = Rng::with_seed(0);
+        for upgraded in [false, true] {
+            for _ in 0..1000 {
+                let reward = spin(&mut rng, false, upgraded);
+                assert!(!reward.needs_fortress(upgraded));
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use num_traits::FromPrimitive;
+use sf_api::gamestate::items::Enchantment;
+use sqlx::{Sqlite, SqliteConnection};
+
+use super::{
+    CommandArguments, ResponseBuilder, ServerError, ServerResponse,
+    character_level,
+    item::{
+        DbItem, ItemPlace, equipment_slot, load_equipment, load_item,
+        load_item_ids, take_item,
+    },
+    player::guard_wage,
+    poll,
+};
+use crate::request::Session;
+
+/// The level a character needs to visit the witch
+const WITCH_LEVEL: i64 = 66;
+/// The amount of items, that have to be put into the cauldron to unlock the
+/// next enchantment
+const CAULDRON_TARGET: i64 = 25;
+/// The hours of guard wage enchanting an item costs
+const ENCHANT_PRICE_HOURS: i64 = 10;
+
+/// The bonus in percent the enchantments grant
+const CRIT_DAMAGE_BONUS: i64 = 5;
+const QUEST_XP_BONUS: i64 = 10;
+const QUEST_SILVER_BONUS: i64 = 10;
+const TRAVEL_TIME_REDUCTION: i64 = 10;
+/// The chance in percent the enchantments grant to find something
+const MUSHROOM_CHANCE: i64 = 10;
+const QUEST_ITEM_CHANCE: i64 = 10;
+const ARENA_LOOT_CHANCE: i64 = 10;
+
+/// The enchantments in the order the witch unlocks them. The client uses the
+/// 1 based position to choose an enchantment
+const ENCHANTMENTS: [Enchantment; 9] = {
+    use Enchantment::*;
+    [
+        AdventurersArchaeologicalAura, TheGraveRobbersPrayer, ManyFeetBoots,
+        MariosBeard, ThirstyWanderer, UnholyAcquisitiveness, ShadowOfTheCowboy,
+        SwordOfVengeance, RobberBaronRitual,
+    ]
+};
+
+/// The item type an enchantment can be applied to
+fn enchantment_item_type(enchantment: Enchantment) -> i64 {
+    enchantment as i64 / 10
+}
+
+/// The silver it costs to enchant an item at the level
+pub(crate) fn enchantment_price(level: i64) -> i64 {
+    guard_wage(level, 0) * ENCHANT_PRICE_HOURS
+}
+
+/// The bonuses the enchantments of the equipped items grant. Bonuses and
+/// chances are in percent
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct EnchantmentEffects {
+    pub crit_damage: i64,
+    pub mushroom_chance: i64,
+    pub travel_reduction: i64,
+    /// Strikes first in fights regardless of the luck of the enemy
+    pub first_strike: bool,
+    pub quest_xp: i64,
+    pub extra_beers: i64,
+    pub quest_item_chance: i64,
+    pub quest_silver: i64,
+    pub arena_loot_chance: i64,
+}
+
+impl EnchantmentEffects {
+    /// The effects of the enchantments on the items
+    pub(crate) fn of_items(items: &[Option<DbItem>]) -> EnchantmentEffects {
+        use Enchantment::*;
+        let mut effects = EnchantmentEffects::default();
+        for item in items.iter().flatten() {
+            match Enchantment::from_i64(item.enchantment) {
+                Some(SwordOfVengeance) => {
+                    effects.crit_damage = CRIT_DAMAGE_BONUS;
+                }
+                Some(MariosBeard) => effects.mushroom_chance = MUSHROOM_CHANCE,
+                Some(ManyFeetBoots) => {
+                    effects.travel_reduction = TRAVEL_TIME_REDUCTION;
+                }
+                Some(ShadowOfTheCowboy) => effects.first_strike = true,
+                Some(AdventurersArchaeologicalAura) => {
+                    effects.quest_xp = QUEST_XP_BONUS;
+                }
+                Some(ThirstyWanderer) => effects.extra_beers = 1,
+                Some(UnholyAcquisitiveness) => {
+                    effects.quest_item_chance = QUEST_ITEM_CHANCE;
+                }
+                Some(TheGraveRobbersPrayer) => {
+                    effects.quest_silver = QUEST_SILVER_BONUS;
+                }
+                // The loot is a new random item, the enemy keeps everything
+                // it wears
+                Some(RobberBaronRitual) => {
+                    effects.arena_loot_chance = ARENA_LOOT_CHANCE;
+                }
+                None => {}
+            }
+        }
+        effects
+    }
+
+    /// The length of a quest with the shortened travel time
+    pub(crate) fn quest_length(&self, length: i64) -> i64 {
+        length * (100 - self.travel_reduction) / 100
+    }
+}
+
+/// Loads the effects of the enchantments the character has equipped
+pub(crate) async fn load_enchantment_effects(
+    con: &mut SqliteConnection,
+    pid: i64,
+) -> Result<EnchantmentEffects, ServerError> {
+    let equipment = load_equipment(con, pid).await?;
+    Ok(EnchantmentEffects::of_items(&equipment))
+}
+
+/// The cauldron of a world, that every character helps to fill
+#[derive(Debug)]
+struct Cauldron {
+    /// The items put into the cauldron towards the next enchantment
+    items: i64,
+    /// The amount of enchantments unlocked so far
+    unlocked: usize,
+}
+
+impl Cauldron {
+    /// The enchantment the cauldron is currently filled for. Once everything
+    /// is unlocked, the cauldron is bubbling and this is none
+    fn next_enchantment(&self) -> Option<Enchantment> {
+        ENCHANTMENTS.get(self.unlocked).copied()
+    }
+}
+
+async fn load_cauldron(
+    con: &mut SqliteConnection,
+    world_id: i64,
+) -> Result<Cauldron, ServerError> {
+    let row = sqlx::query!(
+        "SELECT witch_items, witch_unlocked FROM world WHERE world_id = $1",
+        world_id
+    )
+    .fetch_one(&mut *con)
+    .await?;
+    Ok(Cauldron {
+        items: row.witch_items,
+        unlocked: (row.witch_unlocked as usize).min(ENCHANTMENTS.len()),
+    })
+}
+
+/// Puts the item at the position into the cauldron. The witch only accepts
+/// items of the type the next enchantment belongs to
+pub(crate) async fn player_witch_spend_item(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let place = ItemPlace::from_client(args.get_int(0, "inventory")?)
+        .ok_or(ServerError::BadRequest)?;
+    let pos = args.get_int(1, "position")? - 1;
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+
+    if character_level(&mut tx, pid).await? < WITCH_LEVEL {
+        return Err(ServerError::BadRequest);
+    }
+    let cauldron = load_cauldron(&mut tx, session.world_id).await?;
+    let Some(wanted) = cauldron.next_enchantment() else {
+        return Err(ServerError::BadRequest);
+    };
+    let item_id = take_item(&mut tx, pid, place, pos).await?;
+    let item = load_item(&mut tx, item_id)
+        .await?
+        .ok_or(ServerError::BadRequest)?;
+    if item.item_type != enchantment_item_type(wanted) {
+        return Err(ServerError::BadRequest);
+    }
+    sqlx::query!("DELETE FROM item WHERE id = $1", item_id)
+        .execute(&mut *tx)
+        .await?;
+
+    // A full cauldron unlocks the next enchantment for the whole world
+    let (items, unlocked) = match cauldron.items + 1 >= CAULDRON_TARGET {
+        true => (0, cauldron.unlocked as i64 + 1),
+        false => (cauldron.items + 1, cauldron.unlocked as i64),
+    };
+    sqlx::query!(
+        "UPDATE world SET witch_items = $2, witch_unlocked = $3
+        WHERE world_id = $1",
+        session.world_id,
+        items,
+        unlocked
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Enchants the equipped item in the slot the enchantment belongs to. The
+/// ident is the 1 based position of an unlocked enchantment
+pub(crate) async fn player_witch_enchant_item(
+    session: Session,
+    db: &sqlx::Pool<Sqlite>,
+    args: CommandArguments<'_>,
+) -> Result<ServerResponse, ServerError> {
+    let ident = args.get_int(0, "enchantment")? - 1;
+    let pid = session.player_id;
+    let mut tx = db.begin().await?;
+
+    let character =
+        sqlx::query!("SELECT level, silver FROM character WHERE pid = $1", pid)
+            .fetch_one(&mut *tx)
+            .await?;
+    if character.level < WITCH_LEVEL {
+        return Err(ServerError::BadRequest);
+    }
+    let cauldron = load_cauldron(&mut tx, session.world_id).await?;
+    let enchantment = usize::try_from(ident)
+        .ok()
+        .filter(|idx| *idx < cauldron.unlocked)
+        .map(|idx| ENCHANTMENTS[idx])
+        .ok_or(ServerError::BadRequest)?;
+    let slot = equipment_slot(enchantment_item_type(enchantment))
+        .ok_or(ServerError::BadRequest)?;
+    let item_id = load_item_ids(&mut tx, pid, ItemPlace::Equipment)
+        .await?
+        .get(slot - 1)
+        .copied()
+        .flatten()
+        .ok_or(ServerError::BadRequest)?;
+
+    let price = enchantment_price(character.level);
+    if character.silver < price {
+        return Err(ServerError::NotEnoughMoney);
+    }
+    sqlx::query!(
+        "UPDATE character SET silver = silver - $2 WHERE pid = $1", pid, price
+    )
+    .execute(&mut *tx)
+    .await?;
+    let enchantment = enchantment as i64;
+    sqlx::query!(
+        "UPDATE item SET enchantment = $2 WHERE id = $1", item_id, enchantment
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    poll(session, "", db, Default::default()).await
+}
+
+/// Writes the cauldron of the world and the enchantments unlocked so far
+pub(crate) async fn add_witch(
+    resp: &mut ResponseBuilder,
+    con: &mut SqliteConnection,
+    world_id: i64,
+) -> Result<(), ServerError> {
+    let cauldron = load_cauldron(con, world_id).await?;
+    resp.add_key("witch");
+    resp.add_val(0); // ??
+    resp.add_val(cauldron.items);
+    resp.add_val(CAULDRON_TARGET);
+    match cauldron.next_enchantment() {
+        Some(wanted) => {
+            resp.add_val(enchantment_item_type(wanted));
+            resp.add_val(0);
+            resp.add_val(0);
+        }
+        // The cauldron is bubbling
+        None => {
+            resp.add_val(0);
+            resp.add_val(0);
+            resp.add_val(1);
+        }
+    }
+    resp.add_val(0); // ??
+    resp.add_val(ENCHANTMENTS.len());
+    resp.add_val(0); // ??
+    for (idx, enchantment) in ENCHANTMENTS.iter().enumerate() {
+        // Locked enchantments are written as 1
+        resp.add_val(match idx < cauldron.unlocked {
+            true => *enchantment as i64,
+            false => 1,
+        });
+        resp.add_val(0);
+        resp.add_val(0);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enchanted(enchantment: Enchantment) -> Option<DbItem> {
+        Some(DbItem {
+            enchantment: enchantment as i64,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn no_enchantments() {
+        let effects =
+            EnchantmentEffects::of_items(&[None, Some(DbItem::default())]);
+        assert_eq!(effects.crit_damage, 0);
+        assert_eq!(effects.travel_reduction, 0);
+        assert!(!effects.first_strike);
+        assert_eq!(effects.quest_length(100), 100);
+    }
+
+    #[test]
+    fn effects_of_enchanted_items() {
+        let effects = EnchantmentEffects::of_items(&[
+            enchanted(Enchantment::SwordOfVengeance),
+            None,
+            enchanted(Enchantment::ManyFeetBoots),
+            enchanted(Enchantment::ShadowOfTheCowboy),
+            enchanted(Enchantment::ThirstyWanderer),
+        ]);
+        assert_eq!(effects.crit_damage, CRIT_DAMAGE_BONUS);
+        assert_eq!(effects.travel_reduction, TRAVEL_TIME_REDUCTION);
+        assert!(effects.first_strike);
+        assert_eq!(effects.extra_beers, 1);
+        assert_eq!(effects.quest_xp, 0);
+        assert_eq!(effects.quest_length(100), 90);
+    }
+}
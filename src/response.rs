@@ -33,6 +33,12 @@ pub enum ServerError {
     StillBusy,
     #[error("cannot do this right now2")]
     NotRightNow2,
+    #[error("you are muted")]
+    Muted,
+    #[error("inbox full")]
+    InboxFull,
+    #[error("inventory full")]
+    InventoryFull,
     #[error("internal server error: {0}")]
     DBError(#[from] sqlx::Error),
     #[error("internal server error")]
@@ -97,6 +103,16 @@ impl ResponseBuilder {
         self
     }
 
+    /// The values written after the key
+    #[cfg(test)]
+    pub fn values(&self, key: &str) -> Vec<String> {
+        self.resp
+            .split('&')
+            .find_map(|part| part.strip_prefix(key)?.strip_prefix(':'))
+            .map(|vals| vals.split('/').map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
     pub fn build<T>(&mut self) -> Result<ServerResponse, T> {
         let mut a = String::new();
         std::mem::swap(&mut a, &mut self.resp);